@@ -0,0 +1,442 @@
+//! Writes a parsed program out as JSON for tools outside the crate. Every
+//! node is an object whose `"type"` names its `Stmt` or `Expr` variant,
+//! with the variant's fields alongside. Tokens are objects with their
+//! `"type"`, `"lexeme"` and `"line"`.
+
+use crate::expr::Expr;
+use crate::map::Map;
+use crate::stack;
+use crate::stmt::{FunctionDecl, FunctionKind, Stmt};
+use crate::token::{Literal, Token};
+use crate::value::Value;
+
+/// The syntax tree of `stmts` as JSON text. Fails only on trees nested too
+/// deeply to write out.
+pub fn ast_to_json(stmts: &[Stmt], pretty: bool) -> Result<String, String> {
+    stack::run(|| statements(stmts).to_json(pretty))
+}
+
+fn node(kind: &str, fields: Vec<(&str, Value)>) -> Value {
+    let mut map = Map::new();
+    let entries = [("type", Value::from(kind))].into_iter().chain(fields);
+    for (name, value) in entries {
+        // String keys always hash, so inserting can't fail.
+        let _ = map.insert(Value::from(name), value);
+    }
+    Value::from(map)
+}
+
+fn list(values: impl Iterator<Item = Value>) -> Value {
+    Value::from(values.collect::<Vec<_>>())
+}
+
+fn token(token: &Token) -> Value {
+    node(
+        &format!("{:?}", token.token_type),
+        vec![
+            ("lexeme", Value::from(token.lexeme.as_str())),
+            ("line", Value::Int(token.line as i64)),
+        ],
+    )
+}
+
+fn tokens(tokens: &[Token]) -> Value {
+    list(tokens.iter().map(token))
+}
+
+fn optional<T>(value: &Option<T>, convert: impl Fn(&T) -> Value) -> Value {
+    value.as_ref().map_or(Value::Nil, convert)
+}
+
+fn literal(value: &Literal) -> Value {
+    match value {
+        Literal::String(value) => Value::from(value.as_str()),
+        Literal::Int(value) => Value::Int(*value),
+        Literal::BigInt(value) => Value::BigInt(value.clone()),
+        // JSON has no infinity, which a literal like `1e999` parses to.
+        Literal::Float(value) if !value.is_finite() => Value::from(value.to_string()),
+        Literal::Float(value) => Value::Float(*value),
+        Literal::Bool(value) => Value::Boolean(*value),
+        Literal::None => Value::Nil,
+    }
+}
+
+fn statements(stmts: &[Stmt]) -> Value {
+    list(stmts.iter().map(statement))
+}
+
+fn expressions(exprs: &[Expr]) -> Value {
+    list(exprs.iter().map(expression))
+}
+
+fn statement(stmt: &Stmt) -> Value {
+    match stmt {
+        Stmt::Expression(expr) => node("Expression", vec![("expression", expression(expr))]),
+        Stmt::Print { expr, .. } => node("Print", vec![("expression", expression(expr))]),
+        Stmt::Var { name, initializer } => node(
+            "Var",
+            vec![
+                ("name", token(name)),
+                ("initializer", optional(initializer, expression)),
+            ],
+        ),
+        Stmt::Const { name, initializer } => node(
+            "Const",
+            vec![
+                ("name", token(name)),
+                ("initializer", expression(initializer)),
+            ],
+        ),
+        Stmt::Block(stmts) => node("Block", vec![("statements", statements(stmts))]),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => node(
+            "If",
+            vec![
+                ("condition", expression(condition)),
+                ("thenBranch", statement(then_branch)),
+                ("elseBranch", optional(else_branch, |stmt| statement(stmt))),
+            ],
+        ),
+        Stmt::While {
+            condition,
+            body,
+            increment,
+        } => node(
+            "While",
+            vec![
+                ("condition", expression(condition)),
+                ("body", statement(body)),
+                ("increment", optional(increment, expression)),
+            ],
+        ),
+        Stmt::DoWhile { body, condition } => node(
+            "DoWhile",
+            vec![
+                ("body", statement(body)),
+                ("condition", expression(condition)),
+            ],
+        ),
+        Stmt::Function(declaration) => function(declaration),
+        Stmt::Return { keyword, value } => node(
+            "Return",
+            vec![
+                ("keyword", token(keyword)),
+                ("value", optional(value, expression)),
+            ],
+        ),
+        Stmt::Assert {
+            keyword,
+            condition,
+            message,
+        } => node(
+            "Assert",
+            vec![
+                ("keyword", token(keyword)),
+                ("condition", expression(condition)),
+                ("message", optional(message, expression)),
+            ],
+        ),
+        Stmt::Throw { keyword, value } => node(
+            "Throw",
+            vec![("keyword", token(keyword)), ("value", expression(value))],
+        ),
+        Stmt::Try {
+            body,
+            handler,
+            finally,
+        } => node(
+            "Try",
+            vec![
+                ("body", statements(body)),
+                (
+                    "handler",
+                    optional(handler, |(name, handler)| {
+                        node(
+                            "Catch",
+                            vec![("name", token(name)), ("body", statements(handler))],
+                        )
+                    }),
+                ),
+                ("finally", optional(finally, |stmts| statements(stmts))),
+            ],
+        ),
+        Stmt::Continue { keyword } => node("Continue", vec![("keyword", token(keyword))]),
+        Stmt::ForIn {
+            name,
+            keyword,
+            iterable,
+            body,
+        } => node(
+            "ForIn",
+            vec![
+                ("name", token(name)),
+                ("keyword", token(keyword)),
+                ("iterable", expression(iterable)),
+                ("body", statement(body)),
+            ],
+        ),
+        Stmt::Class {
+            name,
+            methods,
+            fields,
+            constants,
+            statics,
+            super_class,
+        } => node(
+            "Class",
+            vec![
+                ("name", token(name)),
+                ("superClass", optional(super_class, expression)),
+                ("methods", statements(methods)),
+                ("fields", statements(fields)),
+                ("constants", statements(constants)),
+                ("statics", statements(statics)),
+            ],
+        ),
+        Stmt::Import {
+            path,
+            name,
+            members,
+            alias,
+        } => node(
+            "Import",
+            vec![
+                ("path", token(path)),
+                ("name", token(name)),
+                ("members", tokens(members)),
+                ("alias", optional(alias, token)),
+            ],
+        ),
+    }
+}
+
+fn function(declaration: &FunctionDecl) -> Value {
+    let kind = match declaration.kind {
+        FunctionKind::FUNCTION => "function",
+        FunctionKind::GETTER => "getter",
+        FunctionKind::SETTER => "setter",
+    };
+    node(
+        "Function",
+        vec![
+            ("name", token(&declaration.name)),
+            ("params", tokens(&declaration.params)),
+            ("body", statements(&declaration.body)),
+            ("kind", Value::from(kind)),
+            ("variadic", Value::Boolean(declaration.variadic)),
+        ],
+    )
+}
+
+fn expression(expr: &Expr) -> Value {
+    match expr {
+        Expr::Literal { value, .. } => node("Literal", vec![("value", literal(value))]),
+        Expr::Unary {
+            operator, right, ..
+        } => node(
+            "Unary",
+            vec![("operator", token(operator)), ("right", expression(right))],
+        ),
+        Expr::Grouping { expr, .. } => node("Grouping", vec![("expression", expression(expr))]),
+        Expr::Binary {
+            left,
+            operator,
+            right,
+            ..
+        } => node(
+            "Binary",
+            vec![
+                ("left", expression(left)),
+                ("operator", token(operator)),
+                ("right", expression(right)),
+            ],
+        ),
+        Expr::Var { name, .. } => node("Var", vec![("name", token(name))]),
+        Expr::Assign { name, value, .. } => node(
+            "Assign",
+            vec![("name", token(name)), ("value", expression(value))],
+        ),
+        Expr::Logical {
+            left,
+            operator,
+            right,
+            ..
+        } => node(
+            "Logical",
+            vec![
+                ("left", expression(left)),
+                ("operator", token(operator)),
+                ("right", expression(right)),
+            ],
+        ),
+        Expr::Call {
+            callee,
+            paren,
+            arguments,
+            named,
+            ..
+        } => node(
+            "Call",
+            vec![
+                ("callee", expression(callee)),
+                ("paren", token(paren)),
+                ("arguments", expressions(arguments)),
+                (
+                    "named",
+                    list(named.iter().map(|(name, value)| {
+                        node(
+                            "NamedArgument",
+                            vec![("name", token(name)), ("value", expression(value))],
+                        )
+                    })),
+                ),
+            ],
+        ),
+        Expr::Get { object, name, .. } => node(
+            "Get",
+            vec![("object", expression(object)), ("name", token(name))],
+        ),
+        Expr::Set {
+            object,
+            name,
+            value,
+            ..
+        } => node(
+            "Set",
+            vec![
+                ("object", expression(object)),
+                ("name", token(name)),
+                ("value", expression(value)),
+            ],
+        ),
+        Expr::This { keyword, .. } => node("This", vec![("keyword", token(keyword))]),
+        Expr::Super {
+            keyword, method, ..
+        } => node(
+            "Super",
+            vec![("keyword", token(keyword)), ("method", token(method))],
+        ),
+        Expr::List { elements, .. } => node("List", vec![("elements", expressions(elements))]),
+        Expr::Tuple { elements, .. } => node("Tuple", vec![("elements", expressions(elements))]),
+        Expr::Yield { keyword, value, .. } => node(
+            "Yield",
+            vec![
+                ("keyword", token(keyword)),
+                ("value", optional(value, |expr| expression(expr))),
+            ],
+        ),
+        Expr::Lambda { declaration, .. } => {
+            node("Lambda", vec![("declaration", function(declaration))])
+        }
+        Expr::Sequence {
+            expressions: exprs, ..
+        } => node("Sequence", vec![("expressions", expressions(exprs))]),
+        Expr::Comparison {
+            operands,
+            operators,
+            ..
+        } => node(
+            "Comparison",
+            vec![
+                ("operands", expressions(operands)),
+                ("operators", tokens(operators)),
+            ],
+        ),
+        Expr::Is {
+            object,
+            keyword,
+            class,
+            ..
+        } => node(
+            "Is",
+            vec![
+                ("object", expression(object)),
+                ("keyword", token(keyword)),
+                ("class", expression(class)),
+            ],
+        ),
+        Expr::Index {
+            object,
+            bracket,
+            index,
+            ..
+        } => node(
+            "Index",
+            vec![
+                ("object", expression(object)),
+                ("bracket", token(bracket)),
+                ("index", expression(index)),
+            ],
+        ),
+        Expr::SetIndex {
+            object,
+            bracket,
+            index,
+            value,
+            ..
+        } => node(
+            "SetIndex",
+            vec![
+                ("object", expression(object)),
+                ("bracket", token(bracket)),
+                ("index", expression(index)),
+                ("value", expression(value)),
+            ],
+        ),
+        Expr::Slice {
+            object,
+            bracket,
+            start,
+            end,
+            ..
+        } => node(
+            "Slice",
+            vec![
+                ("object", expression(object)),
+                ("bracket", token(bracket)),
+                ("start", optional(start, |expr| expression(expr))),
+                ("end", optional(end, |expr| expression(expr))),
+            ],
+        ),
+        Expr::Ternary {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => node(
+            "Ternary",
+            vec![
+                ("condition", expression(condition)),
+                ("thenBranch", expression(then_branch)),
+                ("elseBranch", expression(else_branch)),
+            ],
+        ),
+        Expr::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => node(
+            "If",
+            vec![
+                ("condition", expression(condition)),
+                ("thenBranch", expression(then_branch)),
+                ("elseBranch", optional(else_branch, |expr| expression(expr))),
+            ],
+        ),
+        Expr::Block {
+            statements: stmts,
+            value,
+            ..
+        } => node(
+            "Block",
+            vec![
+                ("statements", statements(stmts)),
+                ("value", optional(value, |expr| expression(expr))),
+            ],
+        ),
+    }
+}
@@ -0,0 +1,232 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Formatter;
+
+const BASE: u64 = 1_000_000_000;
+
+/// 2^63, the smallest float magnitude that doesn't fit in an `i64`.
+pub const I64_LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+/// An integer outside the range of `i64`, which int arithmetic promotes to
+/// instead of overflowing.
+///
+/// The magnitude is kept in base 10^9 limbs, least significant first, so
+/// printing needs no division. Zero has no limbs and is never negative.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn from_i64(value: i64) -> Self {
+        let mut magnitude = value.unsigned_abs();
+        let mut limbs = vec![];
+        while magnitude > 0 {
+            limbs.push((magnitude % BASE) as u32);
+            magnitude /= BASE;
+        }
+        BigInt {
+            negative: value < 0,
+            limbs,
+        }
+    }
+
+    /// The exact value of a finite float with no fractional part.
+    pub fn from_whole_f64(value: f64) -> Self {
+        if value.abs() < I64_LIMIT {
+            return BigInt::from_i64(value as i64);
+        }
+        // Floats this large are their 53-bit mantissa times a power of two.
+        let bits = value.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as u32 - 1075;
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let magnitude = BigInt::from_i64(mantissa as i64).mul(&BigInt::from_i64(2).pow(exponent));
+        if value < 0.0 {
+            magnitude.neg()
+        } else {
+            magnitude
+        }
+    }
+
+    /// Parses a non-empty string of decimal digits.
+    pub fn parse(digits: &str) -> Option<Self> {
+        if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        let mut limbs = vec![];
+        let mut end = digits.len();
+        while end > 0 {
+            let start = end.saturating_sub(9);
+            limbs.push(digits[start..end].parse().ok()?);
+            end = start;
+        }
+        Some(BigInt::normalized(false, limbs))
+    }
+
+    fn normalized(negative: bool, mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigInt {
+            negative: negative && !limbs.is_empty(),
+            limbs,
+        }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        let mut magnitude: i128 = 0;
+        for limb in self.limbs.iter().rev() {
+            magnitude = magnitude
+                .checked_mul(BASE as i128)?
+                .checked_add(*limb as i128)?;
+        }
+        if self.negative {
+            magnitude = -magnitude;
+        }
+        i64::try_from(magnitude).ok()
+    }
+
+    /// Bytes taken up by the digits.
+    pub fn heap_size(&self) -> usize {
+        self.limbs.len() * std::mem::size_of::<u32>()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0.0, |value, limb| value * BASE as f64 + *limb as f64);
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::normalized(!self.negative, self.limbs.clone())
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::normalized(false, self.limbs.clone())
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::normalized(self.negative, add_magnitudes(&self.limbs, &other.limbs));
+        }
+        match compare_magnitudes(&self.limbs, &other.limbs) {
+            Ordering::Less => {
+                BigInt::normalized(other.negative, sub_magnitudes(&other.limbs, &self.limbs))
+            }
+            _ => BigInt::normalized(self.negative, sub_magnitudes(&self.limbs, &other.limbs)),
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        let mut product = vec![0u64; self.limbs.len() + other.limbs.len()];
+        for (i, &left) in self.limbs.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &right) in other.limbs.iter().enumerate() {
+                let current = product[i + j] + left as u64 * right as u64 + carry;
+                product[i + j] = current % BASE;
+                carry = current / BASE;
+            }
+            product[i + other.limbs.len()] += carry;
+        }
+        let limbs = product.into_iter().map(|limb| limb as u32).collect();
+        BigInt::normalized(self.negative != other.negative, limbs)
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> BigInt {
+        let mut result = BigInt::from_i64(1);
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul(&base);
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+}
+
+fn compare_magnitudes(left: &[u32], right: &[u32]) -> Ordering {
+    left.len()
+        .cmp(&right.len())
+        .then_with(|| left.iter().rev().cmp(right.iter().rev()))
+}
+
+fn add_magnitudes(left: &[u32], right: &[u32]) -> Vec<u32> {
+    let mut sum = Vec::with_capacity(left.len().max(right.len()) + 1);
+    let mut carry = 0;
+    for i in 0..left.len().max(right.len()) {
+        let current =
+            *left.get(i).unwrap_or(&0) as u64 + *right.get(i).unwrap_or(&0) as u64 + carry;
+        sum.push((current % BASE) as u32);
+        carry = current / BASE;
+    }
+    if carry > 0 {
+        sum.push(carry as u32);
+    }
+    sum
+}
+
+/// Subtracts `right` from `left`, whose magnitude must be at least as large.
+fn sub_magnitudes(left: &[u32], right: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(left.len());
+    let mut borrow = 0;
+    for (i, &limb) in left.iter().enumerate() {
+        let subtrahend = *right.get(i).unwrap_or(&0) as i64 + borrow;
+        let mut current = limb as i64 - subtrahend;
+        borrow = 0;
+        if current < 0 {
+            current += BASE as i64;
+            borrow = 1;
+        }
+        difference.push(current as u32);
+    }
+    difference
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.limbs, &other.limbs),
+            (true, true) => compare_magnitudes(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Some((most, rest)) = self.limbs.split_last() else {
+            return write!(f, "0");
+        };
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", most)?;
+        for limb in rest.iter().rev() {
+            write!(f, "{:09}", limb)?;
+        }
+        Ok(())
+    }
+}
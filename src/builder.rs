@@ -0,0 +1,125 @@
+use crate::interpreter::{Input, Interpreter, InterpreterOptions, Output};
+
+/// The groups of natives that reach outside the interpreter. Scripts can't
+/// see the natives in a disabled group at all.
+#[derive(Debug, Clone)]
+pub(crate) struct Capabilities {
+    /// The `fs` module and importing `.lox` files.
+    pub file_io: bool,
+    /// `os.exec`, which also needs `InterpreterOptions::allow_exec`.
+    pub exec: bool,
+    /// The `http` module.
+    pub network: bool,
+    /// `os.getEnv` and `os.setEnv`.
+    pub env: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capabilities {
+            file_io: true,
+            exec: true,
+            network: true,
+            env: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Capabilities {
+            file_io: false,
+            exec: false,
+            network: false,
+            env: false,
+        }
+    }
+}
+
+/// Configures an interpreter before creating it, including which natives
+/// with side effects outside the interpreter it provides, e.g.
+/// `InterpreterBuilder::sandboxed().env(true).build()` for one that can
+/// read environment variables but nothing else outside itself.
+pub struct InterpreterBuilder {
+    options: InterpreterOptions,
+    capabilities: Capabilities,
+    input: Option<Input>,
+    output: Option<Output>,
+}
+
+impl Default for InterpreterBuilder {
+    fn default() -> Self {
+        InterpreterBuilder::new()
+    }
+}
+
+impl InterpreterBuilder {
+    /// A builder with every native enabled, like `Interpreter::new`.
+    pub fn new() -> Self {
+        InterpreterBuilder {
+            options: InterpreterOptions::default(),
+            capabilities: Capabilities::all(),
+            input: None,
+            output: None,
+        }
+    }
+
+    /// A builder with file I/O, commands, networking and environment
+    /// variables all disabled, for running untrusted code.
+    pub fn sandboxed() -> Self {
+        InterpreterBuilder {
+            capabilities: Capabilities::none(),
+            ..InterpreterBuilder::new()
+        }
+    }
+
+    pub fn options(mut self, options: InterpreterOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Where `readLine()` and `readAll()` read from, stdin by default.
+    pub fn input(mut self, input: Input) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Where `print` and `write()` write to, stdout by default.
+    pub fn output(mut self, output: Output) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// The `fs` module and importing `.lox` files.
+    pub fn file_io(mut self, enabled: bool) -> Self {
+        self.capabilities.file_io = enabled;
+        self
+    }
+
+    /// `os.exec`. Running a command also needs the `allow_exec` option.
+    pub fn exec(mut self, enabled: bool) -> Self {
+        self.capabilities.exec = enabled;
+        self
+    }
+
+    /// The `http` module, when built with the `http` feature.
+    pub fn network(mut self, enabled: bool) -> Self {
+        self.capabilities.network = enabled;
+        self
+    }
+
+    /// `os.getEnv` and `os.setEnv`.
+    pub fn env(mut self, enabled: bool) -> Self {
+        self.capabilities.env = enabled;
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        let mut interpreter = Interpreter::with_capabilities(self.options, self.capabilities);
+        if let Some(input) = self.input {
+            interpreter.set_input(input);
+        }
+        if let Some(output) = self.output {
+            interpreter.set_output(output);
+        }
+        interpreter
+    }
+}
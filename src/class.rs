@@ -1,66 +1,394 @@
-use crate::function::{Callable, Function};
+use crate::environment::{EnvRef, Environment};
+use crate::function::{Callable, Function, NativeFunction, NativeResult};
+use crate::gc;
 use crate::interpreter::Interpreter;
+use crate::memory;
+use crate::stmt::Stmt;
+use crate::symbol::Symbol;
+use crate::sync::{AnyValue, MaybeSync, Rc, RefCell};
 use crate::token::Token;
 use crate::value::Value;
-use crate::{Exception, RuntimeError};
-use std::cell::RefCell;
+use crate::vm;
+use crate::vm::ClosureRef;
+use crate::{Exception, RuntimeErrorKind};
 use std::collections::HashMap;
 use std::fmt;
-use std::fmt::{write, Formatter};
-use std::rc::Rc;
+use std::fmt::Formatter;
+
+/// A Rust type exposed to Lox as a class. Instances of the class, and of
+/// Lox classes inheriting from it, carry a value of the type that the
+/// methods work on.
+pub trait NativeClass: Sized + MaybeSync + 'static {
+    /// The class name seen from Lox.
+    const NAME: &'static str;
+    /// How many arguments the constructor takes.
+    const ARITY: usize;
+
+    /// Builds the value for a new instance. Subclasses run this through
+    /// `super.init(...)`.
+    fn init(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Self, String>;
+
+    fn methods() -> Vec<NativeMethod<Self>>;
+}
+
+/// A method of a `NativeClass`, taking `arity` arguments.
+pub struct NativeMethod<T> {
+    pub name: &'static str,
+    pub arity: usize,
+    pub method: fn(&mut T, &mut Interpreter, Vec<Value>) -> NativeResult,
+}
+
+impl<T> NativeMethod<T> {
+    pub fn new(
+        name: &'static str,
+        arity: usize,
+        method: fn(&mut T, &mut Interpreter, Vec<Value>) -> NativeResult,
+    ) -> Self {
+        NativeMethod {
+            name,
+            arity,
+            method,
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Class {
     name: String,
     super_class: Option<Box<Class>>,
-    methods: HashMap<String, Function>,
+    methods: HashMap<Symbol, Function>,
+    /// Methods implemented in Rust. They take the instance as their first
+    /// argument.
+    native_methods: HashMap<Symbol, NativeFunction>,
+    /// Methods compiled for the VM backend.
+    compiled_methods: HashMap<Symbol, ClosureRef>,
+    setters: HashMap<Symbol, Function>,
+    fields: Rc<[Stmt]>,
+    constants: HashMap<Symbol, Value>,
+    /// Shared by every copy of this class value, so updates are seen through
+    /// all of them.
+    statics: Rc<RefCell<HashMap<Symbol, Value>>>,
+    closure: EnvRef,
 }
 
 impl Class {
     pub fn new(
         name: String,
         super_class: Option<Box<Class>>,
-        methods: HashMap<String, Function>,
+        methods: HashMap<Symbol, Function>,
+        setters: HashMap<Symbol, Function>,
+        fields: Rc<[Stmt]>,
+        constants: HashMap<Symbol, Value>,
+        closure: EnvRef,
     ) -> Self {
+        let statics = Rc::new(RefCell::new(HashMap::new()));
+        gc::track(&statics);
+        Environment::capture(&closure);
         Class {
             name,
             super_class,
             methods,
+            native_methods: HashMap::new(),
+            compiled_methods: HashMap::new(),
+            setters,
+            fields,
+            constants,
+            statics,
+            closure,
         }
     }
 
+    /// A class compiled for the VM, whose methods are closures. The VM
+    /// doesn't compile fields, constants or setters.
+    pub(crate) fn compiled(
+        name: String,
+        super_class: Option<Box<Class>>,
+        methods: HashMap<Symbol, ClosureRef>,
+    ) -> Self {
+        let mut class = Class::new(
+            name,
+            super_class,
+            HashMap::new(),
+            HashMap::new(),
+            Rc::new([]),
+            HashMap::new(),
+            Environment::new(),
+        );
+        class.compiled_methods = methods;
+        class
+    }
+
+    /// The class for a Rust type, with its constructor as `init`.
+    pub fn native<T: NativeClass>() -> Self {
+        let mut class = Class::new(
+            T::NAME.to_string(),
+            None,
+            HashMap::new(),
+            HashMap::new(),
+            Rc::new([]),
+            HashMap::new(),
+            Environment::new(),
+        );
+        let init = NativeFunction::new(T::ARITY, |interpreter, mut args| {
+            let Value::ClassInstance(instance) = args.remove(0) else {
+                unreachable!("native methods are bound to instances")
+            };
+            let data = T::init(interpreter, args)?;
+            instance.borrow_mut().data = Some(NativeData(Rc::new(RefCell::new(data))));
+            Ok(Value::Nil)
+        });
+        class.native_methods.insert(Symbol::from("init"), init);
+        for NativeMethod {
+            name,
+            arity,
+            method,
+        } in T::methods()
+        {
+            let function = NativeFunction::new(arity, move |interpreter, mut args| {
+                let Value::ClassInstance(instance) = args.remove(0) else {
+                    unreachable!("native methods are bound to instances")
+                };
+                let data = instance.borrow().data.clone();
+                let data =
+                    data.ok_or_else(|| format!("{} instance was not initialized.", T::NAME))?;
+                let mut data = data.0.try_borrow_mut().map_err(|_| {
+                    format!(
+                        "Can't call '{}' while another {} method is running.",
+                        name,
+                        T::NAME
+                    )
+                })?;
+                let data = data
+                    .downcast_mut::<T>()
+                    .ok_or_else(|| format!("Expected a {} instance.", T::NAME))?;
+                method(data, interpreter, args)
+            });
+            class.native_methods.insert(Symbol::from(name), function);
+        }
+        class
+    }
+
+    pub fn statics(&self) -> &Rc<RefCell<HashMap<Symbol, Value>>> {
+        &self.statics
+    }
+
+    pub fn closure(&self) -> &EnvRef {
+        &self.closure
+    }
+
+    pub fn super_class(&self) -> Option<&Class> {
+        self.super_class.as_deref()
+    }
+
+    /// The methods and setters written in Lox, not counting inherited ones.
+    pub(crate) fn functions(&self) -> impl Iterator<Item = &Function> {
+        self.methods.values().chain(self.setters.values())
+    }
+
+    /// The methods compiled for the VM, not counting inherited ones.
+    pub(crate) fn compiled_methods(&self) -> impl Iterator<Item = &ClosureRef> {
+        self.compiled_methods.values()
+    }
+
+    pub(crate) fn constants(&self) -> impl Iterator<Item = &Value> {
+        self.constants.values()
+    }
+
+    pub fn define_static(&self, name: &str, value: Value) {
+        self.statics.borrow_mut().insert(Symbol::from(name), value);
+    }
+
+    /// Looks up a class constant or static field, falling back to the
+    /// superclass.
+    pub fn get_static(&self, name: &Token) -> Result<Value, Exception> {
+        match self.find_static(&name.lexeme) {
+            Some(value) => Ok(value),
+            None => Exception::error(
+                name.clone(),
+                RuntimeErrorKind::UndefinedStatic {
+                    class: self.name.clone(),
+                    name: name.lexeme.to_string(),
+                },
+            ),
+        }
+    }
+
+    fn find_static(&self, name: &str) -> Option<Value> {
+        if let Some(value) = self.constants.get(name) {
+            return Some(value.clone());
+        }
+        if let Some(value) = self.statics.borrow().get(name) {
+            return Some(value.clone());
+        }
+        self.super_class.as_ref()?.find_static(name)
+    }
+
+    /// Assigns a static field on the class that declares it, so subclasses
+    /// share their superclass's state. New names are added to this class.
+    pub fn set_static(&self, name: &Token, value: Value) -> Result<(), Exception> {
+        if self.is_constant(&name.lexeme) {
+            return Exception::error(
+                name.clone(),
+                RuntimeErrorKind::ConstantReassignment {
+                    name: name.lexeme.to_string(),
+                },
+            );
+        }
+        let mut class = self;
+        loop {
+            if class.statics.borrow().contains_key(&name.lexeme) {
+                break;
+            }
+            match &class.super_class {
+                Some(super_class) => class = super_class,
+                None => {
+                    class = self;
+                    break;
+                }
+            }
+        }
+        class.define_static(&name.lexeme, value);
+        Ok(())
+    }
+
+    /// Whether this class is `other` or inherits from it. Copies of a class
+    /// value share their statics, which makes that table its identity.
+    pub fn is_subclass_of(&self, other: &Class) -> bool {
+        Rc::ptr_eq(&self.statics, &other.statics)
+            || self
+                .super_class
+                .as_ref()
+                .is_some_and(|super_class| super_class.is_subclass_of(other))
+    }
+
+    fn is_constant(&self, name: &str) -> bool {
+        self.constants.contains_key(name)
+            || self
+                .super_class
+                .as_ref()
+                .is_some_and(|super_class| super_class.is_constant(name))
+    }
+
+    /// Evaluates the declared field initializers for a new instance,
+    /// superclass fields first so subclasses can override them.
+    fn initialize_fields(
+        &self,
+        interpreter: &mut Interpreter,
+        instance: &ClassInstanceRef,
+    ) -> Result<(), Exception> {
+        if let Some(super_class) = &self.super_class {
+            super_class.initialize_fields(interpreter, instance)?;
+        }
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+
+        let environment = Environment::new_local(&self.closure);
+        environment
+            .borrow_mut()
+            .define(Symbol::from("this"), Value::ClassInstance(instance.clone()));
+        for field in self.fields.iter() {
+            if let Stmt::Var { name, initializer } = field {
+                let value = match initializer {
+                    None => Value::Nil,
+                    Some(expr) => interpreter.evaluate_in(expr, environment.clone())?,
+                };
+                interpreter.allocate(memory::field_size(&name.lexeme))?;
+                instance.borrow_mut().set(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn find_setter(&self, name: &str) -> Option<Function> {
+        self.setters.get(name).cloned().or_else(|| {
+            self.super_class
+                .as_ref()
+                .and_then(|super_class| super_class.find_setter(name))
+        })
+    }
+
     pub fn find_method(&self, name: &str) -> Option<Value> {
         self.methods
             .get(name)
             .map(|method| Value::Function(method.clone()))
-            .or(self
-                .super_class
-                .as_ref()
-                .and_then(|super_class| super_class.find_method(name)))
+            .or_else(|| {
+                self.compiled_methods
+                    .get(name)
+                    .map(|method| Value::Closure(method.clone()))
+            })
+            .or_else(|| {
+                self.native_methods
+                    .get(name)
+                    .map(|method| Value::NativeFunction(method.clone()))
+            })
+            .or_else(|| {
+                self.super_class
+                    .as_ref()
+                    .and_then(|super_class| super_class.find_method(name))
+            })
     }
-}
 
-impl Callable for Class {
-    fn arity(&self) -> usize {
-        if let Some(initializer) = self.find_method("init") {
-            match initializer {
-                Value::Function(initializer) => return initializer.arity(),
-                _ => panic!("initializer is not a function!"),
+    /// Looks up a method and binds `this` to `instance`.
+    pub fn bind_method(&self, name: &str, instance: ClassInstanceRef) -> Option<Value> {
+        match self.find_method(name)? {
+            Value::Function(mut method) => Some(Value::Function(method.bind(instance))),
+            Value::NativeFunction(method) => Some(Value::NativeFunction(
+                method.bind(Value::ClassInstance(instance)),
+            )),
+            Value::Closure(method) => {
+                Some(Value::Closure(method.bind(Value::ClassInstance(instance))))
             }
+            _ => unreachable!("methods are functions"),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self.find_method("init") {
+            Some(Value::Function(initializer)) => initializer.arity(),
+            Some(Value::NativeFunction(initializer)) => initializer.arity,
+            Some(Value::Closure(initializer)) => initializer.arity(),
+            _ => 0,
+        }
+    }
+
+    pub fn is_variadic(&self) -> bool {
+        match self.find_method("init") {
+            Some(Value::Function(initializer)) => initializer.is_variadic(),
+            Some(Value::NativeFunction(initializer)) => initializer.variadic,
+            _ => false,
         }
-        0
     }
 
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, Exception> {
+    /// Creates an instance and runs its initializer. Errors from a native
+    /// initializer are reported at `paren`.
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        args: Vec<Value>,
+    ) -> Result<Value, Exception> {
+        self.instantiate(interpreter, args)
+            .map_err(|error| error.at(paren))
+    }
+
+    /// Creates an instance for a native that has no call site to point
+    /// errors at.
+    pub fn instantiate(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> NativeResult {
         let instance = ClassInstance::new(self.clone());
+        self.initialize_fields(interpreter, &instance)?;
 
-        if let Some(initializer) = self.find_method("init") {
-            match initializer {
-                Value::Function(mut initializer) => {
-                    let _ = initializer.bind(instance.clone()).call(interpreter, args);
-                }
-                _ => panic!("Initializer is not a function"),
+        match self.bind_method("init", instance.clone()) {
+            Some(Value::Function(initializer)) => {
+                initializer.call(interpreter, args)?;
+            }
+            Some(Value::NativeFunction(initializer)) => {
+                (initializer.callable)(interpreter, args)?;
             }
+            Some(Value::Closure(initializer)) => {
+                vm::call(interpreter, &initializer, args)?;
+            }
+            _ => {}
         }
 
         Ok(Value::ClassInstance(instance))
@@ -75,18 +403,51 @@ impl fmt::Display for Class {
 
 pub type ClassInstanceRef = Rc<RefCell<ClassInstance>>;
 
+/// The Rust value behind an instance of a `NativeClass`.
+#[derive(Clone, Debug)]
+struct NativeData(Rc<RefCell<AnyValue>>);
+
+impl PartialEq for NativeData {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ClassInstance {
     class: Class,
-    fields: HashMap<String, Value>,
+    fields: HashMap<Symbol, Value>,
+    data: Option<NativeData>,
+    registration: Option<gc::Registration>,
 }
 
 impl ClassInstance {
     pub fn new(class: Class) -> ClassInstanceRef {
-        Rc::new(RefCell::new(ClassInstance {
+        let instance = Rc::new(RefCell::new(ClassInstance {
             class,
             fields: HashMap::new(),
-        }))
+            data: None,
+            registration: None,
+        }));
+        instance.borrow_mut().registration = Some(gc::register(&instance));
+        instance
+    }
+
+    pub fn class_name(&self) -> &str {
+        &self.class.name
+    }
+
+    pub fn class(&self) -> &Class {
+        &self.class
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&Symbol, &Value)> {
+        self.fields.iter()
+    }
+
+    /// Removes every field's value, for the collector to drop.
+    pub(crate) fn take_fields(&mut self) -> Vec<Value> {
+        self.fields.drain().map(|(_, value)| value).collect()
     }
 
     pub fn get(&self, name: &Token, instance_ref: ClassInstanceRef) -> Result<Value, Exception> {
@@ -94,15 +455,35 @@ impl ClassInstance {
             return Ok(val.clone());
         }
 
-        if let Some(Value::Function(mut method)) = self.class.find_method(&name.lexeme) {
-            let bound = method.bind(instance_ref.clone());
-            return Ok(Value::Function(bound));
+        if let Some(method) = self.class.bind_method(&name.lexeme, instance_ref) {
+            return Ok(method);
         }
 
-        Err(Exception::RuntimeError(RuntimeError {
-            token: name.clone(),
-            message: format!("Undefined property '{}'", name.lexeme),
-        }))
+        Exception::error(
+            name.clone(),
+            RuntimeErrorKind::UndefinedProperty {
+                name: name.lexeme.to_string(),
+            },
+        )
+    }
+
+    pub fn find_setter(&self, name: &str) -> Option<Function> {
+        self.class.find_setter(name)
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Function> {
+        match self.class.find_method(name) {
+            Some(Value::Function(method)) => Some(method),
+            _ => None,
+        }
+    }
+
+    pub fn is_instance_of(&self, class: &Class) -> bool {
+        self.class.is_subclass_of(class)
+    }
+
+    pub fn has_field(&self, name: &str) -> bool {
+        self.fields.contains_key(name)
     }
 
     pub fn set(&mut self, name: &Token, value: Value) {
@@ -0,0 +1,44 @@
+//! Reading the time in a way that also works in the browser, where
+//! `SystemTime::now` and `Instant::now` panic on wasm32-unknown-unknown.
+
+use std::time::Duration;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use std::time::Instant;
+
+/// Time since the Unix epoch.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn since_epoch() -> Duration {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Time since the Unix epoch, from JavaScript's `Date.now()`.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub fn since_epoch() -> Duration {
+    Duration::from_secs_f64(js_sys::Date::now() / 1000.0)
+}
+
+/// Stands in for `std::time::Instant`, which has no clock to read here.
+/// Built on the wall clock, so it can jump if the system time changes.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(Duration);
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Instant {
+    pub fn now() -> Self {
+        Instant(since_epoch())
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl std::ops::Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0 + duration)
+    }
+}
@@ -0,0 +1,290 @@
+use crate::function::Function;
+use crate::sync::Rc;
+use std::fmt;
+use std::fmt::Formatter;
+
+pub type CoroutineRef = Rc<Coroutine>;
+
+/// Each coroutine's stack unless the options say otherwise. Pages are only
+/// committed once they are used.
+#[cfg(not(target_family = "wasm"))]
+const STACK_SIZE: usize = 16 * 1024 * 1024;
+
+/// A function that can suspend itself with `yield` and be resumed later.
+///
+/// The interpreter keeps its call stack on the Rust stack, so each coroutine
+/// body runs on a stack of its own, where its frames stay while it is
+/// suspended. Resuming switches to that stack and `yield` switches back,
+/// both on the same thread, much like calling and returning.
+pub struct Coroutine {
+    function: Function,
+    #[cfg(not(target_family = "wasm"))]
+    state: switching::Mutex<switching::State>,
+}
+
+impl Coroutine {
+    pub fn new(function: Function) -> Self {
+        Coroutine {
+            function,
+            #[cfg(not(target_family = "wasm"))]
+            state: switching::Mutex::new(switching::State::Fresh),
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub use switching::suspend;
+#[cfg(target_family = "wasm")]
+pub use unsupported::suspend;
+
+#[cfg(not(target_family = "wasm"))]
+mod switching {
+    use super::{Coroutine, STACK_SIZE};
+    use crate::function::Callable;
+    use crate::interpreter::Interpreter;
+    use crate::stack;
+    use crate::token::Token;
+    use crate::value::Value;
+    use crate::Exception;
+    use corosensei::stack::DefaultStack;
+    use corosensei::{CoroutineResult, Yielder};
+    use std::cell::Cell;
+    use std::ptr;
+    pub use std::sync::Mutex;
+    use std::sync::PoisonError;
+    use std::thread::{self, ThreadId};
+
+    pub enum State {
+        Fresh,
+        Suspended(Body),
+        Running,
+        Done,
+    }
+
+    /// A started coroutine, with the frames of its body on its own stack.
+    pub struct Body {
+        stack: corosensei::Coroutine<Resume, Value, Result<Value, Exception>>,
+        /// Where the stack counts as full.
+        limit: usize,
+        /// The interpreter the frames on the stack use.
+        interpreter: *const Interpreter,
+        /// The thread that started the body, the only one that may resume
+        /// it, since its frames may hold on to that thread's locals.
+        thread: ThreadId,
+        /// How deeply the body's calls are nested, which is kept apart from
+        /// the resumer's because they are on different stacks.
+        call_depth: usize,
+    }
+
+    // SAFETY: with the `sync` feature every value is `Send`, as is the
+    // interpreter the suspended frames refer to. `resume` only lets the
+    // frames run again with that same interpreter on the thread that started
+    // them, so moving a body elsewhere just moves ownership of it.
+    #[cfg(feature = "sync")]
+    unsafe impl Send for Body {}
+
+    /// What a body is given each time it is resumed.
+    struct Resume {
+        interpreter: *mut Interpreter,
+        value: Value,
+    }
+
+    thread_local! {
+        /// The body running on this thread, for `yield` to suspend.
+        static CURRENT: Cell<*const Yielder<Resume, Value>> = const { Cell::new(ptr::null()) };
+    }
+
+    impl Coroutine {
+        pub fn is_done(&self) -> bool {
+            matches!(
+                *self.state.lock().unwrap_or_else(PoisonError::into_inner),
+                State::Done
+            )
+        }
+
+        fn replace_state(&self, state: State) -> State {
+            let mut current = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            std::mem::replace(&mut current, state)
+        }
+
+        /// Runs the coroutine until it yields or returns. On its first run
+        /// the function gets `value` as its argument if it takes one.
+        pub fn resume(
+            &self,
+            interpreter: &mut Interpreter,
+            paren: &Token,
+            value: Value,
+        ) -> Result<Value, Exception> {
+            let mut body = match self.replace_state(State::Running) {
+                State::Suspended(body) => body,
+                State::Fresh => match self.start(interpreter, paren) {
+                    Ok(body) => body,
+                    Err(error) => {
+                        self.replace_state(State::Fresh);
+                        return Err(error);
+                    }
+                },
+                State::Running => {
+                    return Exception::runtime_error(
+                        paren.clone(),
+                        "Coroutine is already running.".to_string(),
+                    )
+                }
+                State::Done => {
+                    self.replace_state(State::Done);
+                    return Exception::runtime_error(
+                        paren.clone(),
+                        "Can't resume a finished coroutine.".to_string(),
+                    );
+                }
+            };
+            if !ptr::eq(body.interpreter, interpreter) {
+                self.replace_state(State::Suspended(body));
+                return Exception::runtime_error(
+                    paren.clone(),
+                    "Can't resume a coroutine from a different interpreter.".to_string(),
+                );
+            }
+            if body.thread != thread::current().id() {
+                self.replace_state(State::Suspended(body));
+                return Exception::runtime_error(
+                    paren.clone(),
+                    "Can't resume a coroutine from a different thread.".to_string(),
+                );
+            }
+
+            let depth = interpreter.replace_call_depth(body.call_depth);
+            let resumer = CURRENT.get();
+            let resume = Resume {
+                interpreter: &mut *interpreter,
+                value,
+            };
+            let result = stack::switched(body.limit, || body.stack.resume(resume));
+            CURRENT.set(resumer);
+            body.call_depth = interpreter.replace_call_depth(depth);
+
+            match result {
+                CoroutineResult::Yield(value) => {
+                    self.replace_state(State::Suspended(body));
+                    Ok(value)
+                }
+                CoroutineResult::Return(result) => {
+                    self.replace_state(State::Done);
+                    result
+                }
+            }
+        }
+
+        fn start(&self, interpreter: &Interpreter, paren: &Token) -> Result<Body, Exception> {
+            let function = self.function.clone();
+            let size = interpreter
+                .options()
+                .coroutine_stack_size
+                .unwrap_or(STACK_SIZE)
+                .max(stack::MIN_SIZE);
+            let Ok(stack) = DefaultStack::new(size) else {
+                return Exception::runtime_error(
+                    paren.clone(),
+                    "Could not allocate a coroutine's stack.".to_string(),
+                );
+            };
+            let limit = stack::limit(&stack);
+            let stack = corosensei::Coroutine::with_stack(
+                stack,
+                move |yielder: &Yielder<Resume, Value>, resume: Resume| {
+                    CURRENT.set(yielder);
+                    // SAFETY: `resume` hands over the interpreter for as long
+                    // as the body runs, and only ever the same one.
+                    let interpreter = unsafe { &mut *resume.interpreter };
+                    let args = if function.arity() == 0 {
+                        vec![]
+                    } else {
+                        vec![resume.value]
+                    };
+                    function.call(interpreter, args)
+                },
+            );
+            Ok(Body {
+                stack,
+                limit,
+                interpreter,
+                thread: thread::current().id(),
+                call_depth: 0,
+            })
+        }
+    }
+
+    /// Hands `value` to whoever resumed the running coroutine and waits to
+    /// be resumed, returning the value passed back in.
+    ///
+    /// A coroutine dropped while suspended unwinds from here, dropping what
+    /// its frames held.
+    pub fn suspend(keyword: &Token, value: Value) -> Result<Value, Exception> {
+        let yielder = CURRENT.get();
+        if yielder.is_null() {
+            return Exception::runtime_error(
+                keyword.clone(),
+                "Can't yield outside of a coroutine.".to_string(),
+            );
+        }
+        // SAFETY: `CURRENT` only points at the yielder of the body running
+        // now, which lives on that body's stack until the body returns.
+        let yielder = unsafe { &*yielder };
+        let resume = yielder.suspend(value);
+        CURRENT.set(yielder);
+        Ok(resume.value)
+    }
+}
+
+/// WebAssembly can't switch stacks, so coroutines can be made but not run.
+#[cfg(target_family = "wasm")]
+mod unsupported {
+    use super::Coroutine;
+    use crate::interpreter::Interpreter;
+    use crate::token::Token;
+    use crate::value::Value;
+    use crate::Exception;
+
+    impl Coroutine {
+        pub fn is_done(&self) -> bool {
+            false
+        }
+
+        pub fn resume(
+            &self,
+            _: &mut Interpreter,
+            paren: &Token,
+            _: Value,
+        ) -> Result<Value, Exception> {
+            Exception::runtime_error(
+                paren.clone(),
+                "Coroutines aren't supported on this platform.".to_string(),
+            )
+        }
+    }
+
+    pub fn suspend(keyword: &Token, _: Value) -> Result<Value, Exception> {
+        Exception::runtime_error(
+            keyword.clone(),
+            "Can't yield outside of a coroutine.".to_string(),
+        )
+    }
+}
+
+impl fmt::Debug for Coroutine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Coroutine({})", self.function)
+    }
+}
+
+impl PartialEq for Coroutine {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Coroutine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<coroutine>")
+    }
+}
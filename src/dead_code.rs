@@ -0,0 +1,288 @@
+//! Warns about code that can never run: statements after a `return`,
+//! `throw` or `continue`, and the branches and loop bodies behind a
+//! condition that is always true or always false. The program still runs,
+//! dead code and all.
+
+use crate::diagnostics::{ErrorCode, ErrorReporter};
+use crate::expr::Expr;
+use crate::stmt::Stmt;
+use crate::token::{Literal, Span, Token};
+use std::slice;
+
+pub struct DeadCode<'a> {
+    /// The tokens the statements were parsed from, which tell where the
+    /// dead code starts.
+    tokens: &'a [Token],
+    reporter: &'a ErrorReporter,
+}
+
+impl<'a> DeadCode<'a> {
+    pub fn new(tokens: &'a [Token], reporter: &'a ErrorReporter) -> Self {
+        DeadCode { tokens, reporter }
+    }
+
+    pub fn check_block(&self, stmts: &[Stmt]) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            self.check_stmt(stmt);
+            if let Some(keyword) = Self::exit(stmt) {
+                self.warn(
+                    ErrorCode::UnreachableCode,
+                    &stmts[i + 1..],
+                    &format!("Unreachable code after '{}'.", keyword.lexeme),
+                );
+                return;
+            }
+        }
+    }
+
+    /// The `return`, `throw` or `continue` that always leaves `stmt` early,
+    /// if there is one.
+    fn exit(stmt: &Stmt) -> Option<&Token> {
+        match stmt {
+            Stmt::Return { keyword, .. }
+            | Stmt::Throw { keyword, .. }
+            | Stmt::Continue { keyword } => Some(keyword),
+            Stmt::Block(stmts) => stmts.iter().find_map(Self::exit),
+            Stmt::If {
+                then_branch,
+                else_branch: Some(else_branch),
+                ..
+            } => Self::exit(then_branch).and(Self::exit(else_branch)),
+            _ => None,
+        }
+    }
+
+    /// Whether a condition is a literal, and so always true or always
+    /// false.
+    fn constant(condition: &Expr) -> Option<bool> {
+        match condition {
+            Expr::Literal { value, .. } => Some(match value {
+                Literal::Bool(value) => *value,
+                Literal::None => false,
+                _ => true,
+            }),
+            Expr::Grouping { expr, .. } => Self::constant(expr),
+            _ => None,
+        }
+    }
+
+    fn check_stmt(&self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) | Stmt::Print { expr, .. } => self.check_expr(expr),
+            Stmt::Var { initializer, .. } => self.check_optional(initializer.as_ref()),
+            Stmt::Const { initializer, .. } => self.check_expr(initializer),
+            Stmt::Block(stmts) => self.check_block(stmts),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.check_expr(condition);
+                let constant = Self::constant(condition);
+                self.check_branch(then_branch, constant == Some(false), "false");
+                if let Some(else_branch) = else_branch {
+                    self.check_branch(else_branch, constant == Some(true), "true");
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                self.check_expr(condition);
+                if Self::constant(condition) == Some(false) {
+                    self.warn(
+                        ErrorCode::ConstantCondition,
+                        slice::from_ref(body),
+                        "Condition is always false, so the loop body never runs.",
+                    );
+                } else {
+                    self.check_stmt(body);
+                }
+                self.check_optional(increment.as_ref());
+            }
+            Stmt::DoWhile { body, condition } => {
+                self.check_stmt(body);
+                self.check_expr(condition);
+            }
+            Stmt::Function(function) => self.check_block(&function.body),
+            Stmt::Return { value, .. } => self.check_optional(value.as_ref()),
+            Stmt::Assert {
+                condition, message, ..
+            } => {
+                self.check_expr(condition);
+                self.check_optional(message.as_ref());
+            }
+            Stmt::Throw { value, .. } => self.check_expr(value),
+            Stmt::Try {
+                body,
+                handler,
+                finally,
+            } => {
+                self.check_block(body);
+                if let Some((_, handler)) = handler {
+                    self.check_block(handler);
+                }
+                if let Some(finally) = finally {
+                    self.check_block(finally);
+                }
+            }
+            Stmt::ForIn { iterable, body, .. } => {
+                self.check_expr(iterable);
+                self.check_stmt(body);
+            }
+            Stmt::Class {
+                methods,
+                fields,
+                constants,
+                statics,
+                super_class,
+                ..
+            } => {
+                self.check_optional(super_class.as_ref());
+                for stmt in methods.iter().chain(fields.iter()).chain(constants).chain(statics) {
+                    self.check_stmt(stmt);
+                }
+            }
+            Stmt::Continue { .. } | Stmt::Import { .. } => {}
+        }
+    }
+
+    /// Checks a branch of an `if`, or reports it if the condition is
+    /// always `condition`, the opposite of what it needs to run.
+    fn check_branch(&self, branch: &Stmt, dead: bool, condition: &str) {
+        if dead {
+            self.warn(
+                ErrorCode::ConstantCondition,
+                slice::from_ref(branch),
+                &format!(
+                    "Condition is always {}, so this branch never runs.",
+                    condition
+                ),
+            );
+        } else {
+            self.check_stmt(branch);
+        }
+    }
+
+    /// Looks for function bodies and block expressions inside `expr`.
+    fn check_expr(&self, expr: &Expr) {
+        match expr {
+            Expr::Literal { .. } | Expr::Var { .. } | Expr::This { .. } | Expr::Super { .. } => {}
+            Expr::Unary { right: expr, .. }
+            | Expr::Grouping { expr, .. }
+            | Expr::Assign { value: expr, .. }
+            | Expr::Get { object: expr, .. } => self.check_expr(expr),
+            Expr::Binary { left, right, .. }
+            | Expr::Logical { left, right, .. }
+            | Expr::Set {
+                object: left,
+                value: right,
+                ..
+            }
+            | Expr::Is {
+                object: left,
+                class: right,
+                ..
+            }
+            | Expr::Index {
+                object: left,
+                index: right,
+                ..
+            } => {
+                self.check_expr(left);
+                self.check_expr(right);
+            }
+            Expr::Call {
+                callee,
+                arguments,
+                named,
+                ..
+            } => {
+                self.check_expr(callee);
+                arguments
+                    .iter()
+                    .for_each(|argument| self.check_expr(argument));
+                named
+                    .iter()
+                    .for_each(|(_, argument)| self.check_expr(argument));
+            }
+            Expr::List { elements, .. }
+            | Expr::Tuple { elements, .. }
+            | Expr::Sequence {
+                expressions: elements,
+                ..
+            }
+            | Expr::Comparison {
+                operands: elements, ..
+            } => elements.iter().for_each(|element| self.check_expr(element)),
+            Expr::Yield { value, .. } => self.check_optional(value.as_deref()),
+            Expr::Lambda { declaration, .. } => self.check_block(&declaration.body),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.check_expr(object);
+                self.check_expr(index);
+                self.check_expr(value);
+            }
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                self.check_expr(object);
+                self.check_optional(start.as_deref());
+                self.check_optional(end.as_deref());
+            }
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.check_expr(condition);
+                self.check_expr(then_branch);
+                self.check_expr(else_branch);
+            }
+            Expr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.check_expr(condition);
+                self.check_expr(then_branch);
+                self.check_optional(else_branch.as_deref());
+            }
+            Expr::Block {
+                statements, value, ..
+            } => {
+                self.check_block(statements);
+                self.check_optional(value.as_deref());
+            }
+        }
+    }
+
+    fn check_optional(&self, expr: Option<&Expr>) {
+        if let Some(expr) = expr {
+            self.check_expr(expr);
+        }
+    }
+
+    /// Reports `stmts` as dead, pointing at the token they start with.
+    /// Statements with nothing in them, like `{}`, aren't worth a warning.
+    fn warn(&self, code: ErrorCode, stmts: &[Stmt], message: &str) {
+        let Some(span) = stmts.iter().filter_map(Stmt::span).reduce(Span::to) else {
+            return;
+        };
+        let first = self
+            .tokens
+            .partition_point(|token| token.span.start < span.start);
+        let Some(token) = self.tokens.get(first) else {
+            return;
+        };
+        self.reporter
+            .warning(code, token.line, span, &token.lexeme, message);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::token::Span;
+use std::cell::RefCell;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// An error found while compiling source code, before it runs, or a
+/// warning about code that compiles but is probably a mistake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub code: ErrorCode,
+    pub line: usize,
+    /// Where in the source the error is.
+    pub span: Span,
+    /// The source text the error points at, or a description such as
+    /// "at end".
+    pub location: String,
+    pub message: String,
+}
+
+/// Which stage of compiling found a `Diagnostic`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// Text that isn't a valid token, like an unterminated string.
+    Scan,
+    /// Tokens that don't form a valid program.
+    Parse,
+    /// A well-formed program that breaks a scoping rule, like `return`
+    /// outside a function.
+    Resolve,
+    /// Code that compiles but can't be right, like statements after a
+    /// `return`. These are warnings and don't stop the program running.
+    Lint,
+}
+
+/// A stable code for each kind of error, printed as `E1001` and named by
+/// its variant. Scanner codes start at 1001, parser codes at 2001,
+/// resolver codes at 3001, runtime codes at 4001 and warning codes at 5001.
+/// Codes are never reused for a different error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    UnexpectedCharacter = 1001,
+    UnterminatedComment = 1002,
+    UnterminatedString = 1003,
+    InvalidEscape = 1004,
+    InvalidUnicodeEscape = 1005,
+    InvalidCodePoint = 1006,
+    MisplacedDigitSeparator = 1007,
+
+    /// A missing token, like the `;` after a statement.
+    ExpectedToken = 2001,
+    ExpectedExpression = 2002,
+    InvalidAssignmentTarget = 2003,
+    TooManyArguments = 2004,
+    RestParameterNotLast = 2005,
+    PositionalAfterNamed = 2006,
+    /// Statements or expressions nested, or operators chained, past the
+    /// parser's limit.
+    TooDeeplyNested = 2007,
+
+    DuplicateVariable = 3001,
+    ReadInOwnInitializer = 3002,
+    ConstantReassignment = 3003,
+    TopLevelReturn = 3004,
+    ReturnFromInitializer = 3005,
+    ContinueOutsideLoop = 3006,
+    YieldOutsideFunction = 3007,
+    SelfInheritance = 3008,
+    NestedImport = 3009,
+    ThisOutsideClass = 3010,
+    SuperOutsideClass = 3011,
+    SuperWithoutSuperclass = 3012,
+
+    /// A runtime error without a more specific code, including those
+    /// raised by natives and `error()`.
+    RuntimeError = 4000,
+    UndefinedVariable = 4001,
+    UndefinedProperty = 4002,
+    UndefinedStatic = 4003,
+    UndefinedModuleMember = 4004,
+    ConstantReassignedAtRuntime = 4005,
+    ArityMismatch = 4006,
+    NotCallable = 4007,
+    DivisionByZero = 4008,
+    IndexOutOfRange = 4009,
+    UncaughtException = 4010,
+    ExecutionLimit = 4011,
+    MemoryLimit = 4012,
+    Interrupted = 4013,
+    StackOverflow = 4014,
+
+    /// Statements after a `return`, `throw` or `continue`.
+    UnreachableCode = 5001,
+    /// A branch or loop body that a literal condition always skips.
+    ConstantCondition = 5002,
+    /// Something the VM backend can't compile yet, so the program runs on
+    /// the tree-walker instead.
+    UnsupportedByVm = 5003,
+}
+
+impl ErrorCode {
+    pub fn number(self) -> u16 {
+        self as u16
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "E{}", self.number())
+    }
+}
+
+impl Diagnostic {
+    pub fn is_warning(&self) -> bool {
+        self.kind == DiagnosticKind::Lint
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let severity = if self.is_warning() {
+            "Warning"
+        } else {
+            "Error"
+        };
+        write!(
+            f,
+            "[line {}] {} {} at '{}': {}",
+            self.line, severity, self.code, self.location, self.message
+        )
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Collects the diagnostics reported by the scanner, parser and resolver.
+/// Warnings are kept apart, so they don't count as errors.
+///
+/// Reporting only needs a shared reference, so the parser can report while
+/// it holds on to the token it is complaining about.
+#[derive(Debug, Default)]
+pub struct ErrorReporter {
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    warnings: RefCell<Vec<Diagnostic>>,
+}
+
+impl ErrorReporter {
+    pub fn new() -> Self {
+        ErrorReporter::default()
+    }
+
+    pub fn error(
+        &self,
+        kind: DiagnosticKind,
+        code: ErrorCode,
+        line: usize,
+        span: Span,
+        location: &str,
+        message: &str,
+    ) {
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            kind,
+            code,
+            line,
+            span,
+            location: location.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    pub fn warning(&self, code: ErrorCode, line: usize, span: Span, location: &str, message: &str) {
+        self.warnings.borrow_mut().push(Diagnostic {
+            kind: DiagnosticKind::Lint,
+            code,
+            line,
+            span,
+            location: location.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    pub fn had_error(&self) -> bool {
+        !self.diagnostics.borrow().is_empty()
+    }
+
+    pub fn count(&self) -> usize {
+        self.diagnostics.borrow().len()
+    }
+
+    /// Removes and returns every error reported so far.
+    pub fn take(&self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+
+    /// Removes and returns every warning reported so far.
+    pub fn take_warnings(&self) -> Vec<Diagnostic> {
+        self.warnings.take()
+    }
+}
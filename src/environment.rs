@@ -1,15 +1,27 @@
+use crate::gc;
+use crate::symbol::Symbol;
+use crate::sync::{Rc, RefCell};
 use crate::token::Token;
 use crate::value::Value;
-use crate::Exception;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+use crate::{Exception, RuntimeErrorKind};
+use std::collections::{HashMap, HashSet};
 
 pub type EnvRef = Rc<RefCell<Environment>>;
 
+/// The variables of one scope. Globals, and those at the top level of an
+/// imported file, are looked up by name. The resolver knows where every
+/// other variable is declared, so those are kept in slots, in the order
+/// they were declared, and found by index.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Environment {
-    values: HashMap<String, Value>,
+    values: HashMap<Symbol, Value>,
+    constants: HashSet<Symbol>,
+    slots: Vec<Value>,
+    /// Set on the top-level environment of an imported file, which holds
+    /// that file's globals.
+    module_root: bool,
+    /// Set once the environment is registered with the collector.
+    registration: Option<gc::Registration>,
     pub enclosing: Option<EnvRef>,
 }
 
@@ -17,6 +29,10 @@ impl Environment {
     pub fn new() -> EnvRef {
         Rc::new(RefCell::new(Environment {
             values: HashMap::new(),
+            constants: HashSet::new(),
+            slots: Vec::new(),
+            module_root: false,
+            registration: None,
             enclosing: None,
         }))
     }
@@ -25,19 +41,112 @@ impl Environment {
         Rc::new(RefCell::new(Environment {
             enclosing: Some(enclosing.clone()),
             values: HashMap::new(),
+            constants: HashSet::new(),
+            slots: Vec::new(),
+            module_root: false,
+            registration: None,
         }))
     }
 
-    pub fn define(&mut self, name: String, value: Value) {
+    pub fn new_module(globals: &EnvRef) -> EnvRef {
+        let environment = Environment::new_local(globals);
+        environment.borrow_mut().module_root = true;
+        environment
+    }
+
+    /// Registers an environment that a function or class is keeping, and the
+    /// ones around it, with the collector. Until then only running code and
+    /// the environments inside it can refer to one, so it can't be part of a
+    /// cycle.
+    pub(crate) fn capture(environment: &EnvRef) {
+        let mut next = Some(environment.clone());
+        while let Some(environment) = next {
+            let Ok(mut inner) = environment.try_borrow_mut() else {
+                // Registering it twice does no harm.
+                gc::track(&environment);
+                return;
+            };
+            if inner.registration.is_some() {
+                return;
+            }
+            inner.registration = Some(gc::register(&environment));
+            next = inner.enclosing.clone();
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = (&Symbol, &Value)> {
+        self.values.iter()
+    }
+
+    pub fn slots(&self) -> &[Value] {
+        &self.slots
+    }
+
+    /// Removes every variable's value, for the collector to drop.
+    pub(crate) fn take_values(&mut self) -> Vec<Value> {
+        let mut values: Vec<Value> = self.values.drain().map(|(_, value)| value).collect();
+        values.append(&mut self.slots);
+        values
+    }
+
+    fn is_named(&self) -> bool {
+        self.enclosing.is_none() || self.module_root
+    }
+
+    /// Looks up a variable defined directly in this environment.
+    pub fn lookup(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    /// Defines a variable by name, or in the next slot of a local scope.
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        if !self.is_named() {
+            self.slots.push(value);
+            return;
+        }
+        self.constants.remove(&name);
+        self.values.insert(name, value);
+    }
+
+    /// Local constants are checked by the resolver, so only named ones are
+    /// remembered.
+    pub fn define_constant(&mut self, name: Symbol, value: Value) {
+        if !self.is_named() {
+            self.slots.push(value);
+            return;
+        }
+        self.constants.insert(name.clone());
         self.values.insert(name, value);
     }
 
-    pub fn get_at(&self, distance: usize, name: &str) -> Result<Value, Exception> {
+    /// Gives the variable most recently defined here a new value, e.g. a
+    /// class once its methods have been made.
+    pub fn redefine_last(&mut self, name: Symbol, value: Value) {
+        if self.is_named() {
+            self.values.insert(name, value);
+        } else if let Some(slot) = self.slots.last_mut() {
+            *slot = value;
+        }
+    }
+
+    fn check_mutable(&self, name: &Token) -> Result<(), Exception> {
+        if self.constants.contains(&name.lexeme) {
+            return Exception::error(
+                name.clone(),
+                RuntimeErrorKind::ConstantReassignment {
+                    name: name.lexeme.to_string(),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    pub fn get_at(&self, distance: usize, slot: usize) -> Value {
         if distance == 0 {
-            return Ok(self.values.get(name).unwrap().clone());
+            return self.slots[slot].clone();
         }
         if let Some(enclosing) = &self.enclosing {
-            return enclosing.borrow().get_at(distance - 1, name);
+            return enclosing.borrow().get_at(distance - 1, slot);
         }
         panic!("Could not find local scope that variable belongs to.")
     }
@@ -51,18 +160,35 @@ impl Environment {
             return enclosing.borrow().get(name);
         }
 
-        Exception::runtime_error(name.clone(), format!("Undefined variable {}.", name.lexeme))
+        Exception::error(
+            name.clone(),
+            RuntimeErrorKind::UndefinedVariable {
+                name: name.lexeme.to_string(),
+            },
+        )
     }
 
-    pub fn assign_at(&mut self, distance: usize, name: &Token, value: &Value) {
+    /// Looks up a variable the resolver left unresolved. Those live either
+    /// in the globals of the file the code came from or in the real globals,
+    /// so every other environment on the way is skipped.
+    pub fn get_global(&self, name: &Token) -> Result<Value, Exception> {
+        match &self.enclosing {
+            None => self.get(name),
+            Some(enclosing) => match self.values.get(&name.lexeme) {
+                Some(value) if self.module_root => Ok(value.clone()),
+                _ => enclosing.borrow().get_global(name),
+            },
+        }
+    }
+
+    pub fn assign_at(&mut self, distance: usize, slot: usize, value: Value) {
         if distance == 0 {
-            self.values.insert(name.lexeme.clone(), value.clone());
+            self.slots[slot] = value;
             return;
         }
 
         if let Some(enclosing) = &self.enclosing {
-            enclosing.borrow_mut().assign_at(distance - 1, name, value);
-            return;
+            return enclosing.borrow_mut().assign_at(distance - 1, slot, value);
         }
 
         panic!("Could not find local scope that variable belongs to!")
@@ -70,6 +196,7 @@ impl Environment {
 
     pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), Exception> {
         if self.values.contains_key(&name.lexeme) {
+            self.check_mutable(name)?;
             self.values.insert(name.lexeme.clone(), value);
             return Ok(());
         }
@@ -78,6 +205,11 @@ impl Environment {
             return enclosing.borrow_mut().assign(name, value);
         }
 
-        Exception::runtime_error(name.clone(), format!("Undefined variable {}.", name.lexeme))
+        Exception::error(
+            name.clone(),
+            RuntimeErrorKind::UndefinedVariable {
+                name: name.lexeme.to_string(),
+            },
+        )
     }
 }
@@ -1,6 +1,6 @@
-use std::hash::{Hash, Hasher};
-
-use crate::token::{Literal, Token};
+use crate::stmt::{FunctionDecl, Stmt};
+use crate::sync::Rc;
+use crate::token::{Literal, Span, Token};
 
 pub trait Visitor<T> {
     fn visit_expr(&mut self, expr: &Expr) -> T;
@@ -9,69 +9,300 @@ pub trait Visitor<T> {
 #[derive(Debug, Clone)]
 pub enum Expr {
     Literal {
-        uid: u8,
+        uid: usize,
         value: Literal,
+        span: Span,
+        line: usize,
     },
     Unary {
-        uid: u8,
+        uid: usize,
         operator: Token,
         right: Box<Expr>,
     },
     Grouping {
-        uid: u8,
+        uid: usize,
         expr: Box<Expr>,
     },
     Binary {
-        uid: u8,
+        uid: usize,
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
     Var {
-        uid: u8,
+        uid: usize,
         name: Token,
     },
     Assign {
-        uid: u8,
+        uid: usize,
         name: Token,
         value: Box<Expr>,
     },
     Logical {
-        uid: u8,
+        uid: usize,
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
     Call {
-        uid: u8,
+        uid: usize,
         callee: Box<Expr>,
         paren: Token,
         arguments: Vec<Expr>,
+        /// `name: value` arguments, which always follow the positional ones.
+        named: Vec<(Token, Expr)>,
     },
     Get {
-        uid: u8,
+        uid: usize,
         object: Box<Expr>,
         name: Token,
     },
     Set {
-        uid: u8,
+        uid: usize,
         object: Box<Expr>,
         name: Token,
         value: Box<Expr>,
     },
     This {
-        uid: u8,
+        uid: usize,
         keyword: Token,
     },
     Super {
-        uid: u8,
+        uid: usize,
         keyword: Token,
         method: Token,
     },
+    List {
+        uid: usize,
+        elements: Vec<Expr>,
+    },
+    Tuple {
+        uid: usize,
+        elements: Vec<Expr>,
+    },
+    Yield {
+        uid: usize,
+        keyword: Token,
+        value: Option<Box<Expr>>,
+    },
+    /// `(params) => value`, holding the function declaration it desugars to.
+    Lambda {
+        uid: usize,
+        declaration: Rc<FunctionDecl>,
+    },
+    /// `a, b, c`, evaluated left to right to the value of the last one.
+    Sequence {
+        uid: usize,
+        expressions: Vec<Expr>,
+    },
+    /// A chain of two or more comparisons like `a < b <= c`, with one more
+    /// operand than operators.
+    Comparison {
+        uid: usize,
+        operands: Vec<Expr>,
+        operators: Vec<Token>,
+    },
+    /// `object is Class`, true when the object is an instance of the class
+    /// or one of its subclasses.
+    Is {
+        uid: usize,
+        object: Box<Expr>,
+        keyword: Token,
+        class: Box<Expr>,
+    },
+    Index {
+        uid: usize,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        uid: usize,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Slice {
+        uid: usize,
+        object: Box<Expr>,
+        bracket: Token,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+    },
+    Ternary {
+        uid: usize,
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    If {
+        uid: usize,
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Option<Box<Expr>>,
+    },
+    Block {
+        uid: usize,
+        statements: Vec<Stmt>,
+        value: Option<Box<Expr>>,
+    },
 }
 
 impl Expr {
-    fn get_uid(&self) -> u8 {
+    /// The line the expression starts on, or `None` for an empty list,
+    /// tuple or block, which has nothing inside to tell it by.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Expr::Literal { line, .. } => Some(*line),
+            Expr::Unary { operator, .. } => Some(operator.line),
+            Expr::Grouping { expr, .. } => expr.line(),
+            Expr::Binary { left, operator, .. } | Expr::Logical { left, operator, .. } => {
+                left.line().or(Some(operator.line))
+            }
+            Expr::Var { name, .. } | Expr::Assign { name, .. } => Some(name.line),
+            Expr::Call { callee, paren, .. } => callee.line().or(Some(paren.line)),
+            Expr::Get { object, name, .. } | Expr::Set { object, name, .. } => {
+                object.line().or(Some(name.line))
+            }
+            Expr::This { keyword, .. } | Expr::Super { keyword, .. } => Some(keyword.line),
+            Expr::Yield { keyword, .. } => Some(keyword.line),
+            Expr::List { elements, .. }
+            | Expr::Tuple { elements, .. }
+            | Expr::Sequence {
+                expressions: elements,
+                ..
+            } => elements.iter().find_map(Expr::line),
+            Expr::Lambda { declaration, .. } => Some(declaration.name.line),
+            Expr::Comparison {
+                operands,
+                operators,
+                ..
+            } => operands[0].line().or(Some(operators[0].line)),
+            Expr::Is {
+                object, keyword, ..
+            } => object.line().or(Some(keyword.line)),
+            Expr::Index {
+                object, bracket, ..
+            }
+            | Expr::SetIndex {
+                object, bracket, ..
+            }
+            | Expr::Slice {
+                object, bracket, ..
+            } => object.line().or(Some(bracket.line)),
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => condition
+                .line()
+                .or_else(|| then_branch.line())
+                .or_else(|| else_branch.line()),
+            Expr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => condition
+                .line()
+                .or_else(|| then_branch.line())
+                .or_else(|| else_branch.as_ref().and_then(|branch| branch.line())),
+            Expr::Block {
+                statements, value, ..
+            } => statements
+                .iter()
+                .find_map(Stmt::line)
+                .or_else(|| value.as_ref().and_then(|value| value.line())),
+        }
+    }
+
+    /// The source the expression covers, from its first token to its last.
+    /// Brackets and parentheses around it aren't kept, so they're left out,
+    /// and an empty list or tuple has no span.
+    pub fn span(&self) -> Option<Span> {
+        let spans: Vec<Option<Span>> = match self {
+            Expr::Literal { span, .. } => vec![Some(*span)],
+            Expr::Unary {
+                operator, right, ..
+            } => vec![Some(operator.span), right.span()],
+            Expr::Grouping { expr, .. } => vec![expr.span()],
+            Expr::Binary { left, right, .. }
+            | Expr::Logical { left, right, .. }
+            | Expr::Is {
+                object: left,
+                class: right,
+                ..
+            } => vec![left.span(), right.span()],
+            Expr::Var { name, .. } => vec![Some(name.span)],
+            Expr::Assign { name, value, .. } => vec![Some(name.span), value.span()],
+            Expr::Call {
+                callee,
+                paren,
+                named,
+                ..
+            } => {
+                let mut spans = vec![callee.span(), Some(paren.span)];
+                spans.extend(named.iter().map(|(name, _)| Some(name.span)));
+                spans
+            }
+            Expr::Get { object, name, .. } => vec![object.span(), Some(name.span)],
+            Expr::Set { object, value, .. } => vec![object.span(), value.span()],
+            Expr::This { keyword, .. } => vec![Some(keyword.span)],
+            Expr::Super {
+                keyword, method, ..
+            } => vec![Some(keyword.span), Some(method.span)],
+            Expr::List { elements, .. }
+            | Expr::Tuple { elements, .. }
+            | Expr::Sequence {
+                expressions: elements,
+                ..
+            }
+            | Expr::Comparison {
+                operands: elements, ..
+            } => elements.iter().map(Expr::span).collect(),
+            Expr::Yield { keyword, value, .. } => {
+                vec![
+                    Some(keyword.span),
+                    value.as_ref().and_then(|value| value.span()),
+                ]
+            }
+            Expr::Lambda { declaration, .. } => vec![Some(declaration.span())],
+            Expr::Index {
+                object, bracket, ..
+            }
+            | Expr::Slice {
+                object, bracket, ..
+            } => vec![object.span(), Some(bracket.span)],
+            Expr::SetIndex { object, value, .. } => vec![object.span(), value.span()],
+            Expr::Ternary {
+                condition,
+                else_branch,
+                ..
+            } => vec![condition.span(), else_branch.span()],
+            Expr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => vec![
+                condition.span(),
+                then_branch.span(),
+                else_branch.as_ref().and_then(|branch| branch.span()),
+            ],
+            Expr::Block {
+                statements, value, ..
+            } => {
+                let mut spans: Vec<_> = statements.iter().map(Stmt::span).collect();
+                spans.push(value.as_ref().and_then(|value| value.span()));
+                spans
+            }
+        };
+        spans.into_iter().flatten().reduce(Span::to)
+    }
+
+    pub(crate) fn uid(&self) -> usize {
         match self {
             Expr::Literal { uid, .. } => *uid,
             Expr::Unary { uid, .. } => *uid,
@@ -85,20 +316,27 @@ impl Expr {
             Expr::Get { uid, .. } => *uid,
             Expr::This { uid, .. } => *uid,
             Expr::Super { uid, .. } => *uid,
+            Expr::List { uid, .. } => *uid,
+            Expr::Tuple { uid, .. } => *uid,
+            Expr::Yield { uid, .. } => *uid,
+            Expr::Is { uid, .. } => *uid,
+            Expr::Comparison { uid, .. } => *uid,
+            Expr::Sequence { uid, .. } => *uid,
+            Expr::Lambda { uid, .. } => *uid,
+            Expr::Index { uid, .. } => *uid,
+            Expr::SetIndex { uid, .. } => *uid,
+            Expr::Slice { uid, .. } => *uid,
+            Expr::Ternary { uid, .. } => *uid,
+            Expr::If { uid, .. } => *uid,
+            Expr::Block { uid, .. } => *uid,
         }
     }
 }
 
 impl PartialEq for Expr {
     fn eq(&self, other: &Self) -> bool {
-        self.get_uid() == other.get_uid()
+        self.uid() == other.uid()
     }
 }
 
 impl Eq for Expr {}
-
-impl Hash for Expr {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.get_uid().hash(state)
-    }
-}
@@ -1,42 +1,158 @@
-use crate::class::{ClassInstance, ClassInstanceRef};
+use crate::class::ClassInstanceRef;
 use crate::environment::{EnvRef, Environment};
 use crate::interpreter::Interpreter;
-use crate::stmt::Stmt;
+use crate::stmt::{FunctionDecl, FunctionKind};
+use crate::symbol::Symbol;
+use crate::sync::{MaybeSync, Rc};
+use crate::token::Token;
 use crate::value::Value;
-use crate::Exception;
+use crate::{Exception, RuntimeError, RuntimeErrorKind};
 use std::fmt;
 use std::fmt::Formatter;
 
 pub trait Callable {
     fn arity(&self) -> usize;
+    /// Whether extra arguments beyond `arity` are accepted.
+    fn is_variadic(&self) -> bool {
+        false
+    }
     fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, Exception>;
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Why a native function failed.
+#[derive(Debug)]
+pub enum NativeError {
+    /// A plain message, reported as a runtime error at the call site.
+    Message(String),
+    /// An error or thrown value from Lox code the native called back into,
+    /// passed on unchanged.
+    Exception(Exception),
+}
+
+impl NativeError {
+    /// The exception to raise for this failure in a call at `paren`.
+    pub fn at(self, paren: &Token) -> Exception {
+        match self {
+            NativeError::Message(message) => Exception::RuntimeError(RuntimeError {
+                token: paren.clone(),
+                kind: Box::new(RuntimeErrorKind::Message(message)),
+            }),
+            NativeError::Exception(exception) => exception,
+        }
+    }
+}
+
+impl From<String> for NativeError {
+    fn from(message: String) -> Self {
+        NativeError::Message(message)
+    }
+}
+
+impl From<&str> for NativeError {
+    fn from(message: &str) -> Self {
+        NativeError::Message(message.to_string())
+    }
+}
+
+impl From<Exception> for NativeError {
+    fn from(exception: Exception) -> Self {
+        NativeError::Exception(exception)
+    }
+}
+
+pub type NativeResult = Result<Value, NativeError>;
+#[cfg(not(feature = "sync"))]
+pub type NativeCallable = dyn Fn(&mut Interpreter, Vec<Value>) -> NativeResult;
+#[cfg(feature = "sync")]
+pub type NativeCallable = dyn Fn(&mut Interpreter, Vec<Value>) -> NativeResult + Send + Sync;
+
+/// A function implemented in Rust. Being a closure, it can capture state
+/// from the host program.
+#[derive(Clone)]
 pub struct NativeFunction {
     pub arity: usize,
-    pub callable: fn(&mut Interpreter, Vec<Value>) -> Value,
+    /// Whether extra arguments beyond `arity` are accepted.
+    pub variadic: bool,
+    pub callable: Rc<NativeCallable>,
 }
 
-impl Callable for NativeFunction {
-    fn arity(&self) -> usize {
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// Two natives are equal when they share the same closure.
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.callable, &other.callable)
+    }
+}
+
+impl NativeFunction {
+    pub fn new(
+        arity: usize,
+        callable: impl Fn(&mut Interpreter, Vec<Value>) -> NativeResult + MaybeSync + 'static,
+    ) -> Self {
+        NativeFunction {
+            arity,
+            variadic: false,
+            callable: Rc::new(callable),
+        }
+    }
+
+    /// A native that also accepts any number of arguments after the first
+    /// `arity`.
+    pub fn variadic(
+        arity: usize,
+        callable: impl Fn(&mut Interpreter, Vec<Value>) -> NativeResult + MaybeSync + 'static,
+    ) -> Self {
+        NativeFunction {
+            variadic: true,
+            ..NativeFunction::new(arity, callable)
+        }
+    }
+
+    pub fn arity(&self) -> usize {
         self.arity
     }
 
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, Exception> {
-        Ok((self.callable)(interpreter, args))
+    /// Binds a native method to `this`, which is passed ahead of the other
+    /// arguments.
+    pub fn bind(&self, this: Value) -> NativeFunction {
+        let method = self.callable.clone();
+        NativeFunction {
+            callable: Rc::new(move |interpreter, args| {
+                let mut bound = vec![this.clone()];
+                bound.extend(args);
+                method(interpreter, bound)
+            }),
+            ..self.clone()
+        }
+    }
+
+    /// Failure messages are turned into runtime errors pointing at the
+    /// call site.
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        args: Vec<Value>,
+    ) -> Result<Value, Exception> {
+        (self.callable)(interpreter, args).map_err(|error| error.at(paren))
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Function {
-    declaration: Stmt,
+    declaration: Rc<FunctionDecl>,
     closure: EnvRef,
     is_initializer: bool,
 }
 
 impl Function {
-    pub fn new(declaration: Stmt, closure: EnvRef, is_initializer: bool) -> Self {
+    pub fn new(declaration: Rc<FunctionDecl>, closure: EnvRef, is_initializer: bool) -> Self {
+        Environment::capture(&closure);
         Function {
             declaration,
             closure,
@@ -44,59 +160,137 @@ impl Function {
         }
     }
 
+    pub fn is_getter(&self) -> bool {
+        self.declaration.kind == FunctionKind::GETTER
+    }
+
+    pub fn is_setter(&self) -> bool {
+        self.declaration.kind == FunctionKind::SETTER
+    }
+
+    /// Builds the positional argument list for a call that used named
+    /// arguments. Extra positional arguments are kept at the end for a
+    /// rest parameter, or for the arity check to reject.
+    pub fn arrange_arguments(
+        &self,
+        paren: &Token,
+        mut args: Vec<Value>,
+        named: Vec<(Token, Value)>,
+    ) -> Result<Vec<Value>, Exception> {
+        let params = &self.declaration.params[..self.arity()];
+        let extra = args.split_off(args.len().min(params.len()));
+        let mut slots: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+        slots.resize(params.len(), None);
+
+        for (name, value) in named {
+            match params.iter().position(|param| param.lexeme == name.lexeme) {
+                None => {
+                    return Exception::runtime_error(
+                        name.clone(),
+                        format!("Unknown parameter '{}'.", name.lexeme),
+                    )
+                }
+                Some(i) if slots[i].is_some() => {
+                    return Exception::runtime_error(
+                        name.clone(),
+                        format!("Duplicate argument for parameter '{}'.", name.lexeme),
+                    )
+                }
+                Some(i) => slots[i] = Some(value),
+            }
+        }
+
+        let mut arguments = Vec::with_capacity(slots.len() + extra.len());
+        for (param, slot) in params.iter().zip(slots) {
+            match slot {
+                Some(value) => arguments.push(value),
+                None => {
+                    return Exception::runtime_error(
+                        paren.clone(),
+                        format!("Missing argument for parameter '{}'.", param.lexeme),
+                    )
+                }
+            }
+        }
+        arguments.extend(extra);
+        Ok(arguments)
+    }
+
+    pub fn closure(&self) -> &EnvRef {
+        &self.closure
+    }
+
     pub fn bind(&mut self, instance: ClassInstanceRef) -> Function {
         let environment = Environment::new_local(&self.closure);
         environment
             .borrow_mut()
-            .define(String::from("this"), Value::ClassInstance(instance));
-        Function::new(self.declaration.clone(), environment, false)
-    }
-}
-
-impl Callable for Function {
-    fn arity(&self) -> usize {
-        if let Stmt::Function { params, .. } = &self.declaration {
-            return params.len();
-        }
-        panic!("Function was not initialized with a function declaration!");
+            .define(Symbol::from("this"), Value::ClassInstance(instance));
+        Function::new(self.declaration.clone(), environment, self.is_initializer)
     }
 
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, Exception> {
+    /// Runs the body with `args` bound to the parameters.
+    fn execute(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, Exception> {
         let environment = Environment::new_local(&self.closure);
 
-        if let Stmt::Function { params, body, .. } = &self.declaration {
-            for (i, param) in params.iter().enumerate() {
-                environment
-                    .borrow_mut()
-                    .define(param.lexeme.clone(), args.get(i).unwrap().clone());
-            }
-            if let Err(exception) = interpreter.execute_block(body, environment) {
-                return match exception {
-                    Exception::RuntimeError(e) => Err(Exception::RuntimeError(e)),
-                    Exception::Return(value) => {
-                        if self.is_initializer {
-                            return self.closure.borrow().get_at(0, "this");
-                        }
-                        return Ok(value);
+        let FunctionDecl {
+            params,
+            body,
+            variadic,
+            ..
+        } = &*self.declaration;
+        let mut args = args;
+        if *variadic {
+            let rest = args.split_off(params.len() - 1);
+            args.push(Value::from(rest));
+        }
+        for (i, param) in params.iter().enumerate() {
+            environment
+                .borrow_mut()
+                .define(param.lexeme.clone(), args.get(i).unwrap().clone());
+        }
+        if let Err(exception) = interpreter.execute_block(body, environment) {
+            return match exception {
+                Exception::Return(value) => {
+                    if self.is_initializer {
+                        return Ok(self.closure.borrow().get_at(0, 0));
                     }
-                };
-            }
+                    return Ok(value);
+                }
+                exception => Err(exception),
+            };
         }
 
         if self.is_initializer {
-            return self.closure.borrow().get_at(0, "this");
+            return Ok(self.closure.borrow().get_at(0, 0));
         }
 
         Ok(Value::Nil)
     }
 }
 
+impl Callable for Function {
+    fn arity(&self) -> usize {
+        self.declaration.params.len() - usize::from(self.declaration.variadic)
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.declaration.variadic
+    }
+
+    /// Calls made implicitly, like running a getter, have no call site of
+    /// their own, so a stack overflow in one is reported at the function's
+    /// name instead.
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, Exception> {
+        interpreter.check_call_depth(&self.declaration.name)?;
+        interpreter.enter_call();
+        let result = self.execute(interpreter, args);
+        interpreter.exit_call();
+        result
+    }
+}
+
 impl fmt::Display for Function {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let mut string = String::from("unknown");
-        if let Stmt::Function { name, .. } = &self.declaration {
-            string = String::from(name.lexeme.clone());
-        }
-        write!(f, "<fn {}>", string)
+        write!(f, "<fn {}>", self.declaration.name.lexeme)
     }
 }
@@ -0,0 +1,699 @@
+//! Frees reference cycles. Values are reference counted, so objects that
+//! refer to each other, like an instance holding a closure that captured
+//! `this`, are never dropped on their own. Everything that can be part of a
+//! cycle is registered here when it is made, and every so often the
+//! collector looks for groups of those objects that nothing else refers to
+//! and empties them, which lets them be dropped.
+//!
+//! The collector doesn't need to know the roots. For every registered
+//! object it counts the references held by other registered objects. One
+//! with more references than that is also held from somewhere else, such as
+//! a Rust variable or a value that isn't registered, so it is kept along
+//! with everything it refers to. What is left can only be reached through
+//! cycles.
+//!
+//! Environments and instances are made far more often than anything else,
+//! so they let the collector forget them as soon as they are dropped.
+//! Other objects are forgotten at the next collection after they go.
+//!
+//! Objects are registered with the thread that made them, and a collection
+//! only looks at the current thread's. With the `sync` feature, values
+//! shared with another thread mustn't be changed there during a collection.
+
+use crate::class::{Class, ClassInstance, ClassInstanceRef};
+use crate::environment::{EnvRef, Environment};
+use crate::map::{Map, MapRef};
+use crate::symbol::Symbol;
+use crate::sync::{Rc, RefCell, Weak};
+use crate::value::{ListRef, Value};
+use crate::vm::{Closure, ClosureRef, Upvalue, UpvalueRef};
+use std::cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::mem;
+use std::ops::Range;
+
+/// How many new objects start a collection of the young generation.
+const YOUNG_THRESHOLD: usize = 1_000;
+/// The fewest objects there can be before everything is collected.
+const MIN_FULL_THRESHOLD: usize = 10_000;
+
+type StaticsRef = Rc<RefCell<HashMap<Symbol, Value>>>;
+
+thread_local! {
+    static HEAP: cell::RefCell<Heap> = const {
+        cell::RefCell::new(Heap {
+            objects: Vec::new(),
+            free: Vec::new(),
+            young: Vec::new(),
+            full_threshold: MIN_FULL_THRESHOLD,
+        })
+    };
+}
+
+/// The registered objects. Most objects either go away soon after they are
+/// made or stay for a long time, so the ones registered since the last
+/// collection are looked at often and the rest only once there are a lot
+/// more of them.
+struct Heap {
+    /// Every registered object, with gaps where dropped ones were.
+    objects: Vec<Option<Tracked>>,
+    /// The gaps in `objects`.
+    free: Vec<usize>,
+    /// Where the objects registered since the last collection went.
+    young: Vec<usize>,
+    /// How many objects there can be before a full collection. It is twice
+    /// what survived the last one, so the time spent looking at old objects
+    /// again stays in proportion to the number of new ones.
+    full_threshold: usize,
+}
+
+impl Heap {
+    fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    fn insert(&mut self, object: Tracked) -> usize {
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.objects[slot] = Some(object);
+                slot
+            }
+            None => {
+                self.objects.push(Some(object));
+                self.objects.len() - 1
+            }
+        };
+        self.young.push(slot);
+        slot
+    }
+
+    /// Empties `slot`, unless the object in it is still alive.
+    fn remove_dropped(&mut self, slot: usize) {
+        let Some(entry) = self.objects.get_mut(slot) else {
+            return;
+        };
+        if entry.as_ref().is_some_and(|object| !object.is_alive()) {
+            *entry = None;
+            self.free.push(slot);
+        }
+    }
+}
+
+/// A registered object, which the registry doesn't keep alive.
+pub(crate) enum Tracked {
+    Environment(Weak<RefCell<Environment>>),
+    Instance(Weak<RefCell<ClassInstance>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<Map>>),
+    Statics(Weak<RefCell<HashMap<Symbol, Value>>>),
+    Closure(Weak<Closure>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+}
+
+impl Tracked {
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Environment(object) => object.strong_count() > 0,
+            Tracked::Instance(object) => object.strong_count() > 0,
+            Tracked::List(object) => object.strong_count() > 0,
+            Tracked::Map(object) => object.strong_count() > 0,
+            Tracked::Statics(object) => object.strong_count() > 0,
+            Tracked::Closure(object) => object.strong_count() > 0,
+            Tracked::Upvalue(object) => object.strong_count() > 0,
+        }
+    }
+
+    fn upgrade(&self) -> Option<Object> {
+        Some(match self {
+            Tracked::Environment(object) => Object::Environment(object.upgrade()?),
+            Tracked::Instance(object) => Object::Instance(object.upgrade()?),
+            Tracked::List(object) => Object::List(object.upgrade()?),
+            Tracked::Map(object) => Object::Map(object.upgrade()?),
+            Tracked::Statics(object) => Object::Statics(object.upgrade()?),
+            Tracked::Closure(object) => Object::Closure(object.upgrade()?),
+            Tracked::Upvalue(object) => Object::Upvalue(object.upgrade()?),
+        })
+    }
+}
+
+impl From<&EnvRef> for Tracked {
+    fn from(object: &EnvRef) -> Self {
+        Tracked::Environment(Rc::downgrade(object))
+    }
+}
+
+impl From<&ClassInstanceRef> for Tracked {
+    fn from(object: &ClassInstanceRef) -> Self {
+        Tracked::Instance(Rc::downgrade(object))
+    }
+}
+
+impl From<&ListRef> for Tracked {
+    fn from(object: &ListRef) -> Self {
+        Tracked::List(Rc::downgrade(object))
+    }
+}
+
+impl From<&MapRef> for Tracked {
+    fn from(object: &MapRef) -> Self {
+        Tracked::Map(Rc::downgrade(object))
+    }
+}
+
+impl From<&StaticsRef> for Tracked {
+    fn from(object: &StaticsRef) -> Self {
+        Tracked::Statics(Rc::downgrade(object))
+    }
+}
+
+impl From<&ClosureRef> for Tracked {
+    fn from(object: &ClosureRef) -> Self {
+        Tracked::Closure(Rc::downgrade(object))
+    }
+}
+
+impl From<&UpvalueRef> for Tracked {
+    fn from(object: &UpvalueRef) -> Self {
+        Tracked::Upvalue(Rc::downgrade(object))
+    }
+}
+
+/// Registers a newly made object with the collector.
+pub(crate) fn track(object: impl Into<Tracked>) {
+    HEAP.with(|heap| {
+        heap.borrow_mut().insert(object.into());
+    });
+}
+
+/// Registers an object that keeps the returned registration, so it is
+/// forgotten as soon as it is dropped.
+pub(crate) fn register(object: impl Into<Tracked>) -> Registration {
+    HEAP.with(|heap| Registration(heap.borrow_mut().insert(object.into())))
+}
+
+/// Where an object is registered. A copy of an object refers to the same
+/// place, but the original is still alive when the copy is dropped, so it
+/// stays registered.
+#[derive(Debug, Clone)]
+pub(crate) struct Registration(usize);
+
+/// Doesn't affect whether the objects holding it are equal.
+impl PartialEq for Registration {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // During a collection, or if the thread is finishing, the object is
+        // left for the next collection to notice.
+        let _ = HEAP.try_with(|heap| {
+            if let Ok(mut heap) = heap.try_borrow_mut() {
+                heap.remove_dropped(self.0);
+            }
+        });
+    }
+}
+
+/// Whether enough objects have been made since the last collection to be
+/// worth another.
+pub(crate) fn due() -> bool {
+    HEAP.with(|heap| heap.borrow().young.len() >= YOUNG_THRESHOLD)
+}
+
+/// Collects the objects registered since the last collection, or everything
+/// if there are enough more objects than the last full collection left.
+pub(crate) fn collect_due(cycles: Option<&mut Vec<LeakedCycle>>) {
+    let full = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.len() >= heap.full_threshold
+    });
+    collect_generations(full, cycles);
+}
+
+/// Frees the objects that are only reachable through reference cycles and
+/// returns how many there were. Describes each cycle in `cycles`, if given.
+pub(crate) fn collect(cycles: Option<&mut Vec<LeakedCycle>>) -> usize {
+    collect_generations(true, cycles)
+}
+
+/// Objects that nothing but each other referred to, which a collection
+/// found and freed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeakedCycle {
+    /// How many objects there were, counting environments and others that
+    /// programs can't see.
+    pub objects: usize,
+    /// The instances, functions, classes, lists and maps among them.
+    pub names: Vec<String>,
+}
+
+impl fmt::Display for LeakedCycle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let plural = if self.objects == 1 { "" } else { "s" };
+        write!(
+            f,
+            "Leaked {} object{} in a reference cycle",
+            self.objects, plural
+        )?;
+        if !self.names.is_empty() {
+            write!(f, ": {}", self.names.join(", "))?;
+        }
+        write!(f, ".")
+    }
+}
+
+/// A registered object held for the length of a collection.
+enum Object {
+    Environment(EnvRef),
+    Instance(ClassInstanceRef),
+    List(ListRef),
+    Map(MapRef),
+    Statics(StaticsRef),
+    Closure(ClosureRef),
+    Upvalue(UpvalueRef),
+}
+
+fn address<T: ?Sized>(object: &Rc<T>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
+
+impl Object {
+    fn address(&self) -> usize {
+        match self {
+            Object::Environment(object) => address(object),
+            Object::Instance(object) => address(object),
+            Object::List(object) => address(object),
+            Object::Map(object) => address(object),
+            Object::Statics(object) => address(object),
+            Object::Closure(object) => address(object),
+            Object::Upvalue(object) => address(object),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(object) => Rc::strong_count(object),
+            Object::Instance(object) => Rc::strong_count(object),
+            Object::List(object) => Rc::strong_count(object),
+            Object::Map(object) => Rc::strong_count(object),
+            Object::Statics(object) => Rc::strong_count(object),
+            Object::Closure(object) => Rc::strong_count(object),
+            Object::Upvalue(object) => Rc::strong_count(object),
+        }
+    }
+
+    /// Adds the addresses of the objects this one holds a reference to,
+    /// unless it is being changed and can't be looked at.
+    fn references(&self, addresses: &mut Vec<usize>) -> bool {
+        self.try_references(&mut References(addresses)).is_some()
+    }
+
+    fn try_references(&self, references: &mut References) -> Option<()> {
+        match self {
+            Object::Environment(environment) => {
+                let environment = environment.try_borrow().ok()?;
+                for (_, value) in environment.values() {
+                    references.value(value);
+                }
+                for value in environment.slots() {
+                    references.value(value);
+                }
+                if let Some(enclosing) = &environment.enclosing {
+                    references.push(enclosing);
+                }
+            }
+            Object::Instance(instance) => {
+                let instance = instance.try_borrow().ok()?;
+                for (_, value) in instance.fields() {
+                    references.value(value);
+                }
+                references.class(instance.class());
+            }
+            Object::List(list) => {
+                for value in list.try_borrow().ok()?.iter() {
+                    references.value(value);
+                }
+            }
+            Object::Map(map) => {
+                for (key, value) in map.try_borrow().ok()?.entries() {
+                    references.value(key);
+                    references.value(value);
+                }
+            }
+            Object::Statics(statics) => {
+                for value in statics.try_borrow().ok()?.values() {
+                    references.value(value);
+                }
+            }
+            Object::Closure(closure) => {
+                for upvalue in closure.upvalues() {
+                    references.push(upvalue);
+                }
+                if let Some(receiver) = closure.receiver() {
+                    references.value(receiver);
+                }
+            }
+            Object::Upvalue(upvalue) => {
+                if let Some(closure) = upvalue.try_borrow().ok()?.closure() {
+                    references.push(closure);
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// What a program would call the object, if it can see it.
+    fn name(&self) -> Option<String> {
+        match self {
+            Object::Instance(instance) => Some(match instance.try_borrow() {
+                Ok(instance) => instance.to_string(),
+                Err(_) => "instance".to_string(),
+            }),
+            Object::List(_) => Some("list".to_string()),
+            Object::Map(_) => Some("map".to_string()),
+            Object::Closure(closure) => Some(closure.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Calls `visit` with every value the object holds, unless it is being
+    /// changed.
+    fn values(&self, visit: &mut dyn FnMut(&Value)) {
+        match self {
+            Object::Environment(environment) => {
+                if let Ok(environment) = environment.try_borrow() {
+                    environment.values().for_each(|(_, value)| visit(value));
+                    environment.slots().iter().for_each(visit);
+                }
+            }
+            Object::Instance(instance) => {
+                if let Ok(instance) = instance.try_borrow() {
+                    instance.fields().for_each(|(_, value)| visit(value));
+                }
+            }
+            Object::List(list) => {
+                if let Ok(list) = list.try_borrow() {
+                    list.iter().for_each(visit);
+                }
+            }
+            Object::Map(map) => {
+                if let Ok(map) = map.try_borrow() {
+                    for (key, value) in map.entries() {
+                        visit(key);
+                        visit(value);
+                    }
+                }
+            }
+            Object::Statics(statics) => {
+                if let Ok(statics) = statics.try_borrow() {
+                    statics.values().for_each(visit);
+                }
+            }
+            Object::Closure(_) | Object::Upvalue(_) => {}
+        }
+    }
+
+    /// Takes out everything the object holds, breaking the cycles through
+    /// it. Closures can't be changed, but their upvalues are emptied.
+    fn clear(&self, trash: &mut Vec<Value>) {
+        match self {
+            Object::Environment(environment) => {
+                if let Ok(mut environment) = environment.try_borrow_mut() {
+                    trash.extend(environment.take_values());
+                }
+            }
+            Object::Instance(instance) => {
+                if let Ok(mut instance) = instance.try_borrow_mut() {
+                    trash.extend(instance.take_fields());
+                }
+            }
+            Object::List(list) => {
+                if let Ok(mut list) = list.try_borrow_mut() {
+                    trash.append(&mut list);
+                }
+            }
+            Object::Map(map) => {
+                if let Ok(mut map) = map.try_borrow_mut() {
+                    trash.extend(mem::take(&mut *map).into_values());
+                }
+            }
+            Object::Statics(statics) => {
+                if let Ok(mut statics) = statics.try_borrow_mut() {
+                    trash.extend(statics.drain().map(|(_, value)| value));
+                }
+            }
+            Object::Closure(_) => {}
+            Object::Upvalue(upvalue) => {
+                if let Ok(mut upvalue) = upvalue.try_borrow_mut() {
+                    trash.extend(upvalue.take());
+                }
+            }
+        }
+    }
+}
+
+/// Collects the references an object holds. Every one of them is a strong
+/// reference of its own, so a value held in several places is counted once
+/// for each.
+struct References<'a>(&'a mut Vec<usize>);
+
+impl References<'_> {
+    fn push<T: ?Sized>(&mut self, object: &Rc<T>) {
+        self.0.push(address(object));
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::List(list) => self.push(list),
+            Value::Map(map) => self.push(map),
+            Value::ClassInstance(instance) => self.push(instance),
+            Value::Closure(closure) => self.push(closure),
+            Value::Function(function) => self.push(function.closure()),
+            Value::Class(class) => self.class(class),
+            // Tuples, modules and coroutines aren't registered, so the
+            // objects they hold are treated as referred to from outside.
+            _ => {}
+        }
+    }
+
+    /// A class value holds its own copies of its methods, constants and
+    /// superclass, and a reference to the statics shared between copies.
+    fn class(&mut self, class: &Class) {
+        self.push(class.closure());
+        self.push(class.statics());
+        for function in class.functions() {
+            self.push(function.closure());
+        }
+        for method in class.compiled_methods() {
+            self.push(method);
+        }
+        for value in class.constants() {
+            self.value(value);
+        }
+        if let Some(super_class) = class.super_class() {
+            self.class(super_class);
+        }
+    }
+}
+
+/// Hashes the addresses of objects, which need no more than spreading out.
+#[derive(Default)]
+struct AddressHasher(u64);
+
+impl Hasher for AddressHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("only addresses are hashed")
+    }
+
+    fn write_usize(&mut self, address: usize) {
+        self.0 = (address as u64)
+            .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            .rotate_left(32);
+    }
+}
+
+type AddressMap = HashMap<usize, usize, BuildHasherDefault<AddressHasher>>;
+
+/// The registered objects a collection looked at and the references
+/// between them.
+struct Graph<'a> {
+    objects: &'a [Object],
+    /// Where each object is in `objects`, by address.
+    index: &'a AddressMap,
+    targets: &'a [usize],
+    references: &'a [Option<Range<usize>>],
+}
+
+impl Graph<'_> {
+    /// Adds a description of each group of objects that aren't `kept` and
+    /// refer to each other to `cycles`.
+    fn describe_cycles(&self, kept: &[bool], cycles: &mut Vec<LeakedCycle>) {
+        // Joins each object that is going with the others it refers to, so
+        // every group ends up under one of its members.
+        let mut group: Vec<usize> = (0..self.objects.len()).collect();
+        for (i, range) in self.references.iter().enumerate() {
+            let Some(range) = range.clone().filter(|_| !kept[i]) else {
+                continue;
+            };
+            for &target in self.targets[range].iter().filter(|&&target| !kept[target]) {
+                let (from, to) = (find(&mut group, i), find(&mut group, target));
+                group[from] = to;
+            }
+        }
+
+        let mut found = HashMap::new();
+        for i in (0..self.objects.len()).filter(|&i| !kept[i]) {
+            let position = *found.entry(find(&mut group, i)).or_insert_with(|| {
+                cycles.push(LeakedCycle {
+                    objects: 0,
+                    names: Vec::new(),
+                });
+                cycles.len() - 1
+            });
+            let cycle = &mut cycles[position];
+            cycle.objects += 1;
+            let object = &self.objects[i];
+            let mut names: Vec<String> = object.name().into_iter().collect();
+            // Functions and classes aren't registered themselves, but are
+            // part of the cycle if the environment they captured is.
+            object.values(&mut |value| {
+                let (closure, name) = match value {
+                    Value::Function(function) => (function.closure(), function.to_string()),
+                    Value::Class(class) => (class.closure(), format!("class {}", class)),
+                    _ => return,
+                };
+                if self.index.get(&address(closure)).is_some_and(|&j| !kept[j]) {
+                    names.push(name);
+                }
+            });
+            for name in names {
+                if !cycle.names.contains(&name) {
+                    cycle.names.push(name);
+                }
+            }
+        }
+    }
+}
+
+/// The object a group is kept under, halving the path to it on the way.
+fn find(group: &mut [usize], mut i: usize) -> usize {
+    while group[i] != i {
+        group[i] = group[group[i]];
+        i = group[i];
+    }
+    i
+}
+
+/// Collects the objects registered since the last collection, and the rest
+/// too if `full`. References from objects that aren't looked at count as
+/// coming from outside, so what older objects refer to is kept until a
+/// full collection.
+fn collect_generations(full: bool, cycles: Option<&mut Vec<LeakedCycle>>) -> usize {
+    let mut objects = Vec::new();
+    let mut index = AddressMap::default();
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        let slots = mem::take(&mut heap.young);
+        let slots = if full {
+            (0..heap.objects.len()).collect()
+        } else {
+            slots
+        };
+        for slot in slots {
+            let Some(tracked) = &heap.objects[slot] else {
+                continue;
+            };
+            let Some(object) = tracked.upgrade() else {
+                heap.objects[slot] = None;
+                heap.free.push(slot);
+                continue;
+            };
+            // A slot can be young twice if it was emptied and reused.
+            index.entry(object.address()).or_insert_with(|| {
+                objects.push(object);
+                objects.len() - 1
+            });
+        }
+    });
+
+    // References from outside the registered objects, less the one held
+    // by `objects`.
+    let mut outside: Vec<usize> = objects
+        .iter()
+        .map(|object| object.strong_count() - 1)
+        .collect();
+    // The references of every object that could be looked at, one after
+    // the other, as indices into `objects`.
+    let mut targets = Vec::new();
+    let mut references: Vec<Option<Range<usize>>> = Vec::with_capacity(objects.len());
+    let mut addresses = Vec::new();
+    for object in &objects {
+        addresses.clear();
+        if !object.references(&mut addresses) {
+            references.push(None);
+            continue;
+        }
+        let start = targets.len();
+        targets.extend(
+            addresses
+                .iter()
+                .filter_map(|address| index.get(address).copied()),
+        );
+        references.push(Some(start..targets.len()));
+    }
+    for &target in &targets {
+        outside[target] -= 1;
+    }
+
+    // An object that couldn't be looked at is kept, and so is everything
+    // it refers to, since none of its references were subtracted.
+    let mut kept = vec![false; objects.len()];
+    let mut pending: Vec<usize> = (0..objects.len())
+        .filter(|&i| outside[i] > 0 || references[i].is_none())
+        .collect();
+    while let Some(i) = pending.pop() {
+        if mem::replace(&mut kept[i], true) {
+            continue;
+        }
+        if let Some(range) = references[i].clone() {
+            pending.extend(targets[range].iter().filter(|&&target| !kept[target]));
+        }
+    }
+
+    if let Some(cycles) = cycles {
+        let graph = Graph {
+            objects: &objects,
+            index: &index,
+            targets: &targets,
+            references: &references,
+        };
+        graph.describe_cycles(&kept, cycles);
+    }
+
+    let mut trash = Vec::new();
+    let mut freed = 0;
+    for (object, kept) in objects.iter().zip(kept) {
+        if !kept {
+            object.clear(&mut trash);
+            freed += 1;
+        }
+    }
+    drop(objects);
+    drop(trash);
+    if full {
+        HEAP.with(|heap| {
+            let mut heap = heap.borrow_mut();
+            heap.full_threshold = MIN_FULL_THRESHOLD.max(2 * heap.len());
+        });
+    }
+    freed
+}
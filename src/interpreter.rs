@@ -1,75 +1,722 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::process;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::mem;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::class::Class;
+use crate::bigint::{BigInt, I64_LIMIT};
+use crate::builder::Capabilities;
+use crate::class::{Class, ClassInstanceRef, NativeClass};
+use crate::clock::{self, Instant};
+use crate::coroutine::{self, Coroutine};
+use crate::dead_code::DeadCode;
+use crate::diagnostics::{Diagnostic, ErrorCode, ErrorReporter};
 use crate::environment::{EnvRef, Environment};
 use crate::expr::Expr;
-use crate::function::{Callable, Function, NativeFunction};
-use crate::stmt::Stmt;
+use crate::function::{Callable, Function, NativeFunction, NativeResult};
+use crate::gc::{self, LeakedCycle};
+use crate::memory;
+use crate::module::{Module, ModuleRef};
+use crate::natives;
+use crate::parser::{AstIdGen, Parser};
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::stack;
+use crate::stmt::{FunctionDecl, Stmt};
+use crate::string::LoxString;
+use crate::symbol::Symbol;
+use crate::sync::{MaybeSync, Rc, RefCell};
 use crate::token::{Literal, Token, TokenType};
-use crate::value::Value;
-use crate::{expr, stmt, Exception, RuntimeError};
+use crate::value::{format_list, format_map, format_tuple, ListRef, Range, Value};
+use crate::vm;
+use crate::{expr, stmt, Exception, LoxError, RuntimeError, RuntimeErrorKind};
 
 type Result<T> = std::result::Result<T, Exception>;
 
+/// How deeply calls to Lox functions can nest unless the options say
+/// otherwise.
+const MAX_CALL_DEPTH: usize = 1_000;
+
+/// Settings that change how a program is interpreted.
+#[derive(Debug, Clone, Default)]
+pub struct InterpreterOptions {
+    /// Follow the reference Lox semantics instead of rlox's extensions
+    /// where they differ, so `"a" + 1` is an error rather than `"a1"`.
+    pub strict: bool,
+    /// Let scripts run shell commands with `os.exec`.
+    pub allow_exec: bool,
+    /// Stop a run with `LoxError::Timeout` after it executes this many
+    /// statements.
+    pub max_steps: Option<u64>,
+    /// Stop a run with `LoxError::Timeout` once it has taken this long.
+    pub timeout: Option<Duration>,
+    /// Stop a run with `LoxError::OutOfMemory` once the values it can
+    /// reach take up roughly this many bytes. Strings, lists, tuples, maps
+    /// and fields are counted as they are made, so a run stops before
+    /// building a value that would go over.
+    pub max_memory: Option<usize>,
+    /// Raise a "Stack overflow." runtime error once calls nest this deep.
+    /// When unset, the tree walker, which takes up a good deal of the Rust
+    /// stack for each call, allows 1,000 and the VM 10,000. The tree walker
+    /// also stops short of running out of the stack programs run on,
+    /// whatever this is set to.
+    pub max_call_depth: Option<usize>,
+    /// Bytes of stack each coroutine gets for its calls, 16 MB when unset.
+    /// Calls inside a coroutine overflow once it is nearly full, and the
+    /// operating system may refuse to map many large ones.
+    pub coroutine_stack_size: Option<usize>,
+    /// How programs are executed.
+    pub backend: Backend,
+    /// Have `run_file` keep the compiled program in a `.loxb` file next to
+    /// the script, and load it from there while the script is unchanged.
+    /// Implies the VM backend.
+    pub cache: bool,
+    /// Describe the reference cycles the collector frees, for
+    /// `Interpreter::take_leaked_cycles`. `run_file` prints them when the
+    /// program ends.
+    pub report_cycles: bool,
+}
+
+/// How an interpreter executes programs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Walk the syntax tree.
+    #[default]
+    TreeWalk,
+    /// Compile to bytecode for a stack machine, which runs loops and calls
+    /// much faster. Programs it can't compile yet, such as ones with lists,
+    /// are walked instead with an `UnsupportedByVm` warning, and so are
+    /// runs with a statement hook.
+    Vm,
+}
+
 pub struct Interpreter {
+    options: InterpreterOptions,
     environment: EnvRef,
     pub globals: EnvRef,
-    locals: HashMap<Expr, usize>,
+    /// Where the resolver found each local variable, by the id of the
+    /// expression using it: how many scopes out, and its slot in that scope.
+    locals: HashMap<usize, (usize, usize)>,
+    native_modules: HashMap<String, ModuleRef>,
+    /// Imported files by canonical path, so each one only runs once.
+    file_modules: HashMap<PathBuf, ModuleRef>,
+    /// Files currently being imported, outermost first.
+    loading: Vec<PathBuf>,
+    /// Directory that relative import paths are resolved against.
+    script_dir: PathBuf,
+    /// Arguments given after the script path on the command line.
+    script_args: Vec<String>,
+    /// Compile errors in the main script and in imported files.
+    reporter: ErrorReporter,
+    /// Expression ids for everything this interpreter parses, which keeps
+    /// the keys of `locals` distinct.
+    ids: AstIdGen,
+    /// Where `readLine()` and `readAll()` read from.
+    input: Input,
+    /// Where `print` and `write()` write to.
+    output: Output,
+    /// Statements executed in the current run.
+    steps: u64,
+    /// When the current run times out.
+    deadline: Option<Instant>,
+    /// Bytes the program's values took up when last measured, for
+    /// `max_memory`.
+    memory_measured: usize,
+    /// Bytes allocated since then, which may have been freed again.
+    memory_allocated: usize,
+    /// Environments of the blocks and calls being executed, innermost
+    /// last, for measuring memory use.
+    frames: Vec<EnvRef>,
+    /// Calls to Lox functions currently being executed.
+    call_depth: usize,
+    /// Called before each statement is executed.
+    statement_hook: Option<Box<StatementHook>>,
+    /// The line of the last statement passed to the hook that had one.
+    line: usize,
+    /// Which natives with outside effects this interpreter was built with.
+    capabilities: Capabilities,
+    /// Values of the bytecode functions being run.
+    stack: vm::Stack,
+    /// Cycles the collector has freed, with the `report_cycles` option.
+    leaked_cycles: Vec<LeakedCycle>,
+}
+
+/// A callback run before each statement with the statement and its line.
+/// Returning `ControlFlow::Break` stops the run with
+/// `LoxError::Interrupted`.
+#[cfg(not(feature = "sync"))]
+pub type StatementHook = dyn FnMut(&Stmt, usize) -> ControlFlow<()>;
+#[cfg(feature = "sync")]
+pub type StatementHook = dyn FnMut(&Stmt, usize) -> ControlFlow<()> + Send + Sync;
+
+/// A source of input lines, shared between the REPL and the interpreters
+/// it runs so both read from the same stream.
+#[cfg(not(feature = "sync"))]
+pub type Input = Rc<RefCell<dyn BufRead>>;
+#[cfg(feature = "sync")]
+pub type Input = Rc<RefCell<dyn BufRead + Send + Sync>>;
+
+/// Input read from the process's stdin.
+pub fn stdin_input() -> Input {
+    Rc::new(RefCell::new(BufReader::new(io::stdin())))
+}
+
+/// Where a program's output goes, so a host can capture it instead of
+/// letting it reach stdout.
+#[cfg(not(feature = "sync"))]
+pub type Output = Rc<RefCell<dyn Write>>;
+#[cfg(feature = "sync")]
+pub type Output = Rc<RefCell<dyn Write + Send + Sync>>;
+
+/// Output written to the process's stdout.
+pub fn stdout_output() -> Output {
+    Rc::new(RefCell::new(io::stdout()))
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Environment::new();
-        globals.borrow_mut().define(
-            "clock".to_string(),
-            Value::NativeFunction(NativeFunction {
-                arity: 0,
-                callable: |_, _| {
-                    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-                    Value::Number(timestamp.as_millis() as f64)
-                },
-            }),
-        );
+        Interpreter::with_options(InterpreterOptions::default())
+    }
 
-        Interpreter {
+    pub fn with_options(options: InterpreterOptions) -> Self {
+        Interpreter::with_capabilities(options, Capabilities::all())
+    }
+
+    pub(crate) fn with_capabilities(
+        options: InterpreterOptions,
+        capabilities: Capabilities,
+    ) -> Self {
+        let globals = Environment::new();
+        let mut interpreter = Interpreter {
+            options,
             environment: globals.clone(),
             globals,
             locals: HashMap::new(),
+            native_modules: HashMap::new(),
+            file_modules: HashMap::new(),
+            loading: Vec::new(),
+            script_dir: PathBuf::from("."),
+            script_args: Vec::new(),
+            reporter: ErrorReporter::new(),
+            ids: AstIdGen::new(),
+            input: stdin_input(),
+            output: stdout_output(),
+            steps: 0,
+            deadline: None,
+            memory_measured: 0,
+            memory_allocated: 0,
+            frames: Vec::new(),
+            call_depth: 0,
+            statement_hook: None,
+            line: 1,
+            capabilities: Capabilities::all(),
+            stack: vm::Stack::default(),
+            leaked_cycles: Vec::new(),
+        };
+
+        interpreter.define_native("clock", 0, |_, _| {
+            Ok(Value::Float(clock::since_epoch().as_secs_f64()))
+        });
+
+        interpreter.define_native("clockNanos", 0, |_, _| {
+            Ok(Value::Int(clock::since_epoch().as_nanos() as i64))
+        });
+
+        interpreter.define_native("coroutine", 1, |_, args| match &args[0] {
+            Value::Function(function) => {
+                Ok(Value::Coroutine(Rc::new(Coroutine::new(function.clone()))))
+            }
+            other => Err(format!("Argument 1 must be a function, got {}.", other).into()),
+        });
+
+        interpreter.define_native("type", 1, |_, args| {
+            let name = match &args[0] {
+                Value::ClassInstance(instance) => instance.borrow().class_name().to_string(),
+                other => other.type_name().to_string(),
+            };
+            Ok(Value::String(name.into()))
+        });
+
+        interpreter.define_native("readLine", 0, |interpreter, _| {
+            let mut line = String::new();
+            match interpreter.input().borrow_mut().read_line(&mut line) {
+                Ok(0) => Ok(Value::Nil),
+                Ok(_) => {
+                    let line = line.strip_suffix('\n').unwrap_or(&line);
+                    let line = line.strip_suffix('\r').unwrap_or(line);
+                    Ok(Value::String(line.to_string().into()))
+                }
+                Err(err) => Err(format!("Could not read input: {}.", err).into()),
+            }
+        });
+
+        interpreter.define_native("readAll", 0, |interpreter, _| {
+            let mut input = String::new();
+            interpreter
+                .input()
+                .borrow_mut()
+                .read_to_string(&mut input)
+                .map(|_| Value::String(input.into()))
+                .map_err(|err| format!("Could not read input: {}.", err).into())
+        });
+
+        interpreter.define_native("write", 1, natives::write);
+        interpreter.define_native("eprint", 1, natives::eprint);
+
+        interpreter.define_native("args", 0, |interpreter, _| {
+            let args = interpreter
+                .script_args()
+                .iter()
+                .map(|arg| Value::String(arg.clone().into()))
+                .collect::<Vec<_>>();
+            Ok(Value::from(args))
+        });
+
+        interpreter.define_native("error", 1, natives::error);
+        interpreter.globals.borrow_mut().define(
+            Symbol::from("format"),
+            Value::NativeFunction(NativeFunction::variadic(1, natives::format)),
+        );
+
+        for module in natives::builtin_modules(&capabilities) {
+            interpreter.register_module(module);
         }
+        interpreter.capabilities = capabilities;
+        interpreter
+    }
+
+    /// Defines a global native function taking exactly `arity` arguments.
+    /// Errors it returns are reported as runtime errors at the call site.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        callable: impl Fn(&mut Interpreter, Vec<Value>) -> NativeResult + MaybeSync + 'static,
+    ) {
+        self.globals.borrow_mut().define(
+            Symbol::from(name),
+            Value::NativeFunction(NativeFunction::new(arity, callable)),
+        );
+    }
+
+    /// Reads a global variable, e.g. a result left behind by a script.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().lookup(name).cloned()
+    }
+
+    /// Defines or overwrites a global variable, e.g. to pass configuration
+    /// in before running a script.
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        self.globals
+            .borrow_mut()
+            .define(Symbol::from(name), value.into());
+    }
+
+    /// Defines a global class backed by the Rust type `T`.
+    pub fn define_class<T: NativeClass>(&mut self) {
+        self.globals
+            .borrow_mut()
+            .define(Symbol::from(T::NAME), Value::Class(Class::native::<T>()));
+    }
+
+    /// Makes `module` available to `import "<name>";` statements. Registering a
+    /// module under an existing name replaces it.
+    pub fn register_module(&mut self, module: Module) {
+        self.native_modules
+            .insert(module.name().to_string(), ModuleRef::new(module));
+    }
+
+    /// Records the script being run, so its imports are resolved relative to
+    /// it and importing it back is reported as a cycle.
+    pub fn set_script_path(&mut self, script: &Path) {
+        self.script_dir = script.parent().map(Path::to_path_buf).unwrap_or_default();
+        if let Ok(script) = fs::canonicalize(script) {
+            self.loading.push(script);
+        }
+    }
+
+    pub fn set_input(&mut self, input: Input) {
+        self.input = input;
+    }
+
+    pub fn input(&self) -> Input {
+        self.input.clone()
+    }
+
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn output(&self) -> Output {
+        self.output.clone()
+    }
+
+    pub fn reporter(&self) -> &ErrorReporter {
+        &self.reporter
+    }
+
+    /// Removes and returns the warnings about the programs and files this
+    /// interpreter has compiled, such as code that can never run.
+    pub fn take_warnings(&self) -> Vec<Diagnostic> {
+        self.reporter.take_warnings()
+    }
+
+    pub fn options(&self) -> &InterpreterOptions {
+        &self.options
     }
 
-    pub fn interpret(&mut self, stmts: &Vec<Stmt>) {
+    pub fn set_script_args(&mut self, args: Vec<String>) {
+        self.script_args = args;
+    }
+
+    pub fn script_args(&self) -> &[String] {
+        &self.script_args
+    }
+
+    /// Compiles and runs `source` in this interpreter's global scope.
+    pub fn run(&mut self, source: String) -> std::result::Result<(), LoxError> {
+        stack::run(|| {
+            let stmts = self.check(source)?;
+            self.interpret(&stmts)
+        })
+    }
+
+    /// Like `run` on the VM backend, but loads the compiled program from the
+    /// `.loxb` file at `cache` when it was compiled from the same source.
+    /// Otherwise the program is compiled and saved there for next time.
+    pub fn run_cached(
+        &mut self,
+        source: String,
+        cache: &Path,
+    ) -> std::result::Result<(), LoxError> {
+        stack::run(|| {
+            if self.statement_hook.is_some() {
+                return self.run(source);
+            }
+            let hash = vm::source_hash(&source);
+            let cached = fs::read(cache)
+                .ok()
+                .and_then(|bytes| vm::decode(&bytes, Some(hash)));
+            let program = match cached {
+                Some(program) => program,
+                None => {
+                    let stmts = self.check(source)?;
+                    match vm::compile(&stmts) {
+                        Ok(program) => {
+                            // Failing to save only costs the next run its head
+                            // start.
+                            let _ = fs::write(cache, vm::encode(&program, hash));
+                            program
+                        }
+                        Err(unsupported) => {
+                            self.report_unsupported(&unsupported);
+                            self.start_run();
+                            return self.walk(&stmts);
+                        }
+                    }
+                }
+            };
+            self.start_run();
+            vm::run(self, program).map_err(Interpreter::uncaught)
+        })
+    }
+
+    /// Runs a program read from a `.loxb` file, whatever source it was
+    /// compiled from.
+    pub fn run_bytecode(&mut self, bytes: &[u8]) -> std::result::Result<(), LoxError> {
+        stack::run(|| {
+            let program = vm::decode(bytes, None).ok_or_else(|| {
+                LoxError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a program compiled by this version of rlox",
+                ))
+            })?;
+            self.start_run();
+            vm::run(self, program).map_err(Interpreter::uncaught)
+        })
+    }
+
+    /// Scans, parses and resolves `source`.
+    pub(crate) fn check(&mut self, source: String) -> std::result::Result<Vec<Stmt>, LoxError> {
+        let mut tokens: Vec<Token> = Vec::new();
+        Scanner::new(source, &mut tokens, &self.reporter).scan_tokens();
+        if self.reporter.had_error() {
+            return Err(LoxError::Compile(self.reporter.take()));
+        }
+
+        let stmts = Parser::new(&tokens, &self.reporter, &self.ids).parse();
+        Resolver::new(self).resolve_block(&stmts);
+        if self.reporter.had_error() {
+            return Err(LoxError::Compile(self.reporter.take()));
+        }
+        DeadCode::new(&tokens, &self.reporter).check_block(&stmts);
+        Ok(stmts)
+    }
+
+    /// Evaluates `source` as a single expression, like `price * 2`, and
+    /// returns its value. It sees the globals left by earlier runs.
+    pub fn eval(&mut self, source: &str) -> std::result::Result<Value, LoxError> {
+        stack::run(|| {
+            let mut tokens: Vec<Token> = Vec::new();
+            Scanner::new(source.to_string(), &mut tokens, &self.reporter).scan_tokens();
+            if self.reporter.had_error() {
+                return Err(LoxError::Compile(self.reporter.take()));
+            }
+
+            let expr = Parser::new(&tokens, &self.reporter, &self.ids).parse_expression();
+            if let Some(expr) = &expr {
+                Resolver::new(self).resolve_expr(expr);
+            }
+            match expr {
+                Some(expr) if !self.reporter.had_error() => {
+                    self.start_run();
+                    self.evaluate(&expr).map_err(Interpreter::uncaught)
+                }
+                _ => Err(LoxError::Compile(self.reporter.take())),
+            }
+        })
+    }
+
+    pub fn interpret(&mut self, stmts: &[Stmt]) -> std::result::Result<(), LoxError> {
+        stack::run(|| {
+            self.start_run();
+            if self.options.backend == Backend::Vm && self.statement_hook.is_none() {
+                match vm::compile(stmts) {
+                    Ok(program) => return vm::run(self, program).map_err(Interpreter::uncaught),
+                    Err(unsupported) => self.report_unsupported(&unsupported),
+                }
+            }
+            self.walk(stmts)
+        })
+    }
+
+    /// Runs `stmts` on the tree-walker.
+    fn walk(&mut self, stmts: &[Stmt]) -> std::result::Result<(), LoxError> {
         for stmt in stmts {
             match self.execute(stmt) {
                 Ok(_) => {}
-                Err(e) => match e {
-                    Exception::RuntimeError(e) => {
-                        e.error();
-                        process::exit(70);
-                    }
-                    _ => {}
-                },
+                // The resolver rejects `return` and `continue` outside of
+                // functions and loops.
+                Err(Exception::Return(_) | Exception::Continue) => {}
+                Err(exception) => return Err(Interpreter::uncaught(exception)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Warns that the VM was asked for but the program has to be walked.
+    fn report_unsupported(&self, unsupported: &vm::Unsupported) {
+        self.reporter.warning(
+            ErrorCode::UnsupportedByVm,
+            unsupported.line,
+            unsupported.span,
+            &unsupported.location,
+            &format!(
+                "The VM can't compile {} yet, so the program runs on the tree-walker.",
+                unsupported.construct
+            ),
+        );
+    }
+
+    /// Resets the step count, deadline and memory use for a new run.
+    fn start_run(&mut self) {
+        self.steps = 0;
+        self.deadline = self.options.timeout.map(|timeout| Instant::now() + timeout);
+        self.memory_measured = 0;
+        self.memory_allocated = 0;
+    }
+
+    /// The error for an exception that nothing in the program caught.
+    fn uncaught(exception: Exception) -> LoxError {
+        match exception {
+            Exception::Throw(value, keyword) => LoxError::Runtime(RuntimeError {
+                token: keyword,
+                kind: Box::new(RuntimeErrorKind::UncaughtException(value)),
+            }),
+            Exception::RuntimeError(error) => LoxError::Runtime(error),
+            Exception::Timeout => LoxError::Timeout,
+            Exception::OutOfMemory => LoxError::OutOfMemory,
+            Exception::Interrupted => LoxError::Interrupted,
+            Exception::Return(_) | Exception::Continue => {
+                unreachable!("the resolver keeps return and continue inside functions and loops")
             }
         }
     }
 
     fn execute(&mut self, stmt: &Stmt) -> Result<()> {
+        self.count_step()?;
+        if let Some(hook) = &mut self.statement_hook {
+            if let Some(line) = stmt.line() {
+                self.line = line;
+            }
+            if hook(stmt, self.line).is_break() {
+                return Err(Exception::Interrupted);
+            }
+        }
         stmt::Visitor::visit_stmt(self, stmt)
     }
 
-    pub fn resolve(&mut self, expr: &Expr, depth: usize) {
-        self.locals.insert(expr.clone(), depth);
+    /// Counts a statement against the run's limits.
+    pub(crate) fn count_step(&mut self) -> Result<()> {
+        self.steps += 1;
+        if self.options.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(Exception::Timeout);
+        }
+        // Reading the clock and measuring memory cost more than most
+        // statements, so they are only checked every so often.
+        if !self.steps.is_multiple_of(1024) {
+            return Ok(());
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(Exception::Timeout);
+        }
+        if gc::due() {
+            gc::collect_due(self.cycles_to_report());
+        }
+        if let Some(max_memory) = self.options.max_memory {
+            self.measure_memory();
+            if self.memory_measured > max_memory {
+                return Err(Exception::OutOfMemory);
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts `bytes` the program is about to allocate against
+    /// `max_memory`, failing instead if they would take it over the limit.
+    pub(crate) fn allocate(&mut self, bytes: usize) -> Result<()> {
+        let Some(max_memory) = self.options.max_memory else {
+            return Ok(());
+        };
+        let total = self.memory_measured + self.memory_allocated;
+        if total.saturating_add(bytes) > max_memory {
+            // Some of what was allocated since the last measurement may
+            // have been freed, so only fail on a fresh one.
+            self.measure_memory();
+            if self.memory_measured.saturating_add(bytes) > max_memory {
+                return Err(Exception::OutOfMemory);
+            }
+        }
+        self.memory_allocated += bytes;
+        Ok(())
+    }
+
+    fn measure_memory(&mut self) {
+        self.memory_measured = self.memory_used();
+        self.memory_allocated = 0;
+    }
+
+    /// Calls `hook` before every statement from now on, in place of any
+    /// earlier hook. An empty block, which has nothing inside to tell its
+    /// line by, is given the line of the statement before.
+    pub fn set_statement_hook(
+        &mut self,
+        hook: impl FnMut(&Stmt, usize) -> ControlFlow<()> + MaybeSync + 'static,
+    ) {
+        self.statement_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_statement_hook(&mut self) {
+        self.statement_hook = None;
+    }
+
+    /// Frees objects that are only kept alive by referring to each other,
+    /// like an instance holding a closure that captured `this`, and returns
+    /// how many there were. This also happens on its own as programs run.
+    pub fn collect_garbage(&mut self) -> usize {
+        gc::collect(self.cycles_to_report())
+    }
+
+    /// With the `report_cycles` option, every reference cycle freed so far,
+    /// after collecting any that are left.
+    pub fn take_leaked_cycles(&mut self) -> Vec<LeakedCycle> {
+        self.collect_garbage();
+        mem::take(&mut self.leaked_cycles)
+    }
+
+    fn cycles_to_report(&mut self) -> Option<&mut Vec<LeakedCycle>> {
+        self.options
+            .report_cycles
+            .then_some(&mut self.leaked_cycles)
+    }
+
+    /// Roughly how many bytes the program's values take up.
+    pub fn memory_used(&self) -> usize {
+        let mut environments = vec![self.globals.clone(), self.environment.clone()];
+        environments.extend(self.frames.iter().cloned());
+        let mut values: Vec<Value> = self
+            .file_modules
+            .values()
+            .cloned()
+            .map(Value::Module)
+            .collect();
+        values.extend(self.stack.values());
+        memory::reachable_size(environments, values)
+    }
+
+    /// Fails with a stack overflow at `token` if one more call would nest
+    /// deeper than the options allow.
+    pub(crate) fn check_call_depth(&self, token: &Token) -> Result<()> {
+        let max = self.options.max_call_depth.unwrap_or(MAX_CALL_DEPTH);
+        if self.call_depth >= max || stack::is_low() {
+            return Exception::error(token.clone(), RuntimeErrorKind::StackOverflow);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn enter_call(&mut self) {
+        self.call_depth += 1;
+    }
+
+    pub(crate) fn exit_call(&mut self) {
+        self.call_depth -= 1;
+    }
+
+    /// Sets how deeply calls are nested, returning what it was. Coroutines
+    /// count their calls apart from whoever resumes them.
+    #[cfg(not(target_family = "wasm"))]
+    pub(crate) fn replace_call_depth(&mut self, depth: usize) -> usize {
+        mem::replace(&mut self.call_depth, depth)
+    }
+
+    pub(crate) fn stack(&mut self) -> &mut vm::Stack {
+        &mut self.stack
+    }
+
+    pub fn resolve(&mut self, expr: &Expr, depth: usize, slot: usize) {
+        self.locals.insert(expr.uid(), (depth, slot));
     }
     pub fn evaluate(&mut self, expr: &Expr) -> Result<Value> {
         expr::Visitor::visit_expr(self, expr)
     }
 
+    pub fn evaluate_in(&mut self, expr: &Expr, environment: EnvRef) -> Result<Value> {
+        let previous = self.environment.clone();
+        self.environment = environment;
+        let result = self.evaluate(expr);
+        self.environment = previous;
+        result
+    }
+
     fn visit_literal_expr(&self, literal: &Literal) -> Value {
         match literal {
-            Literal::String(value) => Value::String(value.to_string()),
-            Literal::Number(value) => Value::Number(*value),
+            Literal::String(value) => Value::String(LoxString::from(value)),
+            Literal::Int(value) => Value::Int(*value),
+            Literal::BigInt(value) => Value::BigInt(value.clone()),
+            Literal::Float(value) => Value::Float(*value),
             Literal::Bool(value) => Value::Boolean(*value),
             Literal::None => Value::Nil,
         }
@@ -77,10 +724,19 @@ impl Interpreter {
 
     fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> Result<Value> {
         let right = self.evaluate(right)?;
+        self.unary_operation(operator, right)
+    }
+
+    pub(crate) fn unary_operation(&mut self, operator: &Token, right: Value) -> Result<Value> {
         match operator.token_type {
             TokenType::BANG => Ok(Value::Boolean(!Interpreter::is_truthy(&right))),
             TokenType::MINUS => match right {
-                Value::Number(value) => Ok(Value::Number(-value)),
+                Value::Int(value) => match value.checked_neg() {
+                    Some(value) => Ok(Value::Int(value)),
+                    None => Ok(Value::integer(BigInt::from_i64(value).neg())),
+                },
+                Value::BigInt(value) => Ok(Value::integer(value.neg())),
+                Value::Float(value) => Ok(Value::Float(-value)),
                 _ => Interpreter::number_operand_error(operator),
             },
             _ => Interpreter::number_operand_error(operator),
@@ -90,76 +746,252 @@ impl Interpreter {
     fn visit_binary_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<Value> {
         let left = self.evaluate(left)?;
         let right = self.evaluate(right)?;
+        self.binary_operation(left, operator, right)
+    }
+
+    /// Evaluates `a < b < c` as `a < b and b < c`, evaluating each operand
+    /// at most once and stopping at the first false comparison.
+    fn visit_comparison_expr(&mut self, operands: &[Expr], operators: &[Token]) -> Result<Value> {
+        let mut left = self.evaluate(&operands[0])?;
+        for (operator, right) in operators.iter().zip(&operands[1..]) {
+            let right = self.evaluate(right)?;
+            let result = self.binary_operation(left, operator, right.clone())?;
+            if !Interpreter::is_truthy(&result) {
+                return Ok(result);
+            }
+            left = right;
+        }
+        Ok(Value::Boolean(true))
+    }
+
+    fn visit_lambda_expr(&mut self, declaration: &Rc<FunctionDecl>) -> Result<Value> {
+        let function = Function::new(declaration.clone(), self.environment.clone(), false);
+        Ok(Value::Function(function))
+    }
 
+    fn visit_sequence_expr(&mut self, expressions: &[Expr]) -> Result<Value> {
+        let mut value = Value::Nil;
+        for expr in expressions {
+            value = self.evaluate(expr)?;
+        }
+        Ok(value)
+    }
+
+    /// Joins two strings. A long join is flattened when first read, so its
+    /// whole length is counted against the memory limit up front.
+    fn concat(&mut self, left: &LoxString, right: &LoxString) -> Result<Value> {
+        self.allocate(left.len() + right.len())?;
+        Ok(Value::String(left.concat(right)))
+    }
+
+    pub(crate) fn binary_operation(
+        &mut self,
+        left: Value,
+        operator: &Token,
+        right: Value,
+    ) -> Result<Value> {
         match operator.token_type {
             // Arithmetic Binary Operations
-            TokenType::MINUS => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left - right)),
-                _ => Interpreter::number_operand_error(operator),
-            },
+            TokenType::MINUS => Interpreter::arithmetic(
+                operator,
+                &left,
+                &right,
+                i64::checked_sub,
+                BigInt::sub,
+                |l, r| l - r,
+            ),
             TokenType::PLUS => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
-                (Value::String(left), Value::String(right)) => {
-                    Ok(Value::String(format!("{}{}", left, right)))
+                (Value::String(left), Value::String(right)) => self.concat(&left, &right),
+                (left, right)
+                    if self.options.strict
+                        && (matches!(left, Value::String(_))
+                            || matches!(right, Value::String(_))) =>
+                {
+                    Exception::runtime_error(
+                        operator.clone(),
+                        String::from("Operands must be two numbers or two strings."),
+                    )
                 }
-                (Value::String(left), Value::Number(right)) => {
-                    Ok(Value::String(format!("{}{}", left, right)))
+                (
+                    Value::String(left),
+                    right @ (Value::Int(_) | Value::BigInt(_) | Value::Float(_)),
+                ) => self.concat(&left, &right.to_string().into()),
+                (
+                    left @ (Value::Int(_) | Value::BigInt(_) | Value::Float(_)),
+                    Value::String(right),
+                ) => self.concat(&left.to_string().into(), &right),
+                (Value::String(left), right @ Value::ClassInstance(_)) => {
+                    let right = self.stringify(&right)?;
+                    self.concat(&left, &right.into())
                 }
-                (Value::Number(left), Value::String(right)) => {
-                    Ok(Value::String(format!("{}{}", left, right)))
+                (left @ Value::ClassInstance(_), Value::String(right)) => {
+                    let left = self.stringify(&left)?;
+                    self.concat(&left.into(), &right)
                 }
-                _ => Interpreter::number_operand_error(operator),
+                (left, right) => Interpreter::arithmetic(
+                    operator,
+                    &left,
+                    &right,
+                    i64::checked_add,
+                    BigInt::add,
+                    |l, r| l + r,
+                ),
             },
-            TokenType::SLASH => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => match right {
-                    0f64 => Exception::runtime_error(
-                        operator.clone(),
-                        String::from("Cannot divide by zero"),
-                    ),
-                    _ => Ok(Value::Number(left / right)),
-                },
-                _ => Interpreter::number_operand_error(operator),
-            },
-            TokenType::STAR => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left * right)),
+            // Division always produces a float, so `7 / 2` is 3.5.
+            TokenType::SLASH => match (left.as_f64(), right.as_f64()) {
+                (Some(_), Some(0.0)) => {
+                    Exception::error(operator.clone(), RuntimeErrorKind::DivisionByZero)
+                }
+                (Some(left), Some(right)) => Ok(Value::Float(left / right)),
                 _ => Interpreter::number_operand_error(operator),
             },
+            TokenType::STAR => Interpreter::arithmetic(
+                operator,
+                &left,
+                &right,
+                i64::checked_mul,
+                BigInt::mul,
+                |l, r| l * r,
+            ),
 
             // Comparisons
-            TokenType::GREATER => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Boolean(left > right)),
-                _ => Interpreter::number_operand_error(operator),
-            },
-            TokenType::GREATER_EQUAL => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Boolean(left >= right)),
-                _ => Interpreter::number_operand_error(operator),
-            },
-            TokenType::LESS => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Boolean(left < right)),
-                _ => Interpreter::number_operand_error(operator),
-            },
-            TokenType::LESS_EQUAL => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Boolean(left <= right)),
-                _ => Interpreter::number_operand_error(operator),
+            TokenType::GREATER
+            | TokenType::GREATER_EQUAL
+            | TokenType::LESS
+            | TokenType::LESS_EQUAL => {
+                if left.as_f64().is_none() || right.as_f64().is_none() {
+                    return Interpreter::number_operand_error(operator);
+                }
+                let ordering = Interpreter::compare_numbers(&left, &right);
+                Ok(Value::Boolean(match operator.token_type {
+                    TokenType::GREATER => ordering == Some(Ordering::Greater),
+                    TokenType::GREATER_EQUAL => {
+                        matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+                    }
+                    TokenType::LESS => ordering == Some(Ordering::Less),
+                    _ => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                }))
+            }
+            TokenType::DOT_DOT | TokenType::DOT_DOT_EQUAL => match (left, right) {
+                (Value::Int(start), Value::Int(end)) => Ok(Value::Range(Range {
+                    start,
+                    end,
+                    inclusive: operator.token_type == TokenType::DOT_DOT_EQUAL,
+                })),
+                _ => Exception::runtime_error(
+                    operator.clone(),
+                    String::from("Range bounds must be integers"),
+                ),
             },
-            TokenType::BANG_EQUAL => Ok(Value::Boolean(!Interpreter::is_equal(&left, &right))),
-            TokenType::EQUAL_EQUAL => Ok(Value::Boolean(Interpreter::is_equal(&left, &right))),
+            TokenType::BANG_EQUAL => Ok(Value::Boolean(!self.is_equal(&left, &right)?)),
+            TokenType::EQUAL_EQUAL => Ok(Value::Boolean(self.is_equal(&left, &right)?)),
 
             _ => panic!("unexpected operator for binary expression"),
         }
     }
 
-    fn is_equal(left: &Value, right: &Value) -> bool {
-        match (left, right) {
+    /// Compares two values for `==` and `!=`. An instance whose class
+    /// defines `equals(other)` decides for itself; other instances are only
+    /// equal to themselves.
+    fn is_equal(&mut self, left: &Value, right: &Value) -> Result<bool> {
+        let equal = match (left, right) {
             (Value::Nil, Value::Nil) => true,
             (Value::Boolean(left), Value::Boolean(right)) => left == right,
-            (Value::Number(left), Value::Number(right)) => left == right,
+            (
+                Value::Int(_) | Value::BigInt(_) | Value::Float(_),
+                Value::Int(_) | Value::BigInt(_) | Value::Float(_),
+            ) => Interpreter::compare_numbers(left, right) == Some(Ordering::Equal),
             (Value::String(left), Value::String(right)) => left == right,
+            (Value::List(left), Value::List(right)) => ListRef::ptr_eq(left, right),
+            (Value::Map(left), Value::Map(right)) => Rc::ptr_eq(left, right),
+            (Value::Range(left), Value::Range(right)) => left == right,
+            (Value::Coroutine(left), Value::Coroutine(right)) => Rc::ptr_eq(left, right),
+            (Value::Userdata(left), Value::Userdata(right)) => left == right,
+            (Value::Tuple(left), Value::Tuple(right)) => {
+                if left.len() != right.len() {
+                    return Ok(false);
+                }
+                for (left, right) in left.iter().zip(right.iter()) {
+                    if !self.is_equal(left, right)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            (Value::ClassInstance(instance), _) => {
+                match self.call_method(instance, "equals", vec![right.clone()])? {
+                    Some(result) => Interpreter::is_truthy(&result),
+                    None => {
+                        matches!(right, Value::ClassInstance(right) if Rc::ptr_eq(instance, right))
+                    }
+                }
+            }
             _ => false,
+        };
+        Ok(equal)
+    }
+
+    /// Converts a value to the text `print` and string concatenation show,
+    /// calling `toString()` on instances whose class defines it.
+    pub fn stringify(&mut self, value: &Value) -> Result<String> {
+        match value {
+            Value::ClassInstance(instance) => {
+                match self.call_method(instance, "toString", vec![])? {
+                    Some(string) => self.stringify(&string),
+                    None => Ok(value.to_string()),
+                }
+            }
+            Value::List(list) => {
+                let values = list.borrow().clone();
+                let mut elements = Vec::with_capacity(values.len());
+                for value in &values {
+                    elements.push(self.stringify(value)?);
+                }
+                Ok(format_list(&elements))
+            }
+            Value::Tuple(tuple) => {
+                let mut elements = Vec::with_capacity(tuple.len());
+                for value in tuple.iter() {
+                    elements.push(self.stringify(value)?);
+                }
+                Ok(format_tuple(&elements))
+            }
+            Value::Map(map) => {
+                let entries = map.borrow().entries().to_vec();
+                let mut strings = Vec::with_capacity(entries.len());
+                for (key, value) in &entries {
+                    strings.push((self.stringify(key)?, self.stringify(value)?));
+                }
+                Ok(format_map(&strings))
+            }
+            _ => Ok(value.to_string()),
         }
     }
 
-    fn is_truthy(value: &Value) -> bool {
+    /// Calls the method `name` written in Lox, if the instance's class has
+    /// one taking as many arguments as `args` holds.
+    fn call_method(
+        &mut self,
+        instance: &ClassInstanceRef,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<Option<Value>> {
+        let method = instance
+            .borrow()
+            .class()
+            .bind_method(name, instance.clone());
+        match method {
+            Some(Value::Function(method)) if method.arity() == args.len() => {
+                method.call(self, args).map(Some)
+            }
+            Some(Value::Closure(method)) if method.arity() == args.len() => {
+                vm::call(self, &method, args).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn is_truthy(value: &Value) -> bool {
         match value {
             Value::Nil => false,
             Value::Boolean(value) => *value,
@@ -167,6 +999,115 @@ impl Interpreter {
         }
     }
 
+    pub(crate) fn check_arity(
+        paren: &Token,
+        arity: usize,
+        variadic: bool,
+        count: usize,
+    ) -> Result<()> {
+        if count == arity || (variadic && count > arity) {
+            return Ok(());
+        }
+        Exception::error(
+            paren.clone(),
+            RuntimeErrorKind::ArityMismatch {
+                expected: arity,
+                variadic,
+                got: count,
+            },
+        )
+    }
+
+    /// Named arguments can only be matched against the parameters of Lox
+    /// functions, or of a class's initializer.
+    fn arrange_arguments(
+        callee: &Value,
+        paren: &Token,
+        args: Vec<Value>,
+        named: Vec<(Token, Value)>,
+    ) -> Result<Vec<Value>> {
+        match callee {
+            Value::Function(func) => func.arrange_arguments(paren, args, named),
+            Value::Class(class) => match class.find_method("init") {
+                Some(Value::Function(initializer)) => {
+                    initializer.arrange_arguments(paren, args, named)
+                }
+                _ => {
+                    let (name, _) = &named[0];
+                    Exception::runtime_error(
+                        name.clone(),
+                        format!("Unknown parameter '{}'.", name.lexeme),
+                    )
+                }
+            },
+            Value::NativeFunction(_) => Exception::runtime_error(
+                paren.clone(),
+                "Native functions don't accept named arguments.".to_string(),
+            ),
+            _ => Ok(args),
+        }
+    }
+
+    /// Applies an arithmetic operator. Two ints give an int, promoted to a
+    /// `BigInt` if it overflows, and any float operand makes the result a
+    /// float.
+    fn arithmetic(
+        operator: &Token,
+        left: &Value,
+        right: &Value,
+        int_operation: fn(i64, i64) -> Option<i64>,
+        big_operation: fn(&BigInt, &BigInt) -> BigInt,
+        float_operation: fn(f64, f64) -> f64,
+    ) -> Result<Value> {
+        if let (Value::Int(left), Value::Int(right)) = (left, right) {
+            if let Some(value) = int_operation(*left, *right) {
+                return Ok(Value::Int(value));
+            }
+        }
+        if let (Some(left), Some(right)) = (left.as_big(), right.as_big()) {
+            return Ok(Value::integer(big_operation(&left, &right)));
+        }
+        match (left.as_f64(), right.as_f64()) {
+            (Some(left), Some(right)) => Ok(Value::Float(float_operation(left, right))),
+            _ => Interpreter::number_operand_error(operator),
+        }
+    }
+
+    /// Orders two numbers, comparing ints exactly. `None` if either is NaN.
+    pub fn compare_numbers(left: &Value, right: &Value) -> Option<Ordering> {
+        match (left, right) {
+            (Value::Int(left), Value::Int(right)) => Some(left.cmp(right)),
+            (Value::Float(left), Value::Float(right)) => left.partial_cmp(right),
+            (Value::Float(left), _) => {
+                Interpreter::compare_int_float(right, *left).map(Ordering::reverse)
+            }
+            (_, Value::Float(right)) => Interpreter::compare_int_float(left, *right),
+            _ => Some(left.as_big()?.cmp(&right.as_big()?)),
+        }
+    }
+
+    /// Orders an int of either size against a float exactly, where turning
+    /// the int into a float could round it: 2^53 + 1 is not 2^53.
+    pub(crate) fn compare_int_float(int: &Value, float: f64) -> Option<Ordering> {
+        if float.is_nan() {
+            return None;
+        }
+        if float.is_infinite() {
+            return Some(if float > 0.0 {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            });
+        }
+        let whole = float.trunc();
+        let ordering = match int {
+            Value::Int(int) if whole.abs() < I64_LIMIT => int.cmp(&(whole as i64)),
+            _ => int.as_big()?.cmp(&BigInt::from_whole_f64(whole)),
+        };
+        // Equal to the whole part, so the fraction decides.
+        Some(ordering.then(0.0.partial_cmp(&(float - whole))?))
+    }
+
     fn number_operand_error<T>(operator: &Token) -> Result<T> {
         Exception::runtime_error(operator.clone(), String::from("Operands must be a number"))
     }
@@ -176,8 +1117,15 @@ impl Interpreter {
     }
 
     fn visit_print_stmt(&mut self, expr: &Expr) -> Result<()> {
-        let res = self.evaluate(expr)?;
-        println!("{}", res);
+        let value = self.evaluate(expr)?;
+        self.print(&value)
+    }
+
+    pub(crate) fn print(&mut self, value: &Value) -> Result<()> {
+        let string = self.stringify(value)?;
+        // Like `println!`, except that output which can't be written, e.g.
+        // to a closed pipe, is dropped instead of panicking.
+        let _ = writeln!(self.output.borrow_mut(), "{}", string);
         Ok(())
     }
 
@@ -192,27 +1140,33 @@ impl Interpreter {
         Ok(())
     }
 
-    fn lookup_variable(&self, name: &Token, expr: &Expr) -> Result<Value> {
-        let distance = self.locals.get(expr);
+    fn visit_const_stmt(&mut self, name: &Token, initializer: &Expr) -> Result<()> {
+        let value = self.evaluate(initializer)?;
+        self.environment
+            .borrow_mut()
+            .define_constant(name.lexeme.clone(), value);
+        Ok(())
+    }
 
-        match distance {
-            None => self.globals.borrow().get(name),
-            Some(distance) => self.environment.borrow().get_at(*distance, &name.lexeme),
+    fn lookup_variable(&self, name: &Token, expr: &Expr) -> Result<Value> {
+        match self.locals.get(&expr.uid()) {
+            None => self.environment.borrow().get_global(name),
+            Some(&(distance, slot)) => Ok(self.environment.borrow().get_at(distance, slot)),
         }
     }
     fn visit_var_expr(&self, name: &Token, expr: &Expr) -> Result<Value> {
         self.lookup_variable(name, expr)
     }
 
-    fn visit_assign_expr(&mut self, name: &Token, expr: &Expr) -> Result<Value> {
-        let value = self.evaluate(expr)?;
+    fn visit_assign_expr(&mut self, name: &Token, value: &Expr, expr: &Expr) -> Result<Value> {
+        let value = self.evaluate(value)?;
 
-        let distance = self.locals.get(expr);
-        match distance {
-            Some(distance) => self
-                .environment
-                .borrow_mut()
-                .assign_at(*distance, name, &value),
+        match self.locals.get(&expr.uid()) {
+            Some(&(distance, slot)) => {
+                self.environment
+                    .borrow_mut()
+                    .assign_at(distance, slot, value.clone())
+            }
             None => self.environment.borrow_mut().assign(name, value.clone())?,
         }
 
@@ -226,15 +1180,72 @@ impl Interpreter {
 
     pub fn execute_block(&mut self, stmts: &Vec<Stmt>, environment: EnvRef) -> Result<()> {
         let previous = self.environment.clone();
+        let depth = self.frames.len();
+        self.frames.push(previous.clone());
         self.environment = environment;
+        let mut result = Ok(());
         for stmt in stmts {
-            if let Err(e) = self.execute(stmt) {
-                self.environment = previous;
-                return Err(e);
+            result = self.execute(stmt);
+            if result.is_err() {
+                break;
             }
         }
+        // A coroutine may have left frames of its own behind.
+        self.frames.truncate(depth);
         self.environment = previous;
-        Ok(())
+        result
+    }
+
+    fn visit_assert_stmt(
+        &mut self,
+        keyword: &Token,
+        condition: &Expr,
+        message: &Option<Expr>,
+    ) -> Result<()> {
+        if Interpreter::is_truthy(&self.evaluate(condition)?) {
+            return Ok(());
+        }
+        let message = match message {
+            None => "Assertion failed.".to_string(),
+            Some(message) => format!("Assertion failed: {}", self.evaluate(message)?),
+        };
+        Exception::runtime_error(keyword.clone(), message)
+    }
+
+    fn visit_throw_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<()> {
+        let value = self.evaluate(value)?;
+        Err(Exception::Throw(value, keyword.clone()))
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        body: &Vec<Stmt>,
+        handler: &Option<(Token, Vec<Stmt>)>,
+        finally: &Option<Vec<Stmt>>,
+    ) -> Result<()> {
+        let mut result = self.execute_block(body, Environment::new_local(&self.environment));
+
+        if let Some((name, handler)) = handler {
+            let caught = match &result {
+                Err(Exception::Throw(value, _)) => Some(value.clone()),
+                Err(Exception::RuntimeError(error)) => {
+                    Some(Value::String(error.kind.to_string().into()))
+                }
+                _ => None,
+            };
+            if let Some(caught) = caught {
+                let environment = Environment::new_local(&self.environment);
+                environment.borrow_mut().define(name.lexeme.clone(), caught);
+                result = self.execute_block(handler, environment);
+            }
+        }
+
+        // The finally block runs however the try exits, including `return`
+        // and `continue`. If it exits abruptly itself, that wins.
+        if let Some(finally) = finally {
+            self.execute_block(finally, Environment::new_local(&self.environment))?;
+        }
+        result
     }
 
     fn visit_if_stmt(
@@ -257,9 +1268,340 @@ impl Interpreter {
         Ok(())
     }
 
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> Result<()> {
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: &Option<Expr>,
+    ) -> Result<()> {
         while Interpreter::is_truthy(&self.evaluate(condition)?) {
-            self.execute(body)?;
+            match self.execute(body) {
+                Ok(_) | Err(Exception::Continue) => {}
+                Err(e) => return Err(e),
+            }
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_list_expr(&mut self, elements: &Vec<Expr>) -> Result<Value> {
+        self.allocate(memory::elements_size(elements.len()))?;
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements {
+            values.push(self.evaluate(element)?);
+        }
+        Ok(Value::from(values))
+    }
+
+    fn visit_yield_expr(&mut self, keyword: &Token, value: &Option<Box<Expr>>) -> Result<Value> {
+        let value = match value {
+            None => Value::Nil,
+            Some(value) => self.evaluate(value)?,
+        };
+        let environment = self.environment.clone();
+        let sent = coroutine::suspend(keyword, value);
+        self.environment = environment;
+        sent
+    }
+
+    fn visit_tuple_expr(&mut self, elements: &Vec<Expr>) -> Result<Value> {
+        self.allocate(memory::elements_size(elements.len()))?;
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements {
+            values.push(self.evaluate(element)?);
+        }
+        Ok(Value::Tuple(Rc::new(values)))
+    }
+
+    fn visit_index_expr(&mut self, object: &Expr, bracket: &Token, index: &Expr) -> Result<Value> {
+        let object = self.evaluate(object)?;
+        let index = self.evaluate(index)?;
+        match object {
+            Value::List(list) => {
+                let list = list.borrow();
+                let i = Interpreter::list_index(bracket, &index, list.len(), "List")?;
+                Ok(list[i].clone())
+            }
+            Value::Tuple(tuple) => {
+                let i = Interpreter::list_index(bracket, &index, tuple.len(), "Tuple")?;
+                Ok(tuple[i].clone())
+            }
+            Value::Map(map) => match map.borrow().get(&index) {
+                Ok(Some(value)) => Ok(value),
+                Ok(None) => {
+                    Exception::runtime_error(bracket.clone(), format!("Undefined key '{}'.", index))
+                }
+                Err(message) => Exception::runtime_error(bracket.clone(), message),
+            },
+            _ => Exception::runtime_error(
+                bracket.clone(),
+                "Only lists, tuples and maps can be indexed.".to_string(),
+            ),
+        }
+    }
+
+    fn visit_set_index_expr(
+        &mut self,
+        object: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<Value> {
+        let object = self.evaluate(object)?;
+        let index = self.evaluate(index)?;
+        let value = self.evaluate(value)?;
+        match object {
+            Value::List(list) => {
+                let mut list = list.borrow_mut();
+                let i = Interpreter::list_index(bracket, &index, list.len(), "List")?;
+                list[i] = value.clone();
+                Ok(value)
+            }
+            Value::Map(map) => {
+                self.allocate(memory::elements_size(2))?;
+                match map.borrow_mut().insert(index, value.clone()) {
+                    Ok(()) => Ok(value),
+                    Err(message) => Exception::runtime_error(bracket.clone(), message),
+                }
+            }
+            Value::Tuple(_) => {
+                Exception::runtime_error(bracket.clone(), "Tuples are immutable.".to_string())
+            }
+            _ => Exception::runtime_error(
+                bracket.clone(),
+                "Only lists and maps can be indexed.".to_string(),
+            ),
+        }
+    }
+
+    fn list_index(bracket: &Token, index: &Value, len: usize, kind: &str) -> Result<usize> {
+        let index = match index {
+            Value::Int(index) => *index,
+            _ => {
+                return Exception::runtime_error(
+                    bracket.clone(),
+                    format!("{} index must be an integer.", kind),
+                )
+            }
+        };
+        if index < 0 || index >= len as i64 {
+            return Exception::error(
+                bracket.clone(),
+                RuntimeErrorKind::IndexOutOfRange {
+                    kind: kind.to_string(),
+                    index,
+                    length: len,
+                },
+            );
+        }
+        Ok(index as usize)
+    }
+
+    fn visit_slice_expr(
+        &mut self,
+        object: &Expr,
+        bracket: &Token,
+        start: &Option<Box<Expr>>,
+        end: &Option<Box<Expr>>,
+    ) -> Result<Value> {
+        let object = self.evaluate(object)?;
+        let start = match start {
+            None => None,
+            Some(expr) => Some(self.evaluate(expr)?),
+        };
+        let end = match end {
+            None => None,
+            Some(expr) => Some(self.evaluate(expr)?),
+        };
+
+        match object {
+            Value::List(list) => {
+                let list = list.borrow();
+                let (start, end) = Interpreter::slice_bounds(bracket, start, end, list.len())?;
+                self.allocate(memory::elements_size(end - start))?;
+                Ok(Value::from(list[start..end].to_vec()))
+            }
+            Value::Tuple(tuple) => {
+                let (start, end) = Interpreter::slice_bounds(bracket, start, end, tuple.len())?;
+                self.allocate(memory::elements_size(end - start))?;
+                Ok(Value::Tuple(Rc::new(tuple[start..end].to_vec())))
+            }
+            Value::String(string) => {
+                let length = string.chars().count();
+                let (start, end) = Interpreter::slice_bounds(bracket, start, end, length)?;
+                Ok(Value::String(
+                    string
+                        .chars()
+                        .skip(start)
+                        .take(end - start)
+                        .collect::<String>()
+                        .into(),
+                ))
+            }
+            _ => Exception::runtime_error(
+                bracket.clone(),
+                "Only lists and strings can be sliced.".to_string(),
+            ),
+        }
+    }
+
+    /// Resolves optional, possibly negative, slice bounds against `len`.
+    /// Negative bounds count from the end, missing ones default to the
+    /// start and end of the sequence.
+    fn slice_bounds(
+        bracket: &Token,
+        start: Option<Value>,
+        end: Option<Value>,
+        len: usize,
+    ) -> Result<(usize, usize)> {
+        let mut bounds = [0, len as i64];
+        for (bound, value) in bounds.iter_mut().zip([start, end]) {
+            match value {
+                None => {}
+                Some(Value::Int(n)) => {
+                    *bound = if n < 0 { n + len as i64 } else { n };
+                }
+                Some(_) => {
+                    return Exception::runtime_error(
+                        bracket.clone(),
+                        "Slice bounds must be integers.".to_string(),
+                    )
+                }
+            }
+        }
+
+        let [start, end] = bounds;
+        if start < 0 || end > len as i64 || start > end {
+            return Exception::runtime_error(
+                bracket.clone(),
+                format!("Slice [{}:{}] out of range for length {}.", start, end, len),
+            );
+        }
+        Ok((start as usize, end as usize))
+    }
+
+    fn visit_is_expr(&mut self, object: &Expr, keyword: &Token, class: &Expr) -> Result<Value> {
+        let object = self.evaluate(object)?;
+        let Value::Class(class) = self.evaluate(class)? else {
+            return Exception::runtime_error(
+                keyword.clone(),
+                String::from("Right operand of 'is' must be a class."),
+            );
+        };
+        Ok(Value::Boolean(match object {
+            Value::ClassInstance(instance) => instance.borrow().is_instance_of(&class),
+            _ => false,
+        }))
+    }
+
+    fn visit_ternary_expr(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Result<Value> {
+        if Interpreter::is_truthy(&self.evaluate(condition)?) {
+            self.evaluate(then_branch)
+        } else {
+            self.evaluate(else_branch)
+        }
+    }
+
+    fn visit_if_expr(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Option<Box<Expr>>,
+    ) -> Result<Value> {
+        let value = self.evaluate(condition)?;
+        if Interpreter::is_truthy(&value) {
+            self.evaluate(then_branch)
+        } else {
+            match else_branch {
+                None => Ok(Value::Nil),
+                Some(expr) => self.evaluate(expr),
+            }
+        }
+    }
+
+    fn visit_block_expr(&mut self, stmts: &Vec<Stmt>, value: &Option<Box<Expr>>) -> Result<Value> {
+        let previous = self.environment.clone();
+        self.environment = Environment::new_local(&previous);
+        let result = self.evaluate_block(stmts, value);
+        self.environment = previous;
+        result
+    }
+
+    fn evaluate_block(&mut self, stmts: &Vec<Stmt>, value: &Option<Box<Expr>>) -> Result<Value> {
+        for stmt in stmts {
+            self.execute(stmt)?;
+        }
+        match value {
+            None => Ok(Value::Nil),
+            Some(expr) => self.evaluate(expr),
+        }
+    }
+
+    fn visit_do_while_stmt(&mut self, body: &Stmt, condition: &Expr) -> Result<()> {
+        loop {
+            match self.execute(body) {
+                Ok(_) | Err(Exception::Continue) => {}
+                Err(e) => return Err(e),
+            }
+            if !Interpreter::is_truthy(&self.evaluate(condition)?) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn visit_for_in_stmt(
+        &mut self,
+        name: &Token,
+        keyword: &Token,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Result<()> {
+        // Iterate over a snapshot so the body can freely modify the collection.
+        let elements: Box<dyn Iterator<Item = Value>> = match self.evaluate(iterable)? {
+            Value::List(list) => Box::new(list.borrow().clone().into_iter()),
+            Value::String(string) => Box::new(
+                string
+                    .chars()
+                    .map(|c| Value::String(c.to_string().into()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+            Value::Range(range) => Box::new(range.values().map(Value::Int)),
+            Value::Map(map) => Box::new(
+                map.borrow()
+                    .entries()
+                    .iter()
+                    .map(|(key, _)| key.clone())
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+            _ => {
+                return Exception::runtime_error(
+                    keyword.clone(),
+                    "Can only iterate over lists, strings, ranges and maps.".to_string(),
+                )
+            }
+        };
+
+        for element in elements {
+            let previous = self.environment.clone();
+            self.environment = Environment::new_local(&previous);
+            self.environment
+                .borrow_mut()
+                .define(name.lexeme.clone(), element);
+            let result = self.execute(body);
+            self.environment = previous;
+            match result {
+                Ok(_) | Err(Exception::Continue) => {}
+                Err(e) => return Err(e),
+            }
         }
         Ok(())
     }
@@ -283,6 +1625,7 @@ impl Interpreter {
         callee: &Expr,
         paren: &Token,
         arguments: &Vec<Expr>,
+        named: &[(Token, Expr)],
     ) -> Result<Value> {
         let callee = self.evaluate(callee)?;
 
@@ -290,58 +1633,65 @@ impl Interpreter {
         for argument in arguments {
             args.push(self.evaluate(argument)?);
         }
+        if !named.is_empty() {
+            let mut named_args = vec![];
+            for (name, argument) in named {
+                named_args.push((name.clone(), self.evaluate(argument)?));
+            }
+            args = Interpreter::arrange_arguments(&callee, paren, args, named_args)?;
+        }
+        self.call_value(callee, paren, args)
+    }
+
+    /// Calls anything callable with arguments already evaluated.
+    pub(crate) fn call_value(
+        &mut self,
+        callee: Value,
+        paren: &Token,
+        args: Vec<Value>,
+    ) -> Result<Value> {
+        let count = args.len();
         match callee {
             Value::Function(func) => {
-                if arguments.len() != func.arity() {
-                    return Exception::runtime_error(
-                        paren.clone(),
-                        format!(
-                            "Expected {} arguments but got {}.",
-                            func.arity(),
-                            arguments.len()
-                        ),
-                    );
-                }
-                return func.call(self, args);
+                Interpreter::check_arity(paren, func.arity(), func.is_variadic(), count)?;
+                self.check_call_depth(paren)?;
+                func.call(self, args)
             }
             Value::NativeFunction(func) => {
-                if arguments.len() != func.arity() {
+                Interpreter::check_arity(paren, func.arity, func.variadic, count)?;
+                func.call(self, paren, args)
+            }
+            Value::Closure(closure) => {
+                Interpreter::check_arity(paren, closure.arity(), false, count)?;
+                vm::call(self, &closure, args)
+            }
+            Value::Coroutine(coroutine) => {
+                if count > 1 {
                     return Exception::runtime_error(
                         paren.clone(),
-                        format!(
-                            "Expected {} arguments but got {}.",
-                            func.arity(),
-                            arguments.len()
-                        ),
+                        format!("Expected at most 1 arguments but got {}.", count),
                     );
                 }
-                return func.call(self, args);
+                let environment = self.environment.clone();
+                let value = args.into_iter().next().unwrap_or(Value::Nil);
+                let result = coroutine.resume(self, paren, value);
+                self.environment = environment;
+                result
             }
             Value::Class(class) => {
-                if arguments.len() != class.arity() {
-                    return Exception::runtime_error(
-                        paren.clone(),
-                        format!(
-                            "Expected {} arguments but got {}.",
-                            class.arity(),
-                            arguments.len()
-                        ),
-                    );
-                }
-                return class.call(self, args);
+                Interpreter::check_arity(paren, class.arity(), class.is_variadic(), count)?;
+                class.call(self, paren, args)
             }
-            _ => Exception::runtime_error(
-                paren.clone(),
-                "Can only call functions and classes.".to_string(),
-            ),
+            _ => Exception::error(paren.clone(), RuntimeErrorKind::NotCallable),
         }
     }
 
-    fn visit_function_stmt(&mut self, name: &Token, function_stmt: &Stmt) -> Result<()> {
-        let function = Function::new(function_stmt.clone(), self.environment.clone(), false);
+    fn visit_function_stmt(&mut self, declaration: &Rc<FunctionDecl>) -> Result<()> {
+        let name = declaration.name.lexeme.clone();
+        let function = Function::new(declaration.clone(), self.environment.clone(), false);
         self.environment
             .borrow_mut()
-            .define(name.lexeme.clone(), Value::Function(function));
+            .define(name, Value::Function(function));
         Ok(())
     }
 
@@ -371,6 +1721,9 @@ impl Interpreter {
         &mut self,
         name: &Token,
         methods: &Vec<Stmt>,
+        fields: &Rc<[Stmt]>,
+        constants: &[Stmt],
+        statics: &[Stmt],
         super_class: &Option<Expr>,
     ) -> Result<()> {
         let super_class = match super_class {
@@ -381,7 +1734,8 @@ impl Interpreter {
             }
         };
 
-        self.environment
+        let declared = self.environment.clone();
+        declared
             .borrow_mut()
             .define(name.lexeme.clone(), Value::Nil);
 
@@ -390,61 +1744,269 @@ impl Interpreter {
             self.environment = Environment::new_local(&self.environment);
             self.environment
                 .borrow_mut()
-                .define("super".to_string(), Value::Class(*super_class))
+                .define(Symbol::from("super"), Value::Class(*super_class))
+        }
+
+        let mut class_constants = HashMap::new();
+        for constant in constants {
+            if let Stmt::Const { name, initializer } = constant {
+                let value = self.evaluate(initializer)?;
+                class_constants.insert(name.lexeme.clone(), value);
+            }
         }
 
         let mut class_methods = HashMap::new();
+        let mut class_setters = HashMap::new();
         for method in methods {
-            match method {
-                Stmt::Function { name, body, params } => {
-                    let func = Function::new(
-                        method.clone(),
-                        self.environment.clone(),
-                        name.lexeme == "init",
-                    );
-                    class_methods.insert(name.lexeme.clone(), func);
+            if let Stmt::Function(declaration) = method {
+                let name = declaration.name.lexeme.clone();
+                let func = Function::new(
+                    declaration.clone(),
+                    self.environment.clone(),
+                    name == "init",
+                );
+                if func.is_setter() {
+                    class_setters.insert(name, func);
+                } else {
+                    class_methods.insert(name, func);
                 }
-                _ => {}
             }
         }
 
-        let klass = Value::Class(Class::new(
-            name.lexeme.clone(),
+        let class = Class::new(
+            name.lexeme.to_string(),
             super_class.clone(),
             class_methods,
-        ));
+            class_setters,
+            fields.clone(),
+            class_constants,
+            self.environment.clone(),
+        );
+        for field in statics {
+            if let Stmt::Var { name, initializer } = field {
+                let value = match initializer {
+                    None => Value::Nil,
+                    Some(expr) => self.evaluate(expr)?,
+                };
+                class.define_static(&name.lexeme, value);
+            }
+        }
+        let klass = Value::Class(class);
 
         if super_class.is_some() {
             self.environment = prev_environment;
         }
 
-        self.environment.borrow_mut().assign(name, klass)
+        declared
+            .borrow_mut()
+            .redefine_last(name.lexeme.clone(), klass);
+        Ok(())
+    }
+
+    fn visit_import_stmt(
+        &mut self,
+        path: &Token,
+        name: &Token,
+        members: &Vec<Token>,
+        alias: &Option<Token>,
+    ) -> Result<()> {
+        let is_file = name.lexeme.ends_with(".lox");
+        let module = if is_file {
+            if !self.capabilities.file_io {
+                return Exception::runtime_error(
+                    path.clone(),
+                    "Importing files is disabled.".to_string(),
+                );
+            }
+            self.load_file_module(path, &name.lexeme)?
+        } else {
+            match self.native_modules.get(name.lexeme.as_str()) {
+                Some(module) => module.clone(),
+                None => {
+                    return Exception::runtime_error(
+                        path.clone(),
+                        format!("Unknown module '{}'.", name.lexeme),
+                    )
+                }
+            }
+        };
+
+        let mut environment = self.environment.borrow_mut();
+        if let Some(alias) = alias {
+            environment.define(alias.lexeme.clone(), Value::Module(module));
+        } else if !members.is_empty() {
+            for member in members {
+                environment.define(member.lexeme.clone(), module.get(member)?);
+            }
+        } else if is_file {
+            for (member, value) in module.members() {
+                environment.define(member.clone(), value.clone());
+            }
+        } else {
+            environment.define(name.lexeme.clone(), Value::Module(module));
+        }
+        Ok(())
+    }
+
+    /// Runs a `.lox` file in its own top-level environment and collects the
+    /// names it declares into a module.
+    fn load_file_module(&mut self, path: &Token, relative: &str) -> Result<ModuleRef> {
+        let file = match fs::canonicalize(self.script_dir.join(relative)) {
+            Ok(file) => file,
+            Err(err) => {
+                return Exception::runtime_error(
+                    path.clone(),
+                    format!("Could not load module '{}': {}.", relative, err),
+                )
+            }
+        };
+        if let Some(module) = self.file_modules.get(&file) {
+            return Ok(module.clone());
+        }
+        if let Some(start) = self.loading.iter().position(|loading| *loading == file) {
+            let cycle: Vec<String> = self.loading[start..]
+                .iter()
+                .chain([&file])
+                .map(|file| {
+                    file.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+            return Exception::runtime_error(
+                path.clone(),
+                format!("Circular import: {}.", cycle.join(" -> ")),
+            );
+        }
+        let source = match fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(err) => {
+                return Exception::runtime_error(
+                    path.clone(),
+                    format!("Could not load module '{}': {}.", relative, err),
+                )
+            }
+        };
+
+        let mut tokens = Vec::new();
+        Scanner::new(source, &mut tokens, &self.reporter).scan_tokens();
+        let mut stmts = vec![];
+        if !self.reporter.had_error() {
+            stmts = Parser::new(&tokens, &self.reporter, &self.ids).parse();
+            Resolver::new(self).resolve_block(&stmts);
+        }
+        if !self.reporter.had_error() {
+            DeadCode::new(&tokens, &self.reporter).check_block(&stmts);
+        }
+        if self.reporter.had_error() {
+            let diagnostics: Vec<String> = self
+                .reporter
+                .take()
+                .iter()
+                .map(Diagnostic::to_string)
+                .collect();
+            return Exception::runtime_error(
+                path.clone(),
+                format!(
+                    "Could not compile module '{}':\n{}",
+                    relative,
+                    diagnostics.join("\n")
+                ),
+            );
+        }
+
+        let environment = Environment::new_module(&self.globals);
+        let script_dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        let previous_dir = std::mem::replace(&mut self.script_dir, script_dir);
+        self.loading.push(file.clone());
+        let result = self.execute_block(&stmts, environment.clone());
+        self.loading.pop();
+        self.script_dir = previous_dir;
+        result?;
+
+        let name = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut module = Module::new(&name);
+        for (member, value) in environment.borrow().values() {
+            module.define(member, value.clone());
+        }
+        let module = ModuleRef::new(module);
+        self.file_modules.insert(file, module.clone());
+        Ok(module)
     }
 
     fn visit_get_expr(&mut self, name: &Token, object: &Expr) -> Result<Value> {
         let obj = self.evaluate(object)?;
+        self.get_property(obj, name)
+    }
+
+    /// Reads the property `name` of `obj`, for both backends.
+    pub(crate) fn get_property(&mut self, obj: Value, name: &Token) -> Result<Value> {
         if let Value::ClassInstance(class_instance) = obj {
             let instance_ref = class_instance.clone();
-            return class_instance.borrow().get(name, instance_ref);
+            let value = class_instance.borrow().get(name, instance_ref)?;
+            return self.call_if_getter(value);
+        }
+        if let Value::Module(module) = obj {
+            return module.get(name);
+        }
+        if let Value::Class(class) = obj {
+            return class.get_static(name);
+        }
+        if let Value::Coroutine(coroutine) = obj {
+            if name.lexeme == "done" {
+                return Ok(Value::Boolean(coroutine.is_done()));
+            }
+            return Exception::runtime_error(
+                name.clone(),
+                "Coroutines only have a 'done' property.".to_string(),
+            );
+        }
+        Exception::runtime_error(name.clone(), "Only instances have properties".to_string())
+    }
+
+    fn call_if_getter(&mut self, value: Value) -> Result<Value> {
+        match value {
+            Value::Function(getter) if getter.is_getter() => getter.call(self, vec![]),
+            value => Ok(value),
         }
-        Err(Exception::RuntimeError(RuntimeError {
-            token: name.clone(),
-            message: "Only instances have properties".to_string(),
-        }))
     }
 
     fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr) -> Result<Value> {
         let obj = self.evaluate(object)?;
+        // Other values are an error before the value is evaluated.
+        let value = match obj {
+            Value::ClassInstance(_) | Value::Class(_) => self.evaluate(value)?,
+            _ => Value::Nil,
+        };
+        self.set_property(obj, name, value)
+    }
 
-        if let Value::ClassInstance(mut instance) = obj {
-            let value = self.evaluate(value)?;
-            instance.borrow_mut().set(name, value.clone());
+    /// Sets the property `name` of `obj`, for both backends.
+    pub(crate) fn set_property(&mut self, obj: Value, name: &Token, value: Value) -> Result<Value> {
+        if let Value::ClassInstance(instance) = obj {
+            let setter = instance.borrow().find_setter(&name.lexeme);
+            match setter {
+                Some(mut setter) => {
+                    setter.bind(instance).call(self, vec![value.clone()])?;
+                }
+                None => {
+                    if !instance.borrow().has_field(&name.lexeme) {
+                        self.allocate(memory::field_size(&name.lexeme))?;
+                    }
+                    instance.borrow_mut().set(name, value.clone());
+                }
+            }
             return Ok(value);
         }
-        Err(Exception::RuntimeError(RuntimeError {
-            token: name.clone(),
-            message: "Only instance have fields".to_string(),
-        }))
+        if let Value::Class(class) = obj {
+            class.set_static(name, value.clone())?;
+            return Ok(value);
+        }
+        Exception::runtime_error(name.clone(), "Only instance have fields".to_string())
     }
 
     fn visit_this_expr(&mut self, keyword: &Token, expr: &Expr) -> Result<Value> {
@@ -452,37 +2014,47 @@ impl Interpreter {
     }
 
     fn visit_super_expr(&mut self, expr: &Expr, method: &Token) -> Result<Value> {
-        let distance = self
+        let &(distance, slot) = self
             .locals
-            .get(expr)
+            .get(&expr.uid())
             .expect("Super class to have been resolved");
-        let super_class = self.environment.borrow().get_at(*distance, "super")?;
-        let super_class = match super_class {
-            Value::Class(super_class) => super_class,
-            _ => panic!("Expected superclass to be a class!"),
-        };
+        let super_class = self.environment.borrow().get_at(distance, slot);
         let this = self
             .environment
             .borrow()
-            // "this" is always right inside where "super" is stored
-            .get_at(*distance - 1, "this")
-            .expect("'this' to have been resolved");
-        let this = match this {
-            Value::ClassInstance(instance) => instance,
-            _ => panic!("Expected 'this' to be a class instance!"),
-        };
+            // "this" is always alone in the scope right inside "super"'s
+            .get_at(distance - 1, 0);
+        self.get_super(super_class, this, method)
+    }
 
-        let method = super_class.find_method(&method.lexeme).ok_or_else(|| {
-            Exception::runtime_error::<()>(
+    /// Looks `method` up on `super_class` and binds it to `this`, for both
+    /// backends.
+    pub(crate) fn get_super(
+        &mut self,
+        super_class: Value,
+        this: Value,
+        method: &Token,
+    ) -> Result<Value> {
+        // The resolver and compiler see to both, unless a `.loxb` file was
+        // damaged.
+        let (Value::Class(super_class), Value::ClassInstance(this)) = (super_class, this) else {
+            return Exception::runtime_error(
                 method.clone(),
-                format!("Undefined property {}.", method.lexeme),
-            )
-            .unwrap_err()
-        })?;
-        match method {
-            Value::Function(mut method) => Ok(Value::Function(method.bind(this))),
-            _ => panic!("Expected method to be a function!"),
-        }
+                String::from("Can't use 'super' outside of a method."),
+            );
+        };
+        let method = super_class
+            .bind_method(&method.lexeme, this)
+            .ok_or_else(|| {
+                Exception::error::<()>(
+                    method.clone(),
+                    RuntimeErrorKind::UndefinedProperty {
+                        name: method.lexeme.to_string(),
+                    },
+                )
+                .unwrap_err()
+            })?;
+        self.call_if_getter(method)
     }
 }
 
@@ -501,7 +2073,7 @@ impl expr::Visitor<Result<Value>> for Interpreter {
                 ..
             } => self.visit_binary_expr(left, operator, right),
             Expr::Var { name, .. } => self.visit_var_expr(name, expr),
-            Expr::Assign { name, value, .. } => self.visit_assign_expr(name, value),
+            Expr::Assign { name, value, .. } => self.visit_assign_expr(name, value, expr),
             Expr::Logical {
                 left,
                 operator,
@@ -512,8 +2084,9 @@ impl expr::Visitor<Result<Value>> for Interpreter {
                 callee,
                 paren,
                 arguments,
+                named,
                 ..
-            } => self.visit_call_expr(callee, paren, arguments),
+            } => self.visit_call_expr(callee, paren, arguments, named),
             Expr::Get { name, object, .. } => self.visit_get_expr(name, object),
             Expr::Set {
                 object,
@@ -523,6 +2096,57 @@ impl expr::Visitor<Result<Value>> for Interpreter {
             } => self.visit_set_expr(object, name, value),
             Expr::This { keyword, .. } => self.visit_this_expr(keyword, expr),
             Expr::Super { method, .. } => self.visit_super_expr(expr, method),
+            Expr::List { elements, .. } => self.visit_list_expr(elements),
+            Expr::Tuple { elements, .. } => self.visit_tuple_expr(elements),
+            Expr::Yield { keyword, value, .. } => self.visit_yield_expr(keyword, value),
+            Expr::Is {
+                object,
+                keyword,
+                class,
+                ..
+            } => self.visit_is_expr(object, keyword, class),
+            Expr::Comparison {
+                operands,
+                operators,
+                ..
+            } => self.visit_comparison_expr(operands, operators),
+            Expr::Sequence { expressions, .. } => self.visit_sequence_expr(expressions),
+            Expr::Lambda { declaration, .. } => self.visit_lambda_expr(declaration),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+                ..
+            } => self.visit_index_expr(object, bracket, index),
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+                ..
+            } => self.visit_set_index_expr(object, bracket, index, value),
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+                ..
+            } => self.visit_slice_expr(object, bracket, start, end),
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => self.visit_ternary_expr(condition, then_branch, else_branch),
+            Expr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => self.visit_if_expr(condition, then_branch, else_branch),
+            Expr::Block {
+                statements, value, ..
+            } => self.visit_block_expr(statements, value),
         }
     }
 }
@@ -531,16 +2155,40 @@ impl stmt::Visitor<Result<()>> for Interpreter {
     fn visit_stmt(&mut self, stmt: &Stmt) -> Result<()> {
         match stmt {
             Stmt::Expression(expr) => self.visit_expr_stmt(expr),
-            Stmt::Print(expr) => self.visit_print_stmt(expr),
+            Stmt::Print { expr, .. } => self.visit_print_stmt(expr),
             Stmt::Var { name, initializer } => self.visit_var_stmt(name, initializer),
+            Stmt::Const { name, initializer } => self.visit_const_stmt(name, initializer),
             Stmt::Block(stmts) => self.visit_block_stmt(stmts),
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
             } => self.visit_if_stmt(condition, then_branch, else_branch),
-            Stmt::While { condition, body } => self.visit_while_stmt(condition, body),
-            Stmt::Function { name, .. } => self.visit_function_stmt(name, stmt),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => self.visit_while_stmt(condition, body, increment),
+            Stmt::Continue { .. } => Err(Exception::Continue),
+            Stmt::Throw { keyword, value } => self.visit_throw_stmt(keyword, value),
+            Stmt::Assert {
+                keyword,
+                condition,
+                message,
+            } => self.visit_assert_stmt(keyword, condition, message),
+            Stmt::Try {
+                body,
+                handler,
+                finally,
+            } => self.visit_try_stmt(body, handler, finally),
+            Stmt::DoWhile { body, condition } => self.visit_do_while_stmt(body, condition),
+            Stmt::ForIn {
+                name,
+                keyword,
+                iterable,
+                body,
+            } => self.visit_for_in_stmt(name, keyword, iterable, body),
+            Stmt::Function(declaration) => self.visit_function_stmt(declaration),
             Stmt::Return {
                 keyword: _keyword,
                 value,
@@ -548,9 +2196,17 @@ impl stmt::Visitor<Result<()>> for Interpreter {
             Stmt::Class {
                 name,
                 methods,
+                fields,
+                constants,
+                statics,
                 super_class,
-                ..
-            } => self.visit_class_stmt(name, methods, super_class),
+            } => self.visit_class_stmt(name, methods, fields, constants, statics, super_class),
+            Stmt::Import {
+                path,
+                name,
+                members,
+                alias,
+            } => self.visit_import_stmt(path, name, members, alias),
         }
     }
 }
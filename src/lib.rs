@@ -1,119 +1,456 @@
-use std::{fs, io, process};
+#![allow(
+    non_camel_case_types,
+    clippy::upper_case_acronyms,
+    clippy::result_large_err,
+    clippy::large_enum_variant
+)]
 
-use crate::interpreter::Interpreter;
-use crate::parser::Parser;
-use crate::resolver::Resolver;
+use std::fmt::Formatter;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+pub use crate::ast_json::ast_to_json;
+pub use crate::builder::InterpreterBuilder;
+pub use crate::class::{NativeClass, NativeMethod};
+pub use crate::diagnostics::{Diagnostic, DiagnosticKind, ErrorCode, ErrorReporter};
+pub use crate::expr::Expr;
+pub use crate::gc::LeakedCycle;
+pub use crate::interpreter::{
+    stdin_input, stdout_output, Backend, Input, Interpreter, InterpreterOptions, Output,
+    StatementHook,
+};
+use crate::parser::{AstIdGen, Parser};
 use crate::scanner::Scanner;
-use crate::stmt::Stmt;
-use crate::token::Token;
-use crate::value::Value;
+pub use crate::session::Session;
+pub use crate::stmt::Stmt;
+pub use crate::string::LoxString;
+pub use crate::symbol::Symbol;
+use crate::sync::{Rc, RefCell};
+pub use crate::token::{Span, Token};
+pub use crate::value::{Userdata, Value};
 
+mod ast_json;
+mod bigint;
+mod builder;
 mod class;
+mod clock;
+mod coroutine;
+mod dead_code;
+mod diagnostics;
 mod environment;
 mod expr;
 mod function;
+mod gc;
 mod interpreter;
+mod map;
+mod memory;
+mod module;
+mod natives;
 mod parser;
+#[cfg(feature = "python")]
+mod python;
 mod resolver;
 mod scanner;
+#[cfg(feature = "serde")]
+mod serialize;
+mod session;
+mod stack;
 mod stmt;
+mod string;
+mod symbol;
+mod sync;
 mod token;
 mod utils;
 mod value;
+mod vm;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-static mut HAD_RUNTIME_ERROR: bool = false;
+/// Why a program didn't run to completion.
+#[derive(Debug)]
+pub enum LoxError {
+    /// The source had errors, so it wasn't run.
+    Compile(Vec<Diagnostic>),
+    /// Running the program raised an error that nothing caught.
+    Runtime(RuntimeError),
+    /// The script couldn't be read.
+    Io(io::Error),
+    /// The program ran past the step or time limit in its options.
+    Timeout,
+    /// The program's values outgrew the memory limit in its options.
+    OutOfMemory,
+    /// The statement hook stopped the program.
+    Interrupted,
+}
 
+/// An error raised while the program ran, at the token where it happened.
 #[derive(Debug)]
 pub struct RuntimeError {
     token: Token,
-    message: String,
+    /// Boxed because some kinds hold a `Value`, and runtime errors travel
+    /// in every `Result` the interpreter returns.
+    kind: Box<RuntimeErrorKind>,
+}
+
+impl RuntimeError {
+    pub fn kind(&self) -> &RuntimeErrorKind {
+        &self.kind
+    }
+
+    pub fn line(&self) -> usize {
+        self.token.line
+    }
+
+    pub fn span(&self) -> Span {
+        self.token.span
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.kind.code()
+    }
+}
+
+/// What went wrong in a `RuntimeError`. Errors without a variant of their
+/// own, including those from natives, are a `Message`.
+#[derive(Debug, Clone)]
+pub enum RuntimeErrorKind {
+    UndefinedVariable {
+        name: String,
+    },
+    /// A property read on an instance or through `super` that isn't there.
+    UndefinedProperty {
+        name: String,
+    },
+    /// A constant or static field that a class doesn't have.
+    UndefinedStatic {
+        class: String,
+        name: String,
+    },
+    UndefinedModuleMember {
+        module: String,
+        name: String,
+    },
+    ConstantReassignment {
+        name: String,
+    },
+    /// A call with the wrong number of arguments. A variadic callee takes
+    /// `expected` or more.
+    ArityMismatch {
+        expected: usize,
+        variadic: bool,
+        got: usize,
+    },
+    NotCallable,
+    DivisionByZero,
+    IndexOutOfRange {
+        /// What was indexed, like "List".
+        kind: String,
+        index: i64,
+        length: usize,
+    },
+    /// A value thrown by `throw` that nothing caught.
+    UncaughtException(Value),
+    /// Calls nested deeper than the `max_call_depth` option allows.
+    StackOverflow,
+    Message(String),
+}
+
+impl RuntimeErrorKind {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            RuntimeErrorKind::UndefinedVariable { .. } => ErrorCode::UndefinedVariable,
+            RuntimeErrorKind::UndefinedProperty { .. } => ErrorCode::UndefinedProperty,
+            RuntimeErrorKind::UndefinedStatic { .. } => ErrorCode::UndefinedStatic,
+            RuntimeErrorKind::UndefinedModuleMember { .. } => ErrorCode::UndefinedModuleMember,
+            RuntimeErrorKind::ConstantReassignment { .. } => ErrorCode::ConstantReassignedAtRuntime,
+            RuntimeErrorKind::ArityMismatch { .. } => ErrorCode::ArityMismatch,
+            RuntimeErrorKind::NotCallable => ErrorCode::NotCallable,
+            RuntimeErrorKind::DivisionByZero => ErrorCode::DivisionByZero,
+            RuntimeErrorKind::IndexOutOfRange { .. } => ErrorCode::IndexOutOfRange,
+            RuntimeErrorKind::UncaughtException(_) => ErrorCode::UncaughtException,
+            RuntimeErrorKind::StackOverflow => ErrorCode::StackOverflow,
+            RuntimeErrorKind::Message(_) => ErrorCode::RuntimeError,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::UndefinedVariable { name } => {
+                write!(f, "Undefined variable {}.", name)
+            }
+            RuntimeErrorKind::UndefinedProperty { name } => {
+                write!(f, "Undefined property '{}'", name)
+            }
+            RuntimeErrorKind::UndefinedStatic { class, name } => {
+                write!(f, "Undefined property '{}' on class {}.", name, class)
+            }
+            RuntimeErrorKind::UndefinedModuleMember { module, name } => {
+                write!(f, "Module '{}' has no member '{}'.", module, name)
+            }
+            RuntimeErrorKind::ConstantReassignment { name } => {
+                write!(f, "Can't reassign constant '{}'.", name)
+            }
+            RuntimeErrorKind::ArityMismatch {
+                expected,
+                variadic,
+                got,
+            } => {
+                let at_least = if *variadic { "at least " } else { "" };
+                write!(
+                    f,
+                    "Expected {}{} arguments but got {}.",
+                    at_least, expected, got
+                )
+            }
+            RuntimeErrorKind::NotCallable => write!(f, "Can only call functions and classes."),
+            RuntimeErrorKind::DivisionByZero => write!(f, "Cannot divide by zero"),
+            RuntimeErrorKind::IndexOutOfRange {
+                kind,
+                index,
+                length,
+            } => write!(
+                f,
+                "{} index {} out of range for length {}.",
+                kind, index, length
+            ),
+            RuntimeErrorKind::UncaughtException(value) => {
+                write!(f, "Uncaught exception: {}", value)
+            }
+            RuntimeErrorKind::StackOverflow => write!(f, "Stack overflow."),
+            RuntimeErrorKind::Message(message) => write!(f, "{}", message),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Exception {
     RuntimeError(RuntimeError),
     Return(Value),
+    Continue,
+    /// A value raised by a `throw` statement, with the `throw` keyword for
+    /// reporting it if nothing catches it.
+    Throw(Value, Token),
+    /// The program ran past its step or time limit. Unlike the others,
+    /// nothing catches this.
+    Timeout,
+    /// The program's values outgrew its memory limit. Nothing catches this
+    /// either.
+    OutOfMemory,
+    /// The statement hook stopped the program, also past any `catch`.
+    Interrupted,
 }
 
 impl Exception {
     fn runtime_error<T>(token: Token, message: String) -> Result<T, Exception> {
-        Err(Exception::RuntimeError(RuntimeError { token, message }))
+        Exception::error(token, RuntimeErrorKind::Message(message))
     }
-}
 
-impl RuntimeError {
-    fn error(&self) {
-        eprintln!("{}", self.message);
-        eprintln!("[line {}]", self.token.line);
+    fn error<T>(token: Token, kind: RuntimeErrorKind) -> Result<T, Exception> {
+        Err(Exception::RuntimeError(RuntimeError {
+            token,
+            kind: Box::new(kind),
+        }))
+    }
+}
 
-        unsafe {
-            HAD_RUNTIME_ERROR = true;
-        }
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}\n[line {}]",
+            self.code(),
+            self.kind,
+            self.token.line
+        )
     }
 }
 
-fn runtime_error() -> bool {
-    unsafe { HAD_RUNTIME_ERROR }
+impl std::error::Error for RuntimeError {}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LoxError::Compile(diagnostics) => {
+                let lines: Vec<String> = diagnostics.iter().map(Diagnostic::to_string).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            LoxError::Runtime(error) => write!(f, "{}", error),
+            LoxError::Io(error) => write!(f, "{}", error),
+            LoxError::Timeout => write!(
+                f,
+                "{}: Execution limit exceeded.",
+                ErrorCode::ExecutionLimit
+            ),
+            LoxError::OutOfMemory => {
+                write!(f, "{}: Memory limit exceeded.", ErrorCode::MemoryLimit)
+            }
+            LoxError::Interrupted => {
+                write!(f, "{}: Execution interrupted.", ErrorCode::Interrupted)
+            }
+        }
+    }
 }
 
-fn check_runtime_error() {
-    unsafe {
-        if HAD_RUNTIME_ERROR {
-            process::exit(70)
+impl std::error::Error for LoxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoxError::Runtime(error) => Some(error),
+            LoxError::Io(error) => Some(error),
+            _ => None,
         }
     }
 }
 
-pub fn print_error(line: usize, location: &str, message: &str) {
-    eprintln!("[line {line}] Error at '{location}': {message}");
-    unsafe { HAD_RUNTIME_ERROR = true }
+pub fn run_prompt(options: InterpreterOptions) {
+    run_prompt_from(stdin_input(), options);
 }
 
-pub fn run_prompt() {
+/// Runs a REPL that reads lines from `input` until it runs out or reads
+/// "exit". Scripts calling `readLine()` read from the same input, and
+/// definitions carry over from one line to the next.
+pub fn run_prompt_from(input: Input, options: InterpreterOptions) {
+    let mut session = Session::with_input(input.clone(), options);
     loop {
         println!("> ");
         let mut user_input = String::new();
-        io::stdin()
-            .read_line(&mut user_input)
-            .expect("Valid user input");
+        match input.borrow_mut().read_line(&mut user_input) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("Could not read input: {}.", err);
+                break;
+            }
+        }
 
         let user_input = user_input.trim();
         if user_input == "exit" {
             break;
         }
-        run(user_input.to_string());
-        unsafe {
-            HAD_RUNTIME_ERROR = false;
+        let result = session.run(user_input);
+        print_warnings(session.interpreter());
+        if let Err(error) = result {
+            eprintln!("{}", error);
         }
     }
 }
 
-pub fn run_file(path: &str) {
-    let file_contents = fs::read_to_string(path).expect("Could not read file");
-    run(file_contents);
-    unsafe {
-        if HAD_RUNTIME_ERROR {
-            process::exit(70);
+/// Scans and parses `source` without resolving or running it.
+pub fn parse(source: &str) -> Result<Vec<Stmt>, LoxError> {
+    stack::run(|| {
+        let reporter = ErrorReporter::new();
+        let mut tokens = Vec::new();
+        Scanner::new(source.to_string(), &mut tokens, &reporter).scan_tokens();
+        if reporter.had_error() {
+            return Err(LoxError::Compile(reporter.take()));
         }
-    }
+        let stmts = Parser::new(&tokens, &reporter, &AstIdGen::new()).parse();
+        if reporter.had_error() {
+            return Err(LoxError::Compile(reporter.take()));
+        }
+        Ok(stmts)
+    })
 }
 
-fn run(source: String) {
-    let mut tokens: Vec<Token> = Vec::new();
-    let mut scanner = Scanner::new(source, &mut tokens);
-    scanner.scan_tokens();
+/// Compiles `source` for the VM backend and lists the bytecode, or `None`
+/// if it uses something the VM can't run yet.
+pub fn disassemble(source: &str) -> Result<Option<String>, LoxError> {
+    let mut interpreter = InterpreterBuilder::sandboxed().build();
+    stack::run(|| {
+        let stmts = interpreter.check(source.to_string())?;
+        Ok(vm::compile(&stmts)
+            .ok()
+            .map(|program| vm::disassemble(&program)))
+    })
+}
 
-    if runtime_error() {
-        process::exit(64);
-    }
-    let mut parser = Parser::new(&mut tokens);
-    let stmts: Vec<Stmt> = parser.parse();
-    let mut interpreter = Interpreter::new();
+/// Lists the bytecode in a `.loxb` file, or `None` if it doesn't hold a
+/// program compiled by this version.
+pub fn disassemble_bytecode(bytes: &[u8]) -> Option<String> {
+    vm::decode(bytes, None).map(|program| vm::disassemble(&program))
+}
+
+/// What running a program printed, any warnings about it and the errors
+/// that stopped it if any.
+pub struct Captured {
+    pub output: String,
+    pub diagnostics: Vec<String>,
+}
 
-    let mut resolver = Resolver::new(&mut interpreter);
-    resolver.resolve_block(&stmts);
+/// Runs `source` in a sandboxed interpreter with no input, collecting its
+/// output instead of writing it to stdout. This suits hosts with no
+/// terminal or file system, such as a page embedding rlox compiled to
+/// WebAssembly.
+pub fn run_captured(source: &str, options: InterpreterOptions) -> Captured {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let mut interpreter = InterpreterBuilder::sandboxed()
+        .options(options)
+        .input(Rc::new(RefCell::new(io::empty())))
+        .output(output.clone())
+        .build();
+    let result = interpreter.run(source.to_string());
+    let mut diagnostics: Vec<String> = interpreter
+        .take_warnings()
+        .iter()
+        .map(Diagnostic::to_string)
+        .collect();
+    match result {
+        Ok(()) => {}
+        Err(LoxError::Compile(errors)) => {
+            diagnostics.extend(errors.iter().map(Diagnostic::to_string))
+        }
+        Err(error) => diagnostics.push(error.to_string()),
+    }
+    let output = String::from_utf8_lossy(&output.borrow()).into_owned();
+    Captured {
+        output,
+        diagnostics,
+    }
+}
 
-    check_runtime_error();
+fn print_warnings(interpreter: &Interpreter) {
+    for warning in interpreter.take_warnings() {
+        eprintln!("{}", warning);
+    }
+}
 
-    interpreter.interpret(&stmts);
+/// Runs the script at `path`, which can read `args` through the `args()`
+/// native. A `.loxb` file is run as the compiled program it holds.
+pub fn run_file(
+    path: &str,
+    args: Vec<String>,
+    options: InterpreterOptions,
+) -> Result<(), LoxError> {
+    let path = Path::new(path);
+    let mut interpreter = Interpreter::with_options(options);
+    interpreter.set_script_path(path);
+    interpreter.set_script_args(args);
+    let result = if path
+        .extension()
+        .is_some_and(|extension| extension == "loxb")
+    {
+        let bytes = fs::read(path).map_err(LoxError::Io)?;
+        interpreter.run_bytecode(&bytes)
+    } else {
+        let file_contents = fs::read_to_string(path).map_err(LoxError::Io)?;
+        if interpreter.options().cache {
+            interpreter.run_cached(file_contents, &path.with_extension("loxb"))
+        } else {
+            stack::run(|| {
+                // Warnings are shown before the program's own output.
+                let stmts = interpreter.check(file_contents)?;
+                print_warnings(&interpreter);
+                interpreter.interpret(&stmts)
+            })
+        }
+    };
+    // Those from imported files, and from compiling for the VM.
+    print_warnings(&interpreter);
+    if interpreter.options().report_cycles {
+        for cycle in interpreter.take_leaked_cycles() {
+            eprintln!("{}", cycle);
+        }
+    }
+    result
 }
@@ -1,20 +1,148 @@
-use rlox::{run_file, run_prompt};
-use std::cmp::Ordering;
-use std::{env, process};
+use rlox::{
+    ast_to_json, disassemble, disassemble_bytecode, parse, run_file, run_prompt, Backend,
+    InterpreterOptions, LoxError,
+};
+use std::{env, fs, io, process};
 
 fn main() {
     // env::set_var("RUST_BACKTRACE", "1");
 
-    let args: Vec<String> = env::args().collect();
+    let mut options = InterpreterOptions::default();
+    let mut ast = false;
+    let mut args = env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "disasm").is_some() {
+        match (args.next(), args.next()) {
+            (Some(script), None) => {
+                if let Err(error) = print_disassembly(&script) {
+                    process::exit(command_exit_code(&script, error));
+                }
+                return;
+            }
+            _ => usage(),
+        }
+    }
+    // Everything after the script path belongs to the script.
+    let script = loop {
+        match args.next() {
+            Some(arg) if arg == "--strict" => options.strict = true,
+            Some(arg) if arg == "--allow-exec" => options.allow_exec = true,
+            Some(arg) if arg == "--ast" => ast = true,
+            Some(arg) if arg == "--backend=tree" => options.backend = Backend::TreeWalk,
+            Some(arg) if arg == "--backend=vm" => options.backend = Backend::Vm,
+            Some(arg) if arg == "--cache" => options.cache = true,
+            Some(arg) if arg == "--report-cycles" => options.report_cycles = true,
+            Some(flag) if flag.starts_with("--") => usage(),
+            script => break script,
+        }
+    };
+
+    match script {
+        None if ast => usage(),
+        None => run_prompt(options),
+        Some(script) if ast => {
+            if let Err(error) = print_ast(&script) {
+                process::exit(command_exit_code(&script, error));
+            }
+        }
+        Some(script) => {
+            if let Err(error) = run_file(&script, args.collect(), options) {
+                process::exit(exit_code(&script, error));
+            }
+        }
+    }
+}
+
+/// Why `--ast` or `disasm` couldn't print a listing of the script.
+enum CommandError {
+    Lox(LoxError),
+    /// The syntax tree couldn't be converted to JSON.
+    Ast(String),
+    /// The script uses features the VM can't compile yet.
+    Unsupported,
+}
+
+impl From<LoxError> for CommandError {
+    fn from(error: LoxError) -> Self {
+        CommandError::Lox(error)
+    }
+}
 
-    match args.len().cmp(&2) {
-        Ordering::Greater => {
-            println!("Usage: rlox [script]");
-            process::exit(64);
+/// Prints the syntax tree of `script` as JSON instead of running it.
+fn print_ast(script: &str) -> Result<(), CommandError> {
+    let source = fs::read_to_string(script).map_err(LoxError::Io)?;
+    let json = ast_to_json(&parse(&source)?, true).map_err(CommandError::Ast)?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Prints the bytecode `script` compiles to instead of running it. A
+/// `.loxb` file is listed as it is.
+fn print_disassembly(script: &str) -> Result<(), CommandError> {
+    let listing = if script.ends_with(".loxb") {
+        let bytes = fs::read(script).map_err(LoxError::Io)?;
+        disassemble_bytecode(&bytes).ok_or_else(|| {
+            LoxError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a program compiled by this version of rlox",
+            ))
+        })?
+    } else {
+        let source = fs::read_to_string(script).map_err(LoxError::Io)?;
+        disassemble(&source)?.ok_or(CommandError::Unsupported)?
+    };
+    println!("{}", listing);
+    Ok(())
+}
+
+/// Reports `error` if it hasn't been already, and picks the exit code from
+/// the BSD sysexits.h conventions.
+fn exit_code(script: &str, error: LoxError) -> i32 {
+    match error {
+        LoxError::Compile(diagnostics) => {
+            for diagnostic in diagnostics {
+                eprintln!("{}", diagnostic);
+            }
+            65
         }
-        Ordering::Equal => run_file(&args[1]),
-        _ => {
-            run_prompt();
+        LoxError::Runtime(error) => {
+            eprintln!("{}", error);
+            70
+        }
+        LoxError::Io(error) => {
+            eprintln!("Could not read '{}': {}.", script, error);
+            66
+        }
+        LoxError::Timeout | LoxError::OutOfMemory | LoxError::Interrupted => {
+            eprintln!("{}", error);
+            70
         }
     }
 }
+
+/// Like `exit_code`, for the errors of `--ast` and `disasm`.
+fn command_exit_code(script: &str, error: CommandError) -> i32 {
+    match error {
+        CommandError::Lox(error) => exit_code(script, error),
+        CommandError::Ast(message) => {
+            eprintln!("{}", message);
+            70
+        }
+        CommandError::Unsupported => {
+            eprintln!("'{}' uses features the VM can't compile yet.", script);
+            65
+        }
+    }
+}
+
+fn usage() -> ! {
+    println!("Usage: rlox [--strict] [--allow-exec] [--backend=tree|vm] [--cache] [--report-cycles] [script [arguments...]]");
+    println!("       rlox --ast script");
+    println!("       rlox disasm script");
+    println!();
+    println!("--backend=vm can't compile these yet, and runs programs using them on the");
+    println!("tree-walker with a warning: class fields, constants and static fields,");
+    println!("getters and setters, lists, tuples, indexing, slices, 'if' and block");
+    println!("expressions, chained comparisons, 'is', 'try', 'assert', 'for-in', 'import',");
+    println!("'yield' and coroutines, rest parameters and named arguments.");
+    process::exit(64);
+}
@@ -0,0 +1,92 @@
+use crate::string::LoxString;
+use crate::sync::{Rc, RefCell};
+use crate::value::Value;
+use std::collections::HashMap;
+
+pub type MapRef = Rc<RefCell<Map>>;
+
+/// A map from keys to values that remembers insertion order, so printing
+/// and iterating over it are reproducible.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    /// Position of each key in `entries`.
+    positions: HashMap<Key, usize>,
+}
+
+/// The hashable subset of values that can be used as keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Nil,
+    Boolean(bool),
+    Int(i64),
+    String(LoxString),
+}
+
+impl Key {
+    fn new(value: &Value) -> Result<Key, String> {
+        match value {
+            Value::Nil => Ok(Key::Nil),
+            Value::Boolean(value) => Ok(Key::Boolean(*value)),
+            Value::Int(value) => Ok(Key::Int(*value)),
+            Value::String(value) => Ok(Key::String(value.clone())),
+            other => Err(format!(
+                "Map keys must be strings, integers, booleans or nil, got {}.",
+                other
+            )),
+        }
+    }
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn entries(&self) -> &[(Value, Value)] {
+        &self.entries
+    }
+
+    /// The values without their keys, in insertion order.
+    pub fn into_values(self) -> impl Iterator<Item = Value> {
+        self.entries.into_iter().map(|(_, value)| value)
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<Value>, String> {
+        let position = self.positions.get(&Key::new(key)?);
+        Ok(position.map(|&position| self.entries[position].1.clone()))
+    }
+
+    pub fn contains_key(&self, key: &Value) -> Result<bool, String> {
+        Ok(self.positions.contains_key(&Key::new(key)?))
+    }
+
+    /// Replacing the value of an existing key keeps its original position.
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), String> {
+        match self.positions.get(&Key::new(&key)?) {
+            Some(&position) => self.entries[position].1 = value,
+            None => {
+                self.positions.insert(Key::new(&key)?, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Result<Option<Value>, String> {
+        let Some(position) = self.positions.remove(&Key::new(key)?) else {
+            return Ok(None);
+        };
+        let (_, value) = self.entries.remove(position);
+        for later in self.positions.values_mut() {
+            if *later > position {
+                *later -= 1;
+            }
+        }
+        Ok(Some(value))
+    }
+}
@@ -0,0 +1,141 @@
+//! A rough measure of the memory a program holds, for the `max_memory`
+//! option. It walks everything reachable from the given environments and
+//! adds up approximate sizes, counting each shared object once.
+
+use crate::environment::EnvRef;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::collections::HashSet;
+use std::mem::size_of;
+
+/// The size counted for `count` values held in a list, tuple or map.
+pub fn elements_size(count: usize) -> usize {
+    count * size_of::<Value>()
+}
+
+/// The size counted for a named field or variable.
+pub fn field_size(name: &str) -> usize {
+    size_of::<String>() + name.len() + size_of::<Value>()
+}
+
+struct Walk {
+    seen: HashSet<usize>,
+    values: Vec<Value>,
+    environments: Vec<EnvRef>,
+    size: usize,
+}
+
+impl Walk {
+    /// Whether the object at `pointer` is new to the walk.
+    fn first_visit<T: ?Sized>(&mut self, pointer: *const T) -> bool {
+        self.seen.insert(pointer as *const () as usize)
+    }
+
+    fn push_values<'a>(&mut self, values: impl Iterator<Item = &'a Value>) {
+        for value in values {
+            self.size += elements_size(1);
+            self.values.push(value.clone());
+        }
+    }
+
+    fn push_entries<'a>(&mut self, entries: impl Iterator<Item = (&'a Symbol, &'a Value)>) {
+        for (name, value) in entries {
+            self.size += field_size(name);
+            self.values.push(value.clone());
+        }
+    }
+
+    fn visit_environment(&mut self, environment: EnvRef) {
+        if !self.first_visit(&*environment) {
+            return;
+        }
+        let environment = environment.borrow();
+        self.push_entries(environment.values());
+        self.push_values(environment.slots().iter());
+        if let Some(enclosing) = &environment.enclosing {
+            self.environments.push(enclosing.clone());
+        }
+    }
+
+    fn visit_value(&mut self, value: Value) {
+        match value {
+            Value::String(string) => self.size += string.len(),
+            Value::BigInt(value) => self.size += value.heap_size(),
+            Value::List(list) => {
+                if self.first_visit(&*list) {
+                    self.push_values(list.borrow().iter());
+                }
+            }
+            Value::Tuple(tuple) => {
+                if self.first_visit(&*tuple) {
+                    self.push_values(tuple.iter());
+                }
+            }
+            Value::Map(map) => {
+                if self.first_visit(&*map) {
+                    for (key, value) in map.borrow().entries() {
+                        self.size += elements_size(2);
+                        self.values.push(key.clone());
+                        self.values.push(value.clone());
+                    }
+                }
+            }
+            Value::ClassInstance(instance) => {
+                if self.first_visit(&*instance) {
+                    let instance = instance.borrow();
+                    self.push_entries(instance.fields());
+                    self.values.push(Value::Class(instance.class().clone()));
+                }
+            }
+            Value::Class(class) => {
+                if self.first_visit(class.statics()) {
+                    self.push_entries(class.statics().borrow().iter());
+                    self.environments.push(class.closure().clone());
+                    for method in class.compiled_methods() {
+                        self.values.push(Value::Closure(method.clone()));
+                    }
+                }
+            }
+            Value::Function(function) => self.environments.push(function.closure().clone()),
+            Value::Closure(closure) => {
+                if self.first_visit(&*closure) {
+                    let values = closure.closed_values();
+                    self.push_values(values.iter());
+                }
+            }
+            Value::Module(module) => {
+                if self.first_visit(&*module) {
+                    self.push_entries(module.members());
+                }
+            }
+            Value::Boolean(_)
+            | Value::Int(_)
+            | Value::Float(_)
+            | Value::Range(_)
+            | Value::NativeFunction(_)
+            | Value::Coroutine(_)
+            | Value::Userdata(_)
+            | Value::Nil => {}
+        }
+    }
+}
+
+/// Approximately how many bytes are reachable from `environments` and
+/// `values`.
+pub fn reachable_size(environments: Vec<EnvRef>, values: Vec<Value>) -> usize {
+    let mut walk = Walk {
+        seen: HashSet::new(),
+        values,
+        environments,
+        size: 0,
+    };
+    loop {
+        if let Some(value) = walk.values.pop() {
+            walk.visit_value(value);
+        } else if let Some(environment) = walk.environments.pop() {
+            walk.visit_environment(environment);
+        } else {
+            return walk.size;
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::symbol::Symbol;
+use crate::sync::Rc;
+use crate::token::Token;
+use crate::value::Value;
+use crate::{Exception, RuntimeErrorKind};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+
+pub type ModuleRef = Rc<Module>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    name: String,
+    members: HashMap<Symbol, Value>,
+}
+
+impl Module {
+    pub fn new(name: &str) -> Self {
+        Module {
+            name: name.to_string(),
+            members: HashMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.members.insert(Symbol::from(name), value);
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = (&Symbol, &Value)> {
+        self.members.iter()
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, Exception> {
+        if let Some(value) = self.members.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+
+        Exception::error(
+            name.clone(),
+            RuntimeErrorKind::UndefinedModuleMember {
+                module: self.name.clone(),
+                name: name.lexeme.to_string(),
+            },
+        )
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
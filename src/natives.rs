@@ -0,0 +1,657 @@
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::bigint::BigInt;
+use crate::builder::Capabilities;
+use crate::function::{Callable, NativeError, NativeFunction, NativeResult};
+use crate::interpreter::Interpreter;
+use crate::map::Map;
+use crate::module::Module;
+use crate::value::Value;
+use crate::vm;
+
+#[cfg(feature = "http")]
+mod http;
+pub mod json;
+mod list;
+mod map;
+mod time;
+
+/// Modules available to `import` without touching the file system, leaving
+/// out natives whose capability is disabled.
+pub fn builtin_modules(capabilities: &Capabilities) -> Vec<Module> {
+    let mut modules = vec![
+        math(),
+        string(),
+        gc(),
+        os(capabilities),
+        time::module(),
+        list::module(),
+        map::module(),
+        json::module(),
+    ];
+    if capabilities.file_io {
+        modules.push(fs());
+    }
+    #[cfg(feature = "http")]
+    if capabilities.network {
+        modules.push(http::module());
+    }
+    modules
+}
+
+fn native(arity: usize, callable: fn(&mut Interpreter, Vec<Value>) -> NativeResult) -> Value {
+    Value::NativeFunction(NativeFunction::new(arity, callable))
+}
+
+fn variadic_native(
+    arity: usize,
+    callable: fn(&mut Interpreter, Vec<Value>) -> NativeResult,
+) -> Value {
+    Value::NativeFunction(NativeFunction::variadic(arity, callable))
+}
+
+/// Converts an argument to a string the way `print` does, calling
+/// `toString` on instances.
+fn stringify_arg(
+    interpreter: &mut Interpreter,
+    args: &[Value],
+    index: usize,
+) -> Result<String, NativeError> {
+    Ok(interpreter.stringify(&args[index])?)
+}
+
+/// Calls a function passed as an argument, such as the callback of `map`.
+fn call_arg(interpreter: &mut Interpreter, callee: &Value, args: Vec<Value>) -> NativeResult {
+    let (arity, variadic) = match callee {
+        Value::Function(function) => (function.arity(), function.is_variadic()),
+        Value::NativeFunction(function) => (function.arity, function.variadic),
+        Value::Closure(closure) => (closure.arity(), false),
+        Value::Class(class) => (class.arity(), class.is_variadic()),
+        other => return Err(format!("Can only call functions and classes, got {}.", other).into()),
+    };
+    if args.len() != arity && !(variadic && args.len() > arity) {
+        return Err(format!("Expected {} arguments but got {}.", arity, args.len()).into());
+    }
+    match callee {
+        Value::Function(function) => Ok(function.call(interpreter, args)?),
+        Value::NativeFunction(function) => (function.callable)(interpreter, args),
+        Value::Closure(closure) => Ok(vm::call(interpreter, closure, args)?),
+        Value::Class(class) => class.instantiate(interpreter, args),
+        _ => unreachable!(),
+    }
+}
+
+/// Writes its argument to the interpreter's output without a trailing
+/// newline, flushing so that prompts show up before input is read.
+pub fn write(interpreter: &mut Interpreter, args: Vec<Value>) -> NativeResult {
+    let string = stringify_arg(interpreter, &args, 0)?;
+    let output = interpreter.output();
+    let mut output = output.borrow_mut();
+    output
+        .write_all(string.as_bytes())
+        .and_then(|_| output.flush())
+        .map_err(|err| format!("Could not write output: {}.", err))?;
+    Ok(Value::Nil)
+}
+
+/// Prints its argument to stderr followed by a newline.
+pub fn eprint(interpreter: &mut Interpreter, args: Vec<Value>) -> NativeResult {
+    eprintln!("{}", stringify_arg(interpreter, &args, 0)?);
+    Ok(Value::Nil)
+}
+
+/// Raises a runtime error with the given message, which `try` can catch.
+pub fn error(interpreter: &mut Interpreter, args: Vec<Value>) -> NativeResult {
+    Err(stringify_arg(interpreter, &args, 0)?.into())
+}
+
+/// Formats its arguments printf-style: `%s` for any value, `%d` for
+/// integers, `%f` for numbers with an optional precision as in `%.2f`, and
+/// `%%` for a literal percent sign.
+pub fn format(interpreter: &mut Interpreter, args: Vec<Value>) -> NativeResult {
+    let format = string_arg(&args, 0)?;
+    let mut values = args.iter().enumerate().skip(1);
+    let mut output = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        let mut precision = None;
+        if chars.next_if_eq(&'.').is_some() {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            match digits.parse::<usize>() {
+                Ok(digits) => precision = Some(digits),
+                Err(_) => return Err("Expect digits after '.' in format specifier.".into()),
+            }
+        }
+        let specifier = match chars.next() {
+            Some(specifier) => specifier,
+            None => return Err("Format string ends with '%'.".into()),
+        };
+        if precision.is_some() && specifier != 'f' {
+            return Err(format!("Precision is not supported for '%{}'.", specifier).into());
+        }
+        if specifier == '%' {
+            output.push('%');
+            continue;
+        }
+        if !matches!(specifier, 's' | 'd' | 'f') {
+            return Err(format!("Unknown format specifier '%{}'.", specifier).into());
+        }
+        let Some((index, value)) = values.next() else {
+            return Err("Not enough arguments for format string.".into());
+        };
+        match (specifier, value) {
+            ('s', _) => output.push_str(&stringify_arg(interpreter, &args, index)?),
+            ('d', Value::Int(_) | Value::BigInt(_)) => output.push_str(&value.to_string()),
+            ('f', Value::Int(_) | Value::BigInt(_) | Value::Float(_)) => {
+                let number = value.as_f64().unwrap();
+                if number.is_finite() {
+                    output.push_str(&format!("{:.*}", precision.unwrap_or(6), number));
+                } else {
+                    output.push_str(&value.to_string());
+                }
+            }
+            (specifier, value) => {
+                let expected = if specifier == 'd' {
+                    "an integer"
+                } else {
+                    "a number"
+                };
+                return Err(format!(
+                    "Argument {} for '%{}' must be {}, got {}.",
+                    index + 1,
+                    specifier,
+                    expected,
+                    value
+                )
+                .into());
+            }
+        }
+    }
+    if values.next().is_some() {
+        return Err("Too many arguments for format string.".into());
+    }
+    Ok(Value::String(output.into()))
+}
+
+fn number_arg(args: &[Value], index: usize) -> Result<f64, String> {
+    match args[index].as_f64() {
+        Some(value) => Ok(value),
+        None => Err(format!(
+            "Argument {} must be a number, got {}.",
+            index + 1,
+            args[index]
+        )),
+    }
+}
+
+/// Rounds a number to an int. Ints are already whole and come back as
+/// they are.
+fn round_with(args: &[Value], round: fn(f64) -> f64) -> NativeResult {
+    if let Value::Int(_) | Value::BigInt(_) = &args[0] {
+        return Ok(args[0].clone());
+    }
+    let value = round(number_arg(args, 0)?);
+    if value.is_finite() && value >= i64::MIN as f64 && value < i64::MAX as f64 {
+        Ok(Value::Int(value as i64))
+    } else {
+        Err(format!("{} is too large to convert to an integer.", value).into())
+    }
+}
+
+fn int_arg(args: &[Value], index: usize) -> Result<i64, String> {
+    match &args[index] {
+        Value::Int(value) => Ok(*value),
+        other => Err(format!(
+            "Argument {} must be an integer, got {}.",
+            index + 1,
+            other
+        )),
+    }
+}
+
+fn string_arg(args: &[Value], index: usize) -> Result<String, String> {
+    match &args[index] {
+        Value::String(value) => Ok(value.to_string()),
+        other => Err(format!(
+            "Argument {} must be a string, got {}.",
+            index + 1,
+            other
+        )),
+    }
+}
+
+fn gc() -> Module {
+    let mut module = Module::new("gc");
+    module.define(
+        "collect",
+        native(0, |interpreter, _| {
+            Ok(Value::Int(interpreter.collect_garbage() as i64))
+        }),
+    );
+    module
+}
+
+fn math() -> Module {
+    let mut module = Module::new("math");
+    module.define(
+        "abs",
+        native(1, |_, args| match &args[0] {
+            Value::Int(value) => Ok(match value.checked_abs() {
+                Some(value) => Value::Int(value),
+                None => Value::integer(BigInt::from_i64(*value).abs()),
+            }),
+            Value::BigInt(value) => Ok(Value::integer(value.abs())),
+            _ => Ok(Value::Float(number_arg(&args, 0)?.abs())),
+        }),
+    );
+    module.define("ceil", native(1, |_, args| round_with(&args, f64::ceil)));
+    module.define("floor", native(1, |_, args| round_with(&args, f64::floor)));
+    module.define("round", native(1, |_, args| round_with(&args, f64::round)));
+    module.define(
+        "sqrt",
+        native(1, |_, args| {
+            let value = number_arg(&args, 0)?;
+            if value < 0.0 {
+                return Err("Cannot take the square root of a negative number.".into());
+            }
+            Ok(Value::Float(value.sqrt()))
+        }),
+    );
+    module.define(
+        "sin",
+        native(1, |_, args| Ok(Value::Float(number_arg(&args, 0)?.sin()))),
+    );
+    module.define(
+        "cos",
+        native(1, |_, args| Ok(Value::Float(number_arg(&args, 0)?.cos()))),
+    );
+    module.define(
+        "tan",
+        native(1, |_, args| Ok(Value::Float(number_arg(&args, 0)?.tan()))),
+    );
+    module.define(
+        "atan2",
+        native(2, |_, args| {
+            Ok(Value::Float(
+                number_arg(&args, 0)?.atan2(number_arg(&args, 1)?),
+            ))
+        }),
+    );
+    module.define(
+        "log",
+        native(1, |_, args| {
+            let value = number_arg(&args, 0)?;
+            if value <= 0.0 {
+                return Err("Cannot take the logarithm of a non-positive number.".into());
+            }
+            Ok(Value::Float(value.ln()))
+        }),
+    );
+    module.define(
+        "exp",
+        native(1, |_, args| Ok(Value::Float(number_arg(&args, 0)?.exp()))),
+    );
+    module.define("PI", Value::Float(std::f64::consts::PI));
+    module.define("E", Value::Float(std::f64::consts::E));
+    module.define(
+        "pow",
+        native(2, |_, args| match (args[0].as_big(), &args[1]) {
+            (Some(base), Value::Int(exponent)) if *exponent >= 0 => {
+                let exponent =
+                    u32::try_from(*exponent).map_err(|_| "Exponent is too large.".to_string())?;
+                Ok(Value::integer(base.pow(exponent)))
+            }
+            _ => Ok(Value::Float(
+                number_arg(&args, 0)?.powf(number_arg(&args, 1)?),
+            )),
+        }),
+    );
+    module.define(
+        "min",
+        native(2, |_, args| match (args[0].as_big(), args[1].as_big()) {
+            (Some(left), Some(right)) => Ok(Value::integer(left.min(right))),
+            _ => Ok(Value::Float(
+                number_arg(&args, 0)?.min(number_arg(&args, 1)?),
+            )),
+        }),
+    );
+    module.define(
+        "max",
+        native(2, |_, args| match (args[0].as_big(), args[1].as_big()) {
+            (Some(left), Some(right)) => Ok(Value::integer(left.max(right))),
+            _ => Ok(Value::Float(
+                number_arg(&args, 0)?.max(number_arg(&args, 1)?),
+            )),
+        }),
+    );
+    // Bitwise natives treat ints as 64-bit two's complement.
+    module.define(
+        "band",
+        native(2, |_, args| {
+            Ok(Value::Int(int_arg(&args, 0)? & int_arg(&args, 1)?))
+        }),
+    );
+    module.define(
+        "bor",
+        native(2, |_, args| {
+            Ok(Value::Int(int_arg(&args, 0)? | int_arg(&args, 1)?))
+        }),
+    );
+    module.define(
+        "bxor",
+        native(2, |_, args| {
+            Ok(Value::Int(int_arg(&args, 0)? ^ int_arg(&args, 1)?))
+        }),
+    );
+    // Bits shifted past either end are dropped, and shifting right keeps
+    // the sign.
+    module.define(
+        "bshl",
+        native(2, |_, args| {
+            Ok(Value::Int(int_arg(&args, 0)? << shift_arg(&args, 1)?))
+        }),
+    );
+    module.define(
+        "bshr",
+        native(2, |_, args| {
+            Ok(Value::Int(int_arg(&args, 0)? >> shift_arg(&args, 1)?))
+        }),
+    );
+    module
+}
+
+fn shift_arg(args: &[Value], index: usize) -> Result<u32, String> {
+    match int_arg(args, index)? {
+        amount @ 0..=63 => Ok(amount as u32),
+        amount => Err(format!(
+            "Shift amount must be between 0 and 63, got {}.",
+            amount
+        )),
+    }
+}
+
+fn fs() -> Module {
+    let mut module = Module::new("fs");
+    module.define(
+        "readFile",
+        native(1, |_, args| {
+            let path = string_arg(&args, 0)?;
+            fs::read_to_string(&path)
+                .map(Value::from)
+                .map_err(|err| format!("Could not read '{}': {}.", path, err).into())
+        }),
+    );
+    module.define(
+        "writeFile",
+        native(2, |_, args| {
+            let path = string_arg(&args, 0)?;
+            let contents = string_arg(&args, 1)?;
+            fs::write(&path, contents)
+                .map(|_| Value::Nil)
+                .map_err(|err| format!("Could not write '{}': {}.", path, err).into())
+        }),
+    );
+    module.define(
+        "appendFile",
+        native(2, |_, args| {
+            let path = string_arg(&args, 0)?;
+            let contents = string_arg(&args, 1)?;
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(contents.as_bytes()))
+                .map(|_| Value::Nil)
+                .map_err(|err| format!("Could not append to '{}': {}.", path, err).into())
+        }),
+    );
+    module.define(
+        "pathJoin",
+        variadic_native(1, |_, args| {
+            let mut path = PathBuf::from(string_arg(&args, 0)?);
+            for index in 1..args.len() {
+                path.push(string_arg(&args, index)?);
+            }
+            Ok(path_value(&path))
+        }),
+    );
+    // Paths without a final component, such as "/", have an empty basename.
+    module.define(
+        "basename",
+        native(1, |_, args| {
+            let path = string_arg(&args, 0)?;
+            let name = Path::new(&path).file_name().unwrap_or_default();
+            Ok(Value::String(name.to_string_lossy().into_owned().into()))
+        }),
+    );
+    module.define(
+        "dirname",
+        native(1, |_, args| {
+            let path = string_arg(&args, 0)?;
+            Ok(path_value(
+                Path::new(&path).parent().unwrap_or(Path::new("")),
+            ))
+        }),
+    );
+    module.define(
+        "exists",
+        native(1, |_, args| {
+            Ok(Value::Boolean(Path::new(&string_arg(&args, 0)?).exists()))
+        }),
+    );
+    module.define(
+        "isDir",
+        native(1, |_, args| {
+            Ok(Value::Boolean(Path::new(&string_arg(&args, 0)?).is_dir()))
+        }),
+    );
+    // Creates any missing parent directories too, and succeeds if the
+    // directory already exists.
+    module.define(
+        "makeDir",
+        native(1, |_, args| {
+            let path = string_arg(&args, 0)?;
+            fs::create_dir_all(&path)
+                .map(|_| Value::Nil)
+                .map_err(|err| format!("Could not create '{}': {}.", path, err).into())
+        }),
+    );
+    module.define(
+        "listDir",
+        native(1, |_, args| {
+            let path = string_arg(&args, 0)?;
+            let names = list_dir(Path::new(&path))?
+                .into_iter()
+                .map(|(name, _)| Value::String(name.into()))
+                .collect::<Vec<_>>();
+            Ok(Value::from(names))
+        }),
+    );
+    module.define(
+        "walk",
+        native(1, |_, args| {
+            let path = string_arg(&args, 0)?;
+            let mut paths = vec![];
+            walk(Path::new(&path), &mut paths)?;
+            Ok(Value::from(paths))
+        }),
+    );
+    module
+}
+
+/// The names in a directory in sorted order, each with whether it is
+/// itself a directory. Symbolic links are not followed.
+fn list_dir(path: &Path) -> Result<Vec<(String, bool)>, String> {
+    let error = |err: io::Error| format!("Could not list '{}': {}.", path.display(), err);
+    let mut entries = vec![];
+    for entry in fs::read_dir(path).map_err(error)? {
+        let entry = entry.map_err(error)?;
+        let is_dir = entry.file_type().map_err(error)?.is_dir();
+        entries.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Collects every path below `path`, each directory followed by its
+/// contents.
+fn walk(path: &Path, paths: &mut Vec<Value>) -> Result<(), String> {
+    for (name, is_dir) in list_dir(path)? {
+        let child = path.join(name);
+        paths.push(path_value(&child));
+        if is_dir {
+            walk(&child, paths)?;
+        }
+    }
+    Ok(())
+}
+
+fn path_value(path: &Path) -> Value {
+    Value::String(path.to_string_lossy().into_owned().into())
+}
+
+fn string() -> Module {
+    let mut module = Module::new("string");
+    module.define(
+        "toUpper",
+        native(1, |interpreter, args| {
+            let string = string_arg(&args, 0)?;
+            interpreter.allocate(string.len())?;
+            Ok(Value::String(string.to_uppercase().into()))
+        }),
+    );
+    module.define(
+        "toLower",
+        native(1, |interpreter, args| {
+            let string = string_arg(&args, 0)?;
+            interpreter.allocate(string.len())?;
+            Ok(Value::String(string.to_lowercase().into()))
+        }),
+    );
+    module.define(
+        "charAt",
+        native(2, |_, args| {
+            let string = string_arg(&args, 0)?;
+            let index = int_arg(&args, 1)?;
+            usize::try_from(index)
+                .ok()
+                .and_then(|index| string.chars().nth(index))
+                .map(|c| Value::String(c.to_string().into()))
+                .ok_or_else(|| {
+                    format!(
+                        "String index {} out of range for length {}.",
+                        index,
+                        string.chars().count()
+                    )
+                    .into()
+                })
+        }),
+    );
+    module.define(
+        "charCode",
+        native(1, |_, args| {
+            let string = string_arg(&args, 0)?;
+            let mut chars = string.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(Value::Int(c as i64)),
+                _ => {
+                    Err(format!("Argument 1 must be a single character, got '{}'.", string).into())
+                }
+            }
+        }),
+    );
+    module.define(
+        "fromCharCode",
+        native(1, |_, args| {
+            let code = int_arg(&args, 0)?;
+            u32::try_from(code)
+                .ok()
+                .and_then(char::from_u32)
+                .map(|c| Value::String(c.to_string().into()))
+                .ok_or_else(|| format!("{} is not a valid character code.", code).into())
+        }),
+    );
+    module
+}
+
+fn os(capabilities: &Capabilities) -> Module {
+    let mut module = Module::new("os");
+    if capabilities.env {
+        define_env(&mut module);
+    }
+    if capabilities.exec {
+        define_exec(&mut module);
+    }
+    module
+}
+
+fn define_env(module: &mut Module) {
+    module.define(
+        "getEnv",
+        native(1, |_, args| {
+            let name = string_arg(&args, 0)?;
+            Ok(env::var(name).map(Value::from).unwrap_or(Value::Nil))
+        }),
+    );
+    module.define(
+        "setEnv",
+        native(2, |_, args| {
+            let name = string_arg(&args, 0)?;
+            let value = string_arg(&args, 1)?;
+            if name.is_empty() || name.contains(['=', '\0']) {
+                return Err(format!("Invalid environment variable name '{}'.", name).into());
+            }
+            if value.contains('\0') {
+                return Err("Environment variable values can't contain NUL.".into());
+            }
+            env::set_var(name, value);
+            Ok(Value::Nil)
+        }),
+    );
+}
+
+fn define_exec(module: &mut Module) {
+    module.define(
+        "exec",
+        native(1, |interpreter, args| {
+            let command = string_arg(&args, 0)?;
+            if !interpreter.options().allow_exec {
+                return Err("Running commands is disabled. Use --allow-exec to enable it.".into());
+            }
+            let (shell, flag) = if cfg!(windows) {
+                ("cmd", "/C")
+            } else {
+                ("sh", "-c")
+            };
+            let output = Command::new(shell)
+                .args([flag, &command])
+                .output()
+                .map_err(|err| format!("Could not run '{}': {}.", command, err))?;
+            let mut result = Map::new();
+            // A process killed by a signal has no exit code.
+            let status = output
+                .status
+                .code()
+                .map_or(Value::Nil, |code| Value::Int(code as i64));
+            result.insert(Value::String("status".into()), status)?;
+            for (name, bytes) in [("stdout", output.stdout), ("stderr", output.stderr)] {
+                let text = String::from_utf8_lossy(&bytes).into_owned();
+                result.insert(Value::String(name.into()), Value::String(text.into()))?;
+            }
+            Ok(Value::from(result))
+        }),
+    );
+}
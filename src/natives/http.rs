@@ -0,0 +1,137 @@
+// A small blocking HTTP/1.1 client. Only plain `http://` URLs are
+// supported, since TLS would need a dependency.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use super::{native, string_arg, NativeResult};
+use crate::map::Map;
+use crate::module::Module;
+use crate::value::Value;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn module() -> Module {
+    let mut module = Module::new("http");
+    module.define(
+        "httpGet",
+        native(1, |_, args| request("GET", &string_arg(&args, 0)?, None)),
+    );
+    module.define(
+        "httpPost",
+        native(2, |_, args| {
+            let body = string_arg(&args, 1)?;
+            request("POST", &string_arg(&args, 0)?, Some(&body))
+        }),
+    );
+    module
+}
+
+struct Url<'a> {
+    host: &'a str,
+    port: u16,
+    path: &'a str,
+}
+
+fn parse_url(url: &str) -> Result<Url<'_>, String> {
+    let Some(rest) = url.strip_prefix("http://") else {
+        return Err(format!("Only http:// URLs are supported, got '{}'.", url));
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => match port.parse() {
+            Ok(port) => (host, port),
+            Err(_) => return Err(format!("Invalid port in URL '{}'.", url)),
+        },
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("Missing host in URL '{}'.", url));
+    }
+    Ok(Url { host, port, path })
+}
+
+/// Sends a request and returns a map of the response's `status`,
+/// `headers` and `body`. Header names are lowercased.
+fn request(method: &str, url: &str, body: Option<&str>) -> NativeResult {
+    let parsed = parse_url(url)?;
+    let failed = |err: std::io::Error| format!("Request to '{}' failed: {}.", url, err);
+
+    let mut stream = TcpStream::connect((parsed.host, parsed.port)).map_err(failed)?;
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(failed)?;
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: rlox\r\n",
+        method, parsed.path, parsed.host
+    );
+    if let Some(body) = body {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    request.push_str(body.unwrap_or(""));
+    stream.write_all(request.as_bytes()).map_err(failed)?;
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).map_err(failed)?;
+    parse_response(&response).ok_or_else(|| format!("Invalid response from '{}'.", url).into())
+}
+
+fn parse_response(response: &[u8]) -> Option<Value> {
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&response[..header_end]).ok()?;
+    let mut body = response[header_end + 4..].to_vec();
+
+    let mut lines = head.split("\r\n");
+    let status: i64 = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+    let mut headers = Map::new();
+    let mut chunked = false;
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        let name = name.trim().to_lowercase();
+        let value = value.trim().to_string();
+        if name == "transfer-encoding" && value.eq_ignore_ascii_case("chunked") {
+            chunked = true;
+        }
+        headers
+            .insert(Value::String(name.into()), Value::String(value.into()))
+            .ok()?;
+    }
+    if chunked {
+        body = decode_chunked(&body)?;
+    }
+
+    let mut result = Map::new();
+    result
+        .insert(Value::String("status".into()), Value::Int(status))
+        .ok()?;
+    result
+        .insert(Value::String("headers".into()), Value::from(headers))
+        .ok()?;
+    let body = String::from_utf8_lossy(&body).into_owned();
+    result
+        .insert(Value::String("body".into()), Value::String(body.into()))
+        .ok()?;
+    Some(Value::from(result))
+}
+
+fn decode_chunked(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        // Chunk extensions after ';' are ignored.
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
@@ -0,0 +1,430 @@
+// Converts between JSON text and Lox values. Objects become maps, arrays
+// become lists, and numbers without a fraction or exponent become ints.
+
+use super::{native, string_arg, variadic_native, NativeResult};
+use crate::bigint::BigInt;
+use crate::interpreter::Interpreter;
+use crate::map::Map;
+use crate::module::Module;
+use crate::value::{format_float, Value};
+
+/// Deeper nesting is rejected rather than risking a stack overflow.
+const MAX_DEPTH: usize = 512;
+
+pub fn module() -> Module {
+    let mut module = Module::new("json");
+    module.define(
+        "jsonParse",
+        native(1, |_, args| {
+            let text = string_arg(&args, 0)?;
+            let mut parser = Parser {
+                text: &text,
+                position: 0,
+            };
+            parser.skip_whitespace();
+            let value = parser.value(0)?;
+            parser.skip_whitespace();
+            if parser.position < text.len() {
+                return Err(parser.unexpected().into());
+            }
+            Ok(value)
+        }),
+    );
+    module.define("jsonStringify", variadic_native(1, stringify));
+    module
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    /// Byte offset of the next character.
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn unexpected(&self) -> String {
+        match self.peek() {
+            Some(c) => format!(
+                "Invalid JSON: unexpected '{}' at position {}.",
+                c, self.position
+            ),
+            None => "Invalid JSON: unexpected end of input.".to_string(),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.peek() == Some(expected) {
+            self.advance();
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.advance();
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("Invalid JSON: nesting is too deep.".to_string());
+        }
+        match self.peek() {
+            Some('{') => self.object(depth),
+            Some('[') => self.array(depth),
+            Some('"') => self.string().map(Value::from),
+            Some('-' | '0'..='9') => self.number(),
+            Some('t') => self.literal("true", Value::Boolean(true)),
+            Some('f') => self.literal("false", Value::Boolean(false)),
+            Some('n') => self.literal("null", Value::Nil),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        if !self.text[self.position..].starts_with(word) {
+            return Err(self.unexpected());
+        }
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut map = Map::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.advance();
+            return Ok(Value::from(map));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.unexpected());
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            let value = self.value(depth + 1)?;
+            map.insert(Value::String(key.into()), value)?;
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.advance();
+                }
+                Some('}') => {
+                    self.advance();
+                    return Ok(Value::from(map));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut elements = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.advance();
+            return Ok(Value::from(elements));
+        }
+        loop {
+            self.skip_whitespace();
+            elements.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.advance();
+                }
+                Some(']') => {
+                    self.advance();
+                    return Ok(Value::from(elements));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.advance();
+                    return Ok(string);
+                }
+                Some('\\') => {
+                    self.advance();
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            self.advance();
+                            string.push(self.unicode_escape()?);
+                            continue;
+                        }
+                        _ => return Err(self.unexpected()),
+                    };
+                    self.advance();
+                    string.push(escaped);
+                }
+                Some(c) if c >= ' ' => {
+                    self.advance();
+                    string.push(c);
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    /// Reads the hex digits after `\u`, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex_digits()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.invalid_escape());
+        }
+        if !self.text[self.position..].starts_with("\\u") {
+            return Err(self.invalid_escape());
+        }
+        self.position += 2;
+        let low = self.hex_digits()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.invalid_escape());
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+            .ok_or_else(|| self.invalid_escape())
+    }
+
+    fn hex_digits(&mut self) -> Result<u32, String> {
+        let digits = self
+            .text
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .ok_or_else(|| self.invalid_escape())?;
+        self.position += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    fn invalid_escape(&self) -> String {
+        format!(
+            "Invalid JSON: invalid unicode escape at position {}.",
+            self.position
+        )
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.position;
+        if self.peek() == Some('-') {
+            self.advance();
+        }
+        match self.peek() {
+            Some('0') => {
+                self.advance();
+            }
+            Some('1'..='9') => self.digits(),
+            _ => return Err(self.unexpected()),
+        }
+        let mut integer = true;
+        if self.peek() == Some('.') {
+            integer = false;
+            self.advance();
+            if !matches!(self.peek(), Some('0'..='9')) {
+                return Err(self.unexpected());
+            }
+            self.digits();
+        }
+        if let Some('e' | 'E') = self.peek() {
+            integer = false;
+            self.advance();
+            if let Some('+' | '-') = self.peek() {
+                self.advance();
+            }
+            if !matches!(self.peek(), Some('0'..='9')) {
+                return Err(self.unexpected());
+            }
+            self.digits();
+        }
+
+        let text = &self.text[start..self.position];
+        if !integer {
+            return Ok(Value::Float(text.parse().unwrap()));
+        }
+        if let Ok(value) = text.parse() {
+            return Ok(Value::Int(value));
+        }
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        let value = BigInt::parse(digits).unwrap();
+        Ok(Value::integer(if negative { value.neg() } else { value }))
+    }
+
+    fn digits(&mut self) {
+        while let Some('0'..='9') = self.peek() {
+            self.advance();
+        }
+    }
+}
+
+/// `jsonStringify(value)` produces compact JSON, and
+/// `jsonStringify(value, true)` indents it by two spaces.
+fn stringify(_: &mut Interpreter, args: Vec<Value>) -> NativeResult {
+    let pretty = match args.get(1) {
+        None => false,
+        Some(Value::Boolean(pretty)) => *pretty,
+        Some(other) => return Err(format!("Argument 2 must be a boolean, got {}.", other).into()),
+    };
+    if args.len() > 2 {
+        return Err(format!("Expected at most 2 arguments but got {}.", args.len()).into());
+    }
+    Ok(Value::String(to_json(&args[0], pretty)?.into()))
+}
+
+/// Converts `value` to JSON text. Instances become objects of their fields.
+pub fn to_json(value: &Value, pretty: bool) -> Result<String, String> {
+    let mut writer = Writer {
+        output: String::new(),
+        pretty,
+        depth: 0,
+    };
+    writer.value(value)?;
+    Ok(writer.output)
+}
+
+struct Writer {
+    output: String,
+    pretty: bool,
+    depth: usize,
+}
+
+impl Writer {
+    fn value(&mut self, value: &Value) -> Result<(), String> {
+        match value {
+            Value::Nil => self.output.push_str("null"),
+            Value::Boolean(value) => self.output.push_str(&value.to_string()),
+            Value::Int(value) => self.output.push_str(&value.to_string()),
+            Value::BigInt(value) => self.output.push_str(&value.to_string()),
+            Value::Float(value) if value.is_finite() => self.output.push_str(&format_float(*value)),
+            Value::String(value) => self.string(value),
+            Value::List(list) => {
+                // Lists and maps stay mutably borrowed while they are
+                // written, so reaching one again means it contains itself.
+                let elements = list.try_borrow_mut().map_err(|_| cycle_error())?;
+                self.array(&elements)?;
+            }
+            Value::Tuple(tuple) => self.array(tuple)?,
+            Value::Map(map) => {
+                let map = map.try_borrow_mut().map_err(|_| cycle_error())?;
+                self.object(map.entries())?;
+            }
+            Value::ClassInstance(instance) => {
+                let instance = instance.try_borrow_mut().map_err(|_| cycle_error())?;
+                // Fields are kept in a hash map, so sort them to make the
+                // output the same every time.
+                let mut fields: Vec<_> = instance.fields().collect();
+                fields.sort_by_key(|(name, _)| *name);
+                self.nested('{', '}', &fields, |writer, (name, value)| {
+                    writer.member(name, value)
+                })?;
+            }
+            other => return Err(format!("Cannot convert {} to JSON.", other)),
+        }
+        Ok(())
+    }
+
+    fn array(&mut self, elements: &[Value]) -> Result<(), String> {
+        self.nested('[', ']', elements, |writer, element| writer.value(element))
+    }
+
+    fn object(&mut self, entries: &[(Value, Value)]) -> Result<(), String> {
+        self.nested('{', '}', entries, |writer, (key, value)| {
+            let Value::String(key) = key else {
+                return Err(format!("JSON object keys must be strings, got {}.", key));
+            };
+            writer.member(key, value)
+        })
+    }
+
+    fn member(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        self.string(key);
+        self.output.push_str(if self.pretty { ": " } else { ":" });
+        self.value(value)
+    }
+
+    /// Writes the items of an array or object between brackets, one per
+    /// indented line when pretty-printing.
+    fn nested<T>(
+        &mut self,
+        open: char,
+        close: char,
+        items: &[T],
+        mut write: impl FnMut(&mut Writer, &T) -> Result<(), String>,
+    ) -> Result<(), String> {
+        if self.depth >= MAX_DEPTH {
+            return Err("Cannot convert values nested this deeply to JSON.".to_string());
+        }
+        self.output.push(open);
+        self.depth += 1;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.output.push(',');
+            }
+            self.newline();
+            write(self, item)?;
+        }
+        self.depth -= 1;
+        if !items.is_empty() {
+            self.newline();
+        }
+        self.output.push(close);
+        Ok(())
+    }
+
+    fn newline(&mut self) {
+        if self.pretty {
+            self.output.push('\n');
+            self.output.push_str(&"  ".repeat(self.depth));
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.output.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => self.output.push_str("\\\""),
+                '\\' => self.output.push_str("\\\\"),
+                '\n' => self.output.push_str("\\n"),
+                '\r' => self.output.push_str("\\r"),
+                '\t' => self.output.push_str("\\t"),
+                c if c < ' ' => self.output.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.output.push(c),
+            }
+        }
+        self.output.push('"');
+    }
+}
+
+fn cycle_error() -> String {
+    "Cannot convert a value that contains itself to JSON.".to_string()
+}
@@ -0,0 +1,152 @@
+// Lists are shared by reference, so the mutating natives change the list
+// that was passed in rather than returning a new one.
+
+use std::cmp::Ordering;
+
+use super::{call_arg, int_arg, native, NativeResult};
+use crate::interpreter::Interpreter;
+use crate::memory;
+use crate::module::Module;
+use crate::sync::Rc;
+use crate::value::{ListRef, Value};
+
+pub fn module() -> Module {
+    let mut module = Module::new("list");
+    module.define(
+        "len",
+        native(1, |_, args| {
+            let len = match &args[0] {
+                Value::List(list) => list.borrow().len(),
+                Value::Tuple(tuple) => tuple.len(),
+                Value::String(string) => string.chars().count(),
+                other => {
+                    return Err(format!(
+                        "Argument 1 must be a list, tuple or string, got {}.",
+                        other
+                    )
+                    .into())
+                }
+            };
+            Ok(Value::Int(len as i64))
+        }),
+    );
+    module.define(
+        "push",
+        native(2, |interpreter, args| {
+            let list = list_arg(&args, 0)?;
+            interpreter.allocate(memory::elements_size(1))?;
+            list.borrow_mut().push(args[1].clone());
+            Ok(Value::Nil)
+        }),
+    );
+    module.define(
+        "pop",
+        native(1, |_, args| {
+            list_arg(&args, 0)?
+                .borrow_mut()
+                .pop()
+                .ok_or_else(|| "Cannot pop from an empty list.".into())
+        }),
+    );
+    module.define(
+        "insert",
+        native(3, |interpreter, args| {
+            let list = list_arg(&args, 0)?;
+            interpreter.allocate(memory::elements_size(1))?;
+            let mut list = list.borrow_mut();
+            let index = index_arg(&args, 1, list.len(), true)?;
+            list.insert(index, args[2].clone());
+            Ok(Value::Nil)
+        }),
+    );
+    module.define(
+        "removeAt",
+        native(2, |_, args| {
+            let list = list_arg(&args, 0)?;
+            let mut list = list.borrow_mut();
+            let index = index_arg(&args, 1, list.len(), false)?;
+            Ok(list.remove(index))
+        }),
+    );
+    module.define("sort", native(1, sort));
+    module.define(
+        "map",
+        native(2, |interpreter, args| {
+            let values = list_arg(&args, 0)?.borrow().clone();
+            interpreter.allocate(memory::elements_size(2 * values.len()))?;
+            let mut mapped = Vec::with_capacity(values.len());
+            for value in values {
+                mapped.push(call_arg(interpreter, &args[1], vec![value])?);
+            }
+            Ok(Value::from(mapped))
+        }),
+    );
+    module.define(
+        "filter",
+        native(2, |interpreter, args| {
+            let values = list_arg(&args, 0)?.borrow().clone();
+            interpreter.allocate(memory::elements_size(2 * values.len()))?;
+            let mut kept = vec![];
+            for value in values {
+                let keep = call_arg(interpreter, &args[1], vec![value.clone()])?;
+                if Interpreter::is_truthy(&keep) {
+                    kept.push(value);
+                }
+            }
+            Ok(Value::from(kept))
+        }),
+    );
+    module.define(
+        "reduce",
+        native(3, |interpreter, args| {
+            let values = list_arg(&args, 0)?.borrow().clone();
+            let mut accumulator = args[2].clone();
+            for value in values {
+                accumulator = call_arg(interpreter, &args[1], vec![accumulator, value])?;
+            }
+            Ok(accumulator)
+        }),
+    );
+    module
+}
+
+fn list_arg(args: &[Value], index: usize) -> Result<ListRef, String> {
+    match &args[index] {
+        Value::List(list) => Ok(Rc::clone(list)),
+        other => Err(format!(
+            "Argument {} must be a list, got {}.",
+            index + 1,
+            other
+        )),
+    }
+}
+
+/// Reads an index into a list of length `len`. Inserting may also use the
+/// index just past the end.
+fn index_arg(args: &[Value], index: usize, len: usize, past_end: bool) -> Result<usize, String> {
+    let value = int_arg(args, index)?;
+    let limit = len + usize::from(past_end);
+    if value < 0 || value as usize >= limit {
+        return Err(format!(
+            "List index {} out of range for length {}.",
+            value, len
+        ));
+    }
+    Ok(value as usize)
+}
+
+/// Sorts a list of numbers or a list of strings in place.
+fn sort(_: &mut Interpreter, args: Vec<Value>) -> NativeResult {
+    let list = list_arg(&args, 0)?;
+    let mut list = list.borrow_mut();
+    let numbers = list.iter().all(|value| value.as_f64().is_some());
+    let strings = list.iter().all(|value| matches!(value, Value::String(_)));
+    if !numbers && !strings {
+        return Err("Can only sort lists of numbers or lists of strings.".into());
+    }
+    list.sort_by(|left, right| match (left, right) {
+        (Value::String(left), Value::String(right)) => left.cmp(right),
+        _ => Interpreter::compare_numbers(left, right).unwrap_or(Ordering::Equal),
+    });
+    Ok(Value::Nil)
+}
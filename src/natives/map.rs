@@ -0,0 +1,72 @@
+use super::native;
+use crate::map::{Map, MapRef};
+use crate::memory;
+use crate::module::Module;
+use crate::sync::Rc;
+use crate::value::Value;
+
+pub fn module() -> Module {
+    let mut module = Module::new("map");
+    module.define("new", native(0, |_, _| Ok(Value::from(Map::new()))));
+    module.define(
+        "len",
+        native(1, |_, args| {
+            Ok(Value::Int(map_arg(&args, 0)?.borrow().len() as i64))
+        }),
+    );
+    module.define(
+        "keys",
+        native(1, |interpreter, args| {
+            let map = map_arg(&args, 0)?;
+            interpreter.allocate(memory::elements_size(map.borrow().len()))?;
+            let keys: Vec<Value> = map
+                .borrow()
+                .entries()
+                .iter()
+                .map(|(key, _)| key.clone())
+                .collect();
+            Ok(Value::from(keys))
+        }),
+    );
+    module.define(
+        "values",
+        native(1, |interpreter, args| {
+            let map = map_arg(&args, 0)?;
+            interpreter.allocate(memory::elements_size(map.borrow().len()))?;
+            let values: Vec<Value> = map
+                .borrow()
+                .entries()
+                .iter()
+                .map(|(_, value)| value.clone())
+                .collect();
+            Ok(Value::from(values))
+        }),
+    );
+    module.define(
+        "has",
+        native(2, |_, args| {
+            let has = map_arg(&args, 0)?.borrow().contains_key(&args[1])?;
+            Ok(Value::Boolean(has))
+        }),
+    );
+    // Returns the removed value, or nil if the key wasn't there.
+    module.define(
+        "remove",
+        native(2, |_, args| {
+            let removed = map_arg(&args, 0)?.borrow_mut().remove(&args[1])?;
+            Ok(removed.unwrap_or(Value::Nil))
+        }),
+    );
+    module
+}
+
+fn map_arg(args: &[Value], index: usize) -> Result<MapRef, String> {
+    match &args[index] {
+        Value::Map(map) => Ok(Rc::clone(map)),
+        other => Err(format!(
+            "Argument {} must be a map, got {}.",
+            index + 1,
+            other
+        )),
+    }
+}
@@ -0,0 +1,254 @@
+// Dates are handled in UTC only, using the proleptic Gregorian calendar.
+// Conversions between days and dates follow Howard Hinnant's
+// days_from_civil / civil_from_days algorithms.
+
+use super::{native, number_arg, string_arg};
+use crate::clock;
+use crate::module::Module;
+use crate::value::Value;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+pub fn module() -> Module {
+    let mut module = Module::new("time");
+    module.define(
+        "now",
+        native(0, |_, _| {
+            Ok(Value::Float(clock::since_epoch().as_secs_f64()))
+        }),
+    );
+    module.define(
+        "date",
+        native(1, |_, args| {
+            let date = DateTime::from_timestamp(timestamp_arg(&args, 0)?);
+            Ok(Value::Tuple(
+                vec![
+                    Value::Int(date.year),
+                    Value::Int(date.month),
+                    Value::Int(date.day),
+                    Value::Int(date.hour),
+                    Value::Int(date.minute),
+                    Value::Int(date.second),
+                    Value::Int(date.weekday),
+                ]
+                .into(),
+            ))
+        }),
+    );
+    module.define(
+        "formatTime",
+        native(2, |_, args| {
+            let format = string_arg(&args, 0)?;
+            let date = DateTime::from_timestamp(timestamp_arg(&args, 1)?);
+            Ok(Value::String(date.format(&format)?.into()))
+        }),
+    );
+    module.define(
+        "parseTime",
+        native(2, |_, args| {
+            let format = string_arg(&args, 0)?;
+            let text = string_arg(&args, 1)?;
+            parse(&format, &text)
+                .map(Value::Int)
+                .ok_or_else(|| format!("Could not parse '{}' as '{}'.", text, format).into())
+        }),
+    );
+    module
+}
+
+/// Reads a timestamp in seconds, dropping any fraction of a second.
+fn timestamp_arg(args: &[Value], index: usize) -> Result<i64, String> {
+    let seconds = number_arg(args, index)?.floor();
+    if !seconds.is_finite() || seconds.abs() > 1e15 {
+        return Err(format!("Timestamp {} is out of range.", args[index]));
+    }
+    Ok(seconds as i64)
+}
+
+struct DateTime {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    /// 0 for Sunday through 6 for Saturday.
+    weekday: i64,
+    /// 1 for January 1st.
+    day_of_year: i64,
+}
+
+impl DateTime {
+    fn from_timestamp(timestamp: i64) -> Self {
+        let days = timestamp.div_euclid(SECONDS_PER_DAY);
+        let seconds = timestamp.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds % 3600 / 60,
+            second: seconds % 60,
+            // 1970-01-01 was a Thursday.
+            weekday: (days + 4).rem_euclid(7),
+            day_of_year: days - days_from_civil(year, 1, 1) + 1,
+        }
+    }
+
+    /// Formats with strftime-style specifiers: `%Y %m %d %H %M %S %j %a %A
+    /// %b %B %s %%`.
+    fn format(&self, format: &str) -> Result<String, String> {
+        let mut output = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                output.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => output.push_str(&format!("{:04}", self.year)),
+                Some('m') => output.push_str(&format!("{:02}", self.month)),
+                Some('d') => output.push_str(&format!("{:02}", self.day)),
+                Some('H') => output.push_str(&format!("{:02}", self.hour)),
+                Some('M') => output.push_str(&format!("{:02}", self.minute)),
+                Some('S') => output.push_str(&format!("{:02}", self.second)),
+                Some('j') => output.push_str(&format!("{:03}", self.day_of_year)),
+                Some('a') => output.push_str(&WEEKDAYS[self.weekday as usize][..3]),
+                Some('A') => output.push_str(WEEKDAYS[self.weekday as usize]),
+                Some('b') => output.push_str(&MONTHS[self.month as usize - 1][..3]),
+                Some('B') => output.push_str(MONTHS[self.month as usize - 1]),
+                Some('s') => output.push_str(&self.timestamp().to_string()),
+                Some('%') => output.push('%'),
+                Some(other) => return Err(format!("Unknown format specifier '%{}'.", other)),
+                None => return Err("Format string ends with '%'.".to_string()),
+            }
+        }
+        Ok(output)
+    }
+
+    fn timestamp(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + self.hour * 3600
+            + self.minute * 60
+            + self.second
+    }
+}
+
+/// Parses `text` against a format using the numeric specifiers of
+/// `DateTime::format` along with `%b` and `%B` month names. Returns the
+/// timestamp, or `None` if the text doesn't match or names an invalid date.
+fn parse(format: &str, text: &str) -> Option<i64> {
+    let mut date = DateTime::from_timestamp(0);
+    let mut text = text;
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text = text.strip_prefix(c)?;
+            continue;
+        }
+        let specifier = chars.next()?;
+        let field = match specifier {
+            'Y' => &mut date.year,
+            'm' => &mut date.month,
+            'd' => &mut date.day,
+            'H' => &mut date.hour,
+            'M' => &mut date.minute,
+            'S' => &mut date.second,
+            'b' | 'B' => {
+                let (index, rest) = MONTHS.iter().enumerate().find_map(|(index, name)| {
+                    let name = if specifier == 'b' { &name[..3] } else { name };
+                    Some((index, text.strip_prefix(name)?))
+                })?;
+                date.month = index as i64 + 1;
+                text = rest;
+                continue;
+            }
+            '%' => {
+                text = text.strip_prefix('%')?;
+                continue;
+            }
+            _ => return None,
+        };
+        let width = if specifier == 'Y' { 4 } else { 2 };
+        let digits = text
+            .bytes()
+            .take(width)
+            .take_while(u8::is_ascii_digit)
+            .count();
+        if digits == 0 {
+            return None;
+        }
+        *field = text[..digits].parse().ok()?;
+        text = &text[digits..];
+    }
+
+    let valid = text.is_empty()
+        && (1..=12).contains(&date.month)
+        && (1..=days_in_month(date.year, date.month)).contains(&date.day)
+        && date.hour < 24
+        && date.minute < 60
+        && date.second < 60;
+    valid.then(|| date.timestamp())
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let next = if month == 12 {
+        days_from_civil(year + 1, 1, 1)
+    } else {
+        days_from_civil(year, month + 1, 1)
+    };
+    next - days_from_civil(year, month, 1)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
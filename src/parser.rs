@@ -1,16 +1,30 @@
+use crate::diagnostics::{DiagnosticKind, ErrorCode, ErrorReporter};
 use crate::expr::Expr;
-use crate::print_error;
-use crate::stmt::Stmt;
+use crate::stack;
+use crate::stmt::{FunctionDecl, FunctionKind, Stmt};
+use crate::symbol::Symbol;
+use crate::sync::Rc;
 use crate::token::TokenType::*;
 use crate::token::{Literal, Token, TokenType};
-use std::process;
+use std::cell::Cell;
 
-static mut ID: u8 = 0;
+/// Hands out the ids that tell otherwise identical expressions apart. An
+/// interpreter shares one between all of its parses, so expressions from
+/// different runs and imported files get different ids.
+#[derive(Debug, Default)]
+pub struct AstIdGen {
+    last: Cell<usize>,
+}
+
+impl AstIdGen {
+    pub fn new() -> Self {
+        AstIdGen::default()
+    }
 
-fn next_id() -> u8 {
-    unsafe {
-        ID += 1;
-        ID
+    fn next_id(&self) -> usize {
+        let id = self.last.get() + 1;
+        self.last.set(id);
+        id
     }
 }
 
@@ -19,14 +33,40 @@ pub struct ParseError;
 
 type Result<T> = std::result::Result<T, ParseError>;
 
+/// How deeply statements and expressions can nest, counting each operator
+/// of a chain like `1 + 2 + 3` as a level. Every later pass, up to running
+/// the program, recurses once per level, so deeper programs are rejected
+/// here rather than overflowing the Rust stack. Parsing also stops short
+/// of the end of the stack, since a module imported from deep in a program
+/// is parsed on what is left of it.
+const MAX_DEPTH: usize = 1_000;
+
 pub struct Parser<'a> {
     tokens: &'a Vec<Token>,
+    reporter: &'a ErrorReporter,
+    ids: &'a AstIdGen,
     current: usize,
+    /// Levels of nesting around the token being parsed.
+    depth: usize,
+    /// Set once the nesting limit is hit, after which nothing more is parsed
+    /// or reported.
+    too_deep: bool,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+    pub fn new(tokens: &'a Vec<Token>, reporter: &'a ErrorReporter, ids: &'a AstIdGen) -> Self {
+        Self {
+            tokens,
+            reporter,
+            ids,
+            current: 0,
+            depth: 0,
+            too_deep: false,
+        }
+    }
+
+    fn next_id(&self) -> usize {
+        self.ids.next_id()
     }
 
     pub fn parse(&mut self) -> Vec<Stmt> {
@@ -42,7 +82,10 @@ impl<'a> Parser<'a> {
         statements
     }
 
+    /// Errors leave the nesting depth where it was, since they can be
+    /// reported from any level.
     fn declaration(&mut self) -> Option<Stmt> {
+        let depth = self.depth;
         let res;
         if self.match_token(vec![CLASS]) {
             res = self.class_declaration();
@@ -50,13 +93,15 @@ impl<'a> Parser<'a> {
             res = self.function("function");
         } else if self.match_token(vec![VAR]) {
             res = self.var_declaration();
+        } else if self.match_token(vec![CONST]) {
+            res = self.const_declaration();
+        } else if self.match_token(vec![IMPORT]) {
+            res = self.import_declaration();
         } else {
             res = self.statement();
         }
-        match res {
-            Ok(stmt) => Some(stmt),
-            Err(_) => None,
-        }
+        self.depth = depth;
+        res.ok()
     }
 
     fn class_declaration(&mut self) -> Result<Stmt> {
@@ -65,7 +110,7 @@ impl<'a> Parser<'a> {
         if self.match_token(vec![LESS]) {
             self.consume(IDENTIFIER, "Expect superclass name.")?;
             super_class = Some(Expr::Var {
-                uid: next_id(),
+                uid: self.next_id(),
                 name: self.previous().clone(),
             })
         }
@@ -73,8 +118,20 @@ impl<'a> Parser<'a> {
         self.consume(LEFT_BRACE, "Expect '{' before class body")?;
 
         let mut methods = Vec::new();
+        let mut fields = Vec::new();
+        let mut constants = Vec::new();
+        let mut statics = Vec::new();
         while !self.check(&RIGHT_BRACE) && !self.is_at_end() {
-            methods.push(self.function("method")?);
+            if self.match_token(vec![STATIC]) {
+                self.consume(VAR, "Expect 'var' after 'static'.")?;
+                statics.push(self.var_declaration()?);
+            } else if self.match_token(vec![VAR]) {
+                fields.push(self.var_declaration()?);
+            } else if self.match_token(vec![CONST]) {
+                constants.push(self.const_declaration()?);
+            } else {
+                methods.push(self.function("method")?);
+            }
         }
 
         self.consume(RIGHT_BRACE, "Expect '}' after class body.")?;
@@ -82,34 +139,143 @@ impl<'a> Parser<'a> {
         Ok(Stmt::Class {
             name,
             methods,
+            fields: fields.into(),
+            constants,
+            statics,
             super_class,
         })
     }
 
+    fn import_declaration(&mut self) -> Result<Stmt> {
+        let mut members = Vec::new();
+        if self.check(&IDENTIFIER) {
+            loop {
+                members.push(self.consume(IDENTIFIER, "Expect member name.")?.clone());
+                if !self.match_token(vec![COMMA]) {
+                    break;
+                }
+            }
+            // `from` is only special here, so it stays usable as a name.
+            if self.peek().token_type != IDENTIFIER || self.peek().lexeme != "from" {
+                return Err(self.error(
+                    self.peek(),
+                    ErrorCode::ExpectedToken,
+                    "Expect 'from' after imported names.",
+                ));
+            }
+            self.advance();
+        }
+        let path = self
+            .consume(STRING, "Expect module path after 'import'.")?
+            .clone();
+        let mut alias = None;
+        if members.is_empty() && self.check(&IDENTIFIER) && self.peek().lexeme == "as" {
+            self.advance();
+            alias = Some(
+                self.consume(IDENTIFIER, "Expect module name after 'as'.")?
+                    .clone(),
+            );
+        }
+        self.consume(SEMICOLON, "Expect ';' after import.")?;
+
+        let module_name = match &path.literal {
+            Literal::String(value) => value.clone(),
+            _ => unreachable!("string token without a string literal"),
+        };
+        let name = Token {
+            token_type: IDENTIFIER,
+            lexeme: module_name,
+            literal: Literal::None,
+            line: path.line,
+            span: path.span,
+        };
+        Ok(Stmt::Import {
+            path,
+            name,
+            members,
+            alias,
+        })
+    }
+
     fn function(&mut self, kind: &str) -> Result<Stmt> {
         let name = self
             .consume(IDENTIFIER, &format!("Expect {} name", kind))?
             .clone();
+        if kind == "method" && name.lexeme == "set" && self.check(&IDENTIFIER) {
+            return self.setter();
+        }
+        if kind == "method" && self.match_token(vec![LEFT_BRACE]) {
+            let body = self.block()?;
+            return Ok(Stmt::Function(Rc::new(FunctionDecl {
+                name,
+                params: vec![],
+                body,
+                kind: FunctionKind::GETTER,
+                variadic: false,
+            })));
+        }
         self.consume(LEFT_PAREN, &format!("Expect '(' after {} name", kind))?;
+        let (parameters, variadic) = self.parameters()?;
+
+        self.consume(LEFT_BRACE, &format!("Expect  before {} body", kind))?;
+        let body = self.block()?;
+        Ok(Stmt::Function(Rc::new(FunctionDecl {
+            name,
+            params: parameters,
+            body,
+            kind: FunctionKind::FUNCTION,
+            variadic,
+        })))
+    }
+
+    /// Parses a parameter list after its '(' up to and including the ')'.
+    fn parameters(&mut self) -> Result<(Vec<Token>, bool)> {
         let mut parameters = vec![];
+        let mut variadic = false;
         if !self.check(&RIGHT_PAREN) {
-            parameters.push(self.consume(IDENTIFIER, "Expect parameter name.")?.clone());
-            while self.match_token(vec![COMMA]) {
+            loop {
                 if parameters.len() >= 255 {
-                    self.error(self.peek(), "Can't have more than 255 parameters");
+                    self.error(
+                        self.peek(),
+                        ErrorCode::TooManyArguments,
+                        "Can't have more than 255 parameters",
+                    );
                 }
+                variadic = self.match_token(vec![DOT_DOT_DOT]);
                 parameters.push(self.consume(IDENTIFIER, "Expect parameter name.")?.clone());
+                if !self.match_token(vec![COMMA]) {
+                    break;
+                }
+                if variadic {
+                    return Err(self.error(
+                        self.previous(),
+                        ErrorCode::RestParameterNotLast,
+                        "Rest parameter must be last.",
+                    ));
+                }
             }
         }
         self.consume(RIGHT_PAREN, "Expect ')' after parameters.")?;
+        Ok((parameters, variadic))
+    }
 
-        self.consume(LEFT_BRACE, &format!("Expect  before {} body", kind))?;
-        let body = self.block();
-        Ok(Stmt::Function {
+    fn setter(&mut self) -> Result<Stmt> {
+        let name = self.advance().clone();
+        self.consume(LEFT_PAREN, "Expect '(' after setter name.")?;
+        let parameter = self
+            .consume(IDENTIFIER, "Setter must take exactly one parameter.")?
+            .clone();
+        self.consume(RIGHT_PAREN, "Setter must take exactly one parameter.")?;
+
+        self.consume(LEFT_BRACE, "Expect '{' before setter body.")?;
+        let body = self.block()?;
+        Ok(Stmt::Function(Rc::new(FunctionDecl {
             name,
-            params: parameters,
+            params: vec![parameter],
             body,
-        })
+            kind: FunctionKind::SETTER,
+            variadic: false,
+        })))
     }
 
     fn if_statement(&mut self) -> Result<Stmt> {
@@ -132,6 +298,9 @@ impl<'a> Parser<'a> {
 
     fn for_statement(&mut self) -> Result<Stmt> {
         self.consume(LEFT_PAREN, "Expect '(' after 'if'.")?;
+        if self.check(&IDENTIFIER) && self.check_next(&IN) {
+            return self.for_in_statement();
+        }
 
         let initializer: Option<Stmt>;
         if self.match_token(vec![SEMICOLON]) {
@@ -143,8 +312,10 @@ impl<'a> Parser<'a> {
         }
 
         let mut condition: Expr = Expr::Literal {
-            uid: next_id(),
+            uid: self.next_id(),
             value: Literal::Bool(true),
+            span: self.peek().span,
+            line: self.peek().line,
         };
         if !self.check(&SEMICOLON) {
             condition = self.expression()?;
@@ -153,18 +324,13 @@ impl<'a> Parser<'a> {
 
         let mut increment: Option<Expr> = None;
         if !self.check(&RIGHT_PAREN) {
-            increment = Some(self.expression()?);
+            increment = Some(self.sequence()?);
         }
         self.consume(RIGHT_PAREN, "Expect ')' after for clauses.")?;
-        let mut body = self.statement()?;
-
-        if let Some(inc) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(inc)])
-        };
-
-        body = Stmt::While {
+        let mut body = Stmt::While {
             condition,
-            body: Box::new(body),
+            body: Box::new(self.statement()?),
+            increment,
         };
 
         if let Some(init) = initializer {
@@ -173,6 +339,21 @@ impl<'a> Parser<'a> {
         Ok(body)
     }
 
+    fn for_in_statement(&mut self) -> Result<Stmt> {
+        let name = self.advance().clone();
+        let keyword = self.advance().clone();
+        let iterable = self.expression()?;
+        self.consume(RIGHT_PAREN, "Expect ')' after for-in clause.")?;
+        let body = self.statement()?;
+
+        Ok(Stmt::ForIn {
+            name,
+            keyword,
+            iterable,
+            body: Box::new(body),
+        })
+    }
+
     fn while_statement(&mut self) -> Result<Stmt> {
         self.consume(LEFT_PAREN, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
@@ -182,24 +363,36 @@ impl<'a> Parser<'a> {
         Ok(Stmt::While {
             condition,
             body: Box::new(body),
+            increment: None,
         })
     }
 
-    fn block(&mut self) -> Vec<Stmt> {
+    fn do_while_statement(&mut self) -> Result<Stmt> {
+        let body = self.statement()?;
+        self.consume(WHILE, "Expect 'while' after do-while body.")?;
+        self.consume(LEFT_PAREN, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(RIGHT_PAREN, "Expect ')' after condition.")?;
+        self.consume(SEMICOLON, "Expect ';' after do-while condition.")?;
+
+        Ok(Stmt::DoWhile {
+            body: Box::new(body),
+            condition,
+        })
+    }
+
+    /// Errors inside the block are reported and skipped past, the same way
+    /// `parse` does at the top level.
+    fn block(&mut self) -> Result<Vec<Stmt>> {
         let mut statements = Vec::new();
         while !self.check(&RIGHT_BRACE) && !self.is_at_end() {
             match self.declaration() {
-                None => {
-                    process::exit(65);
-                }
+                None => self.synchronize(),
                 Some(stmt) => statements.push(stmt),
             }
         }
-        match self.consume(RIGHT_BRACE, "Expect '}'.") {
-            Ok(_) => {}
-            Err(_err) => process::exit(65),
-        };
-        statements
+        self.consume(RIGHT_BRACE, "Expect '}'.")?;
+        Ok(statements)
     }
 
     fn var_declaration(&mut self) -> Result<Stmt> {
@@ -207,11 +400,8 @@ impl<'a> Parser<'a> {
 
         let mut initializer = None;
         if self.match_token(vec![EQUAL]) {
-            match self.expression() {
-                Ok(expr) => {
-                    initializer = Some(expr);
-                }
-                Err(_) => {}
+            if let Ok(expr) = self.expression() {
+                initializer = Some(expr);
             }
         }
 
@@ -219,7 +409,22 @@ impl<'a> Parser<'a> {
         Ok(Stmt::Var { name, initializer })
     }
 
+    fn const_declaration(&mut self) -> Result<Stmt> {
+        let name = self.consume(IDENTIFIER, "Expect constant name")?.clone();
+        self.consume(EQUAL, "Expect '=' after constant name.")?;
+        let initializer = self.expression()?;
+        self.consume(SEMICOLON, "Expect ';' after constant declaration")?;
+        Ok(Stmt::Const { name, initializer })
+    }
+
     fn statement(&mut self) -> Result<Stmt> {
+        self.nest()?;
+        let stmt = self.statement_at_depth();
+        self.depth -= 1;
+        stmt
+    }
+
+    fn statement_at_depth(&mut self) -> Result<Stmt> {
         if self.match_token(vec![IF]) {
             return self.if_statement();
         } else if self.match_token(vec![PRINT]) {
@@ -227,11 +432,21 @@ impl<'a> Parser<'a> {
         } else if self.match_token(vec![RETURN]) {
             return self.return_statement();
         } else if self.match_token(vec![LEFT_BRACE]) {
-            return Ok(Stmt::Block(self.block()));
+            return Ok(Stmt::Block(self.block()?));
         } else if self.match_token(vec![WHILE]) {
             return self.while_statement();
+        } else if self.match_token(vec![DO]) {
+            return self.do_while_statement();
         } else if self.match_token(vec![FOR]) {
             return self.for_statement();
+        } else if self.match_token(vec![CONTINUE]) {
+            return self.continue_statement();
+        } else if self.match_token(vec![THROW]) {
+            return self.throw_statement();
+        } else if self.match_token(vec![ASSERT]) {
+            return self.assert_statement();
+        } else if self.match_token(vec![TRY]) {
+            return self.try_statement();
         }
         self.expression_statement()
     }
@@ -247,105 +462,308 @@ impl<'a> Parser<'a> {
         Ok(Stmt::Return { keyword, value })
     }
 
+    fn continue_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
+        self.consume(SEMICOLON, "Expect ';' after 'continue'.")?;
+        Ok(Stmt::Continue { keyword })
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(SEMICOLON, "Expect ';' after thrown value.")?;
+        Ok(Stmt::Throw { keyword, value })
+    }
+
+    fn assert_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
+        let condition = self.expression()?;
+        let mut message = None;
+        if self.match_token(vec![COMMA]) {
+            message = Some(self.expression()?);
+        }
+        self.consume(SEMICOLON, "Expect ';' after assertion.")?;
+        Ok(Stmt::Assert {
+            keyword,
+            condition,
+            message,
+        })
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt> {
+        self.consume(LEFT_BRACE, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+
+        let mut handler = None;
+        if self.match_token(vec![CATCH]) {
+            self.consume(LEFT_PAREN, "Expect '(' after 'catch'.")?;
+            let name = self
+                .consume(IDENTIFIER, "Expect exception variable name.")?
+                .clone();
+            self.consume(RIGHT_PAREN, "Expect ')' after exception variable.")?;
+            self.consume(LEFT_BRACE, "Expect '{' before catch body.")?;
+            handler = Some((name, self.block()?));
+        }
+
+        let mut finally = None;
+        if self.match_token(vec![FINALLY]) {
+            self.consume(LEFT_BRACE, "Expect '{' after 'finally'.")?;
+            finally = Some(self.block()?);
+        }
+
+        if handler.is_none() && finally.is_none() {
+            return Err(self.error(
+                self.peek(),
+                ErrorCode::ExpectedToken,
+                "Expect 'catch' or 'finally' after try block.",
+            ));
+        }
+
+        Ok(Stmt::Try {
+            body,
+            handler,
+            finally,
+        })
+    }
+
     fn print_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().clone();
         let expr = self.expression()?;
         self.consume(SEMICOLON, "Expect ';' after value.")?;
-        Ok(Stmt::Print(expr))
+        Ok(Stmt::Print { keyword, expr })
     }
 
     fn expression_statement(&mut self) -> Result<Stmt> {
-        let expr = self.expression()?;
+        let expr = self.sequence()?;
         self.consume(SEMICOLON, "Expect ';' after expression.")?;
         Ok(Stmt::Expression(expr))
     }
 
+    /// Parses tokens that should hold a single expression and nothing
+    /// else, reporting any error.
+    pub fn parse_expression(&mut self) -> Option<Expr> {
+        let expr = self.sequence().ok()?;
+        if !self.is_at_end() {
+            self.error(
+                self.peek(),
+                ErrorCode::ExpectedToken,
+                "Expect end of expression.",
+            );
+            return None;
+        }
+        Some(expr)
+    }
+
     pub fn expression(&mut self) -> Result<Expr> {
-        self.assignment()
+        self.nest()?;
+        let expr = self.assignment();
+        self.depth -= 1;
+        expr
+    }
+
+    /// `a, b, c` in the places a comma can't mean anything else: expression
+    /// statements, parentheses, the trailing expression of a block and a
+    /// `for` increment.
+    fn sequence(&mut self) -> Result<Expr> {
+        let expr = self.expression()?;
+        if !self.check(&COMMA) {
+            return Ok(expr);
+        }
+
+        let mut expressions = vec![expr];
+        while self.match_token(vec![COMMA]) {
+            expressions.push(self.expression()?);
+        }
+        Ok(Expr::Sequence {
+            uid: self.next_id(),
+            expressions,
+        })
     }
 
     fn logical_or(&mut self) -> Result<Expr> {
         let mut expr = self.logical_and()?;
+        let depth = self.depth;
         while self.match_token(vec![OR]) {
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.logical_and()?;
             expr = Expr::Logical {
-                uid: next_id(),
+                uid: self.next_id(),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
             }
         }
+        self.depth = depth;
         Ok(expr)
     }
 
     fn logical_and(&mut self) -> Result<Expr> {
         let mut expr = self.equality()?;
+        let depth = self.depth;
         while self.match_token(vec![AND]) {
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.equality()?;
             expr = Expr::Logical {
-                uid: next_id(),
+                uid: self.next_id(),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
             }
         }
+        self.depth = depth;
         Ok(expr)
     }
 
     fn assignment(&mut self) -> Result<Expr> {
-        let expr = self.logical_or()?;
+        if self.match_token(vec![YIELD]) {
+            return self.yield_expression();
+        }
+        let expr = self.ternary()?;
         if self.match_token(vec![EQUAL]) {
             let equals = self.previous().clone();
+            self.nest()?;
             let value = self.assignment()?;
+            self.depth -= 1;
 
             if let Expr::Var { name, .. } = expr {
                 return Ok(Expr::Assign {
-                    uid: next_id(),
+                    uid: self.next_id(),
                     name,
                     value: Box::new(value),
                 });
             }
             if let Expr::Get { name, object, .. } = expr {
                 return Ok(Expr::Set {
-                    uid: next_id(),
+                    uid: self.next_id(),
                     name,
                     object,
                     value: Box::new(value),
                 });
             }
-            return Err(self.error(&equals, "Invalid assignment target"));
+            if let Expr::Index {
+                object,
+                bracket,
+                index,
+                ..
+            } = expr
+            {
+                return Ok(Expr::SetIndex {
+                    uid: self.next_id(),
+                    object,
+                    bracket,
+                    index,
+                    value: Box::new(value),
+                });
+            }
+            return Err(self.error(
+                &equals,
+                ErrorCode::InvalidAssignmentTarget,
+                "Invalid assignment target",
+            ));
+        }
+        Ok(expr)
+    }
+
+    fn ternary(&mut self) -> Result<Expr> {
+        let expr = self.logical_or()?;
+        if self.match_token(vec![QUESTION]) {
+            let then_branch = self.expression()?;
+            self.consume(
+                COLON,
+                "Expect ':' after then branch of conditional expression.",
+            )?;
+            self.nest()?;
+            let else_branch = self.ternary()?;
+            self.depth -= 1;
+            return Ok(Expr::Ternary {
+                uid: self.next_id(),
+                condition: Box::new(expr),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            });
         }
         Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expr> {
         let mut expr = self.comparison()?;
+        let depth = self.depth;
         while self.match_token(vec![TokenType::BANG_EQUAL, TokenType::EQUAL_EQUAL]) {
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.comparison()?;
             expr = Expr::Binary {
-                uid: next_id(),
+                uid: self.next_id(),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
             }
         }
+        self.depth = depth;
         Ok(expr)
     }
 
     fn comparison(&mut self) -> Result<Expr> {
-        let mut expr = self.term()?;
-        while self.match_token(vec![
+        let relational = vec![
             TokenType::GREATER,
             TokenType::GREATER_EQUAL,
             TokenType::LESS_EQUAL,
             TokenType::LESS,
-        ]) {
+        ];
+        let mut expr = self.range()?;
+        let depth = self.depth;
+        loop {
+            if self.match_token(vec![TokenType::IS]) {
+                self.nest()?;
+                let keyword = self.previous().clone();
+                let class = self.range()?;
+                expr = Expr::Is {
+                    uid: self.next_id(),
+                    object: Box::new(expr),
+                    keyword,
+                    class: Box::new(class),
+                };
+                continue;
+            }
+            if !self.match_token(relational.clone()) {
+                self.depth = depth;
+                return Ok(expr);
+            }
+            self.nest()?;
+
+            let mut operators = vec![self.previous().clone()];
+            let mut operands = vec![expr, self.range()?];
+            while self.match_token(relational.clone()) {
+                operators.push(self.previous().clone());
+                operands.push(self.range()?);
+            }
+            expr = if operators.len() == 1 {
+                let right = operands.pop().unwrap();
+                Expr::Binary {
+                    uid: self.next_id(),
+                    left: Box::new(operands.pop().unwrap()),
+                    operator: operators.pop().unwrap(),
+                    right: Box::new(right),
+                }
+            } else {
+                Expr::Comparison {
+                    uid: self.next_id(),
+                    operands,
+                    operators,
+                }
+            };
+        }
+    }
+
+    fn range(&mut self) -> Result<Expr> {
+        let mut expr = self.term()?;
+        if self.match_token(vec![DOT_DOT, DOT_DOT_EQUAL]) {
             let operator = self.previous().clone();
             let right = self.term()?;
             expr = Expr::Binary {
-                uid: next_id(),
+                uid: self.next_id(),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
@@ -356,40 +774,48 @@ impl<'a> Parser<'a> {
 
     fn term(&mut self) -> Result<Expr> {
         let mut expr = self.factor()?;
+        let depth = self.depth;
         while self.match_token(vec![TokenType::PLUS, TokenType::MINUS]) {
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.factor()?;
             expr = Expr::Binary {
-                uid: next_id(),
+                uid: self.next_id(),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
             }
         }
+        self.depth = depth;
         Ok(expr)
     }
 
     fn factor(&mut self) -> Result<Expr> {
         let mut expr = self.unary()?;
+        let depth = self.depth;
         while self.match_token(vec![TokenType::SLASH, TokenType::STAR]) {
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.unary()?;
             expr = Expr::Binary {
-                uid: next_id(),
+                uid: self.next_id(),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
             }
         }
+        self.depth = depth;
         Ok(expr)
     }
 
     fn unary(&mut self) -> Result<Expr> {
         if self.match_token(vec![TokenType::BANG, TokenType::MINUS]) {
             let operator = self.previous().clone();
+            self.nest()?;
             let right = self.unary()?;
+            self.depth -= 1;
             return Ok(Expr::Unary {
-                uid: next_id(),
+                uid: self.next_id(),
                 operator,
                 right: Box::new(right),
             });
@@ -399,109 +825,375 @@ impl<'a> Parser<'a> {
 
     fn call(&mut self) -> Result<Expr> {
         let mut expr = self.primary()?;
+        let depth = self.depth;
         loop {
             if self.match_token(vec![LEFT_PAREN]) {
+                self.nest()?;
                 expr = self.finish_call(expr)?;
             } else if self.match_token(vec![DOT]) {
+                self.nest()?;
                 let name = self
                     .consume(IDENTIFIER, "Expect property name after .")?
                     .clone();
                 expr = Expr::Get {
-                    uid: next_id(),
+                    uid: self.next_id(),
                     name,
                     object: Box::new(expr),
                 }
+            } else if self.match_token(vec![LEFT_BRACKET]) {
+                self.nest()?;
+                expr = self.finish_index(expr)?;
             } else {
                 break;
             }
         }
+        self.depth = depth;
         Ok(expr)
     }
 
+    fn finish_index(&mut self, object: Expr) -> Result<Expr> {
+        let mut start = None;
+        if !self.check(&COLON) {
+            start = Some(Box::new(self.expression()?));
+        }
+
+        if self.match_token(vec![COLON]) {
+            let mut end = None;
+            if !self.check(&RIGHT_BRACKET) {
+                end = Some(Box::new(self.expression()?));
+            }
+            let bracket = self
+                .consume(RIGHT_BRACKET, "Expect ']' after slice.")?
+                .clone();
+            return Ok(Expr::Slice {
+                uid: self.next_id(),
+                object: Box::new(object),
+                bracket,
+                start,
+                end,
+            });
+        }
+
+        let bracket = self
+            .consume(RIGHT_BRACKET, "Expect ']' after index.")?
+            .clone();
+        Ok(Expr::Index {
+            uid: self.next_id(),
+            object: Box::new(object),
+            bracket,
+            index: start.expect("index expression to be parsed"),
+        })
+    }
+
     fn finish_call(&mut self, callee: Expr) -> Result<Expr> {
         let mut arguments = Vec::new();
+        let mut named = Vec::new();
         if !self.check(&RIGHT_PAREN) {
-            arguments.push(self.expression()?);
-            while self.match_token(vec![COMMA]) {
-                if arguments.len() >= 255 {
-                    self.error(self.peek(), "Can't have more than 255 parameters.");
+            loop {
+                if arguments.len() + named.len() >= 255 {
+                    self.error(
+                        self.peek(),
+                        ErrorCode::TooManyArguments,
+                        "Can't have more than 255 parameters.",
+                    );
+                }
+                if self.check(&IDENTIFIER) && self.check_next(&COLON) {
+                    let name = self.advance().clone();
+                    self.advance();
+                    named.push((name, self.expression()?));
+                } else if !named.is_empty() {
+                    return Err(self.error(
+                        self.peek(),
+                        ErrorCode::PositionalAfterNamed,
+                        "Positional argument can't follow a named argument.",
+                    ));
+                } else {
+                    arguments.push(self.expression()?);
+                }
+                if !self.match_token(vec![COMMA]) {
+                    break;
                 }
-                arguments.push(self.expression()?);
             }
         }
-        let paren = self.consume(RIGHT_PAREN, "Expect ')' after arguments")?;
+        let paren = self
+            .consume(RIGHT_PAREN, "Expect ')' after arguments")?
+            .clone();
         Ok(Expr::Call {
-            uid: next_id(),
+            uid: self.next_id(),
             callee: Box::new(callee),
-            paren: paren.clone(),
+            paren,
             arguments,
+            named,
         })
     }
 
     fn primary(&mut self) -> Result<Expr> {
         if self.match_token(vec![TokenType::FALSE]) {
             return Ok(Expr::Literal {
-                uid: next_id(),
+                uid: self.next_id(),
                 value: Literal::Bool(false),
+                span: self.previous().span,
+                line: self.previous().line,
             });
         }
         if self.match_token(vec![TokenType::TRUE]) {
             return Ok(Expr::Literal {
-                uid: next_id(),
+                uid: self.next_id(),
                 value: Literal::Bool(true),
+                span: self.previous().span,
+                line: self.previous().line,
             });
         }
         if self.match_token(vec![TokenType::NIL]) {
             return Ok(Expr::Literal {
-                uid: next_id(),
+                uid: self.next_id(),
                 value: Literal::None,
+                span: self.previous().span,
+                line: self.previous().line,
             });
         }
         if self.match_token(vec![TokenType::NUMBER, TokenType::STRING]) {
             return Ok(Expr::Literal {
-                uid: next_id(),
+                uid: self.next_id(),
                 value: self.previous().literal.clone(),
+                span: self.previous().span,
+                line: self.previous().line,
             });
         }
         if self.match_token(vec![SUPER]) {
             let keyword = self.previous().clone();
             self.consume(DOT, "Expect '.' after 'super'.")?;
-            let method = self.consume(IDENTIFIER, "Expect superclass method name.")?;
+            let method = self
+                .consume(IDENTIFIER, "Expect superclass method name.")?
+                .clone();
             return Ok(Expr::Super {
-                uid: next_id(),
+                uid: self.next_id(),
                 keyword,
-                method: method.clone(),
+                method,
             });
         }
 
         if self.match_token(vec![THIS]) {
             return Ok(Expr::This {
-                uid: next_id(),
+                uid: self.next_id(),
                 keyword: self.previous().clone(),
             });
         }
+        if self.is_lambda_start() {
+            return self.lambda();
+        }
         if self.match_token(vec![IDENTIFIER]) {
             return Ok(Expr::Var {
-                uid: next_id(),
+                uid: self.next_id(),
                 name: self.previous().clone(),
             });
         }
+        if self.match_token(vec![LEFT_BRACKET]) {
+            return self.list_expression();
+        }
+        if self.match_token(vec![IF]) {
+            return self.if_expression();
+        }
+        if self.match_token(vec![LEFT_BRACE]) {
+            return self.block_expression();
+        }
+        if self.match_token(vec![HASH]) {
+            self.consume(LEFT_PAREN, "Expect '(' after '#'.")?;
+            return self.tuple_expression();
+        }
         if self.match_token(vec![TokenType::LEFT_PAREN]) {
-            let expr = self.expression()?;
+            let expr = self.sequence()?;
             self.consume(TokenType::RIGHT_PAREN, "Expect ')' after expression")?;
             return Ok(Expr::Grouping {
-                uid: next_id(),
+                uid: self.next_id(),
                 expr: Box::new(expr),
             });
         }
-        Err(self.error(self.peek(), "Expression expected"))
+        Err(self.error(
+            self.peek(),
+            ErrorCode::ExpectedExpression,
+            "Expression expected",
+        ))
+    }
+
+    /// Looks past a parenthesized parameter list, or a lone parameter name,
+    /// for the '=>' that makes it a lambda rather than a grouping or tuple.
+    fn is_lambda_start(&self) -> bool {
+        if self.check(&IDENTIFIER) {
+            return self.check_next(&ARROW);
+        }
+        if !self.check(&LEFT_PAREN) {
+            return false;
+        }
+        let mut tokens = self.tokens[self.current + 1..].iter();
+        for token in tokens.by_ref() {
+            match token.token_type {
+                IDENTIFIER | COMMA | DOT_DOT_DOT => continue,
+                RIGHT_PAREN => break,
+                _ => return false,
+            }
+        }
+        matches!(tokens.next(), Some(token) if token.token_type == ARROW)
+    }
+
+    /// `(a, b) => expression` or `a => expression`, a function whose body
+    /// returns the expression.
+    fn lambda(&mut self) -> Result<Expr> {
+        let (params, variadic) = if self.match_token(vec![LEFT_PAREN]) {
+            self.parameters()?
+        } else {
+            (vec![self.advance().clone()], false)
+        };
+        let arrow = self
+            .consume(ARROW, "Expect '=>' after lambda parameters.")?
+            .clone();
+        let value = self.expression()?;
+        let name = Token {
+            token_type: IDENTIFIER,
+            lexeme: Symbol::from("lambda"),
+            literal: Literal::None,
+            line: arrow.line,
+            span: arrow.span,
+        };
+        Ok(Expr::Lambda {
+            uid: self.next_id(),
+            declaration: Rc::new(FunctionDecl {
+                name,
+                params,
+                body: vec![Stmt::Return {
+                    keyword: arrow,
+                    value: Some(value),
+                }],
+                kind: FunctionKind::FUNCTION,
+                variadic,
+            }),
+        })
+    }
+
+    fn yield_expression(&mut self) -> Result<Expr> {
+        let keyword = self.previous().clone();
+        let mut value = None;
+        if !matches!(
+            self.peek().token_type,
+            SEMICOLON | RIGHT_PAREN | RIGHT_BRACKET | RIGHT_BRACE | COMMA
+        ) {
+            value = Some(Box::new(self.assignment()?));
+        }
+        Ok(Expr::Yield {
+            uid: self.next_id(),
+            keyword,
+            value,
+        })
+    }
+
+    fn list_expression(&mut self) -> Result<Expr> {
+        let mut elements = Vec::new();
+        if !self.check(&RIGHT_BRACKET) {
+            elements.push(self.expression()?);
+            while self.match_token(vec![COMMA]) {
+                elements.push(self.expression()?);
+            }
+        }
+        self.consume(RIGHT_BRACKET, "Expect ']' after list elements.")?;
+        Ok(Expr::List {
+            uid: self.next_id(),
+            elements,
+        })
+    }
+
+    /// `#(1, "a")`. Without the `#`, commas in parentheses make a sequence
+    /// instead, so `(f(), g())` is the value of `g()`.
+    fn tuple_expression(&mut self) -> Result<Expr> {
+        let mut elements = Vec::new();
+        while !self.check(&RIGHT_PAREN) {
+            elements.push(self.expression()?);
+            if !self.match_token(vec![COMMA]) {
+                break;
+            }
+        }
+        self.consume(RIGHT_PAREN, "Expect ')' after tuple elements.")?;
+        Ok(Expr::Tuple {
+            uid: self.next_id(),
+            elements,
+        })
+    }
+
+    fn if_expression(&mut self) -> Result<Expr> {
+        self.consume(LEFT_PAREN, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(RIGHT_PAREN, "Expect ')' after if condition.")?;
+
+        let then_branch = self.expression()?;
+        let mut else_branch = None;
+        if self.match_token(vec![ELSE]) {
+            else_branch = Some(Box::new(self.expression()?));
+        }
+
+        Ok(Expr::If {
+            uid: self.next_id(),
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch,
+        })
+    }
+
+    fn block_expression(&mut self) -> Result<Expr> {
+        let mut statements = Vec::new();
+        let mut value = None;
+        while !self.check(&RIGHT_BRACE) && !self.is_at_end() {
+            // Anything introduced by a keyword is parsed as a regular statement,
+            // only a trailing expression without ';' becomes the block's value.
+            if self.is_statement_start() {
+                match self.declaration() {
+                    None => return Err(ParseError),
+                    Some(stmt) => statements.push(stmt),
+                }
+                continue;
+            }
+            let expr = self.sequence()?;
+            if self.match_token(vec![SEMICOLON]) {
+                statements.push(Stmt::Expression(expr));
+            } else {
+                value = Some(Box::new(expr));
+                break;
+            }
+        }
+        self.consume(RIGHT_BRACE, "Expect '}' after block.")?;
+
+        Ok(Expr::Block {
+            uid: self.next_id(),
+            statements,
+            value,
+        })
+    }
+
+    fn is_statement_start(&self) -> bool {
+        matches!(
+            self.peek().token_type,
+            CLASS
+                | FUN
+                | VAR
+                | CONST
+                | FOR
+                | IF
+                | PRINT
+                | RETURN
+                | CONTINUE
+                | THROW
+                | TRY
+                | ASSERT
+                | WHILE
+                | DO
+                | IMPORT
+                | LEFT_BRACE
+        )
     }
 
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token> {
         if self.check(&token_type) {
             return Ok(self.advance());
         }
-        Err(self.error(self.peek(), message))
+        Err(self.error(self.peek(), ErrorCode::ExpectedToken, message))
     }
 
     fn match_token(&mut self, types: Vec<TokenType>) -> bool {
@@ -516,7 +1208,7 @@ impl<'a> Parser<'a> {
 
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
-            self.current = self.current + 1;
+            self.current += 1;
         }
         self.previous()
     }
@@ -532,6 +1224,13 @@ impl<'a> Parser<'a> {
         self.peek().token_type == *token_type
     }
 
+    fn check_next(&self, token_type: &TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == *token_type,
+            None => false,
+        }
+    }
+
     fn is_at_end(&self) -> bool {
         self.peek().token_type == TokenType::EOF
     }
@@ -540,8 +1239,36 @@ impl<'a> Parser<'a> {
         &self.tokens[self.current]
     }
 
-    fn error(&self, token: &Token, message: &str) -> ParseError {
-        print_error(token.line, &token.lexeme, message);
+    /// Counts one more level of nesting, failing once there are too many.
+    /// The rest of the source is skipped then, as recovering inside every
+    /// enclosing level would only report their missing closing tokens.
+    fn nest(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH || stack::is_low() {
+            let error = self.error(
+                self.previous(),
+                ErrorCode::TooDeeplyNested,
+                "Code is nested too deeply.",
+            );
+            self.too_deep = true;
+            self.current = self.tokens.len() - 1;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn error(&self, token: &Token, code: ErrorCode, message: &str) -> ParseError {
+        if self.too_deep {
+            return ParseError {};
+        }
+        self.reporter.error(
+            DiagnosticKind::Parse,
+            code,
+            token.line,
+            token.span,
+            &token.lexeme,
+            message,
+        );
         ParseError {}
     }
 
@@ -552,7 +1279,8 @@ impl<'a> Parser<'a> {
                 return;
             }
             match self.peek().token_type {
-                CLASS | FUN | FOR | IF | PRINT | VAR | RETURN | WHILE => {
+                CLASS | FUN | FOR | IF | PRINT | VAR | CONST | RETURN | WHILE | DO | IMPORT
+                | THROW | TRY | ASSERT => {
                     return;
                 }
                 _ => {}
@@ -0,0 +1,225 @@
+//! Python bindings, built with `--features python` into an extension
+//! module named `rlox`:
+//!
+//! ```python
+//! import rlox
+//!
+//! interpreter = rlox.Interpreter()
+//! interpreter.define("double", 1, lambda x: x * 2)
+//! interpreter.run("var price = double(21);")
+//! interpreter.eval("price + 1")  # 43
+//! ```
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+
+use crate::bigint::BigInt;
+use crate::function::NativeError;
+use crate::map::Map;
+use crate::value::Value;
+use crate::{run_captured, InterpreterBuilder, InterpreterOptions};
+
+create_exception!(
+    rlox,
+    LoxError,
+    PyException,
+    "A Lox program failed to compile or run."
+);
+
+impl From<crate::LoxError> for PyErr {
+    fn from(error: crate::LoxError) -> Self {
+        LoxError::new_err(error.to_string())
+    }
+}
+
+/// An interpreter that keeps its globals between calls. It is sandboxed
+/// unless made with `Interpreter(sandboxed=False)`, so scripts can't reach
+/// files, processes or the network.
+#[pyclass(name = "Interpreter", unsendable)]
+struct PyInterpreter {
+    interpreter: crate::Interpreter,
+}
+
+#[pymethods]
+impl PyInterpreter {
+    #[new]
+    #[pyo3(signature = (sandboxed = true))]
+    fn new(sandboxed: bool) -> Self {
+        let builder = if sandboxed {
+            InterpreterBuilder::sandboxed()
+        } else {
+            InterpreterBuilder::new()
+        };
+        PyInterpreter {
+            interpreter: builder.build(),
+        }
+    }
+
+    /// Runs a program, printing to stdout.
+    fn run(&mut self, source: &str) -> PyResult<()> {
+        Ok(self.interpreter.run(source.to_string())?)
+    }
+
+    /// Evaluates a single expression and returns its value.
+    fn eval(&mut self, py: Python<'_>, source: &str) -> PyResult<PyObject> {
+        let value = self.interpreter.eval(source)?;
+        to_python(py, &value)
+    }
+
+    /// Makes `function` callable from Lox as `name`, taking `arity`
+    /// arguments. A Python exception it raises becomes a Lox runtime error.
+    fn define(&mut self, name: &str, arity: usize, function: PyObject) {
+        self.interpreter.define_native(name, arity, move |_, args| {
+            Python::with_gil(|py| {
+                let args = args
+                    .iter()
+                    .map(|arg| to_python(py, arg))
+                    .collect::<PyResult<Vec<_>>>()?;
+                let result = function.call1(py, PyTuple::new(py, args)?)?;
+                from_python(result.bind(py))
+            })
+            .map_err(|error| NativeError::Message(error.to_string()))
+        });
+    }
+}
+
+/// Runs a program in a fresh sandbox and returns `(output, diagnostics)`:
+/// what it printed, and any warnings and errors, one message each.
+#[pyfunction]
+fn run(source: &str) -> (String, Vec<String>) {
+    let captured = run_captured(source, InterpreterOptions::default());
+    (captured.output, captured.diagnostics)
+}
+
+/// Evaluates a single expression in a fresh sandbox.
+#[pyfunction]
+fn eval(py: Python<'_>, source: &str) -> PyResult<PyObject> {
+    let value = InterpreterBuilder::sandboxed().build().eval(source)?;
+    to_python(py, &value)
+}
+
+#[pymodule]
+fn rlox(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyInterpreter>()?;
+    module.add_function(wrap_pyfunction!(run, module)?)?;
+    module.add_function(wrap_pyfunction!(eval, module)?)?;
+    module.add("LoxError", module.py().get_type::<LoxError>())?;
+    Ok(())
+}
+
+/// Lists and tuples become Python lists and tuples, and maps and instances
+/// become dicts. Functions, classes and other values with no Python form
+/// are a `TypeError`, as is a list or map that contains itself.
+fn to_python(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    let object = match value {
+        Value::Nil => py.None(),
+        Value::Boolean(value) => PyBool::new(py, *value).to_owned().into_any().unbind(),
+        Value::Int(value) => value.into_pyobject(py)?.into_any().unbind(),
+        Value::BigInt(value) => py.get_type::<PyInt>().call1((value.to_string(),))?.unbind(),
+        Value::Float(value) => value.into_pyobject(py)?.into_any().unbind(),
+        Value::String(value) => PyString::new(py, value).into_any().unbind(),
+        Value::List(list) => {
+            // Held mutably while the elements convert, so reaching the list
+            // again means it contains itself.
+            let elements = list.try_borrow_mut().map_err(|_| cycle_error())?;
+            let elements = elements
+                .iter()
+                .map(|element| to_python(py, element))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, elements)?.into_any().unbind()
+        }
+        Value::Tuple(elements) => {
+            let elements = elements
+                .iter()
+                .map(|element| to_python(py, element))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyTuple::new(py, elements)?.into_any().unbind()
+        }
+        Value::Map(map) => {
+            let map = map.try_borrow_mut().map_err(|_| cycle_error())?;
+            let dict = PyDict::new(py);
+            for (key, value) in map.entries() {
+                dict.set_item(to_python(py, key)?, to_python(py, value)?)?;
+            }
+            dict.into_any().unbind()
+        }
+        Value::ClassInstance(instance) => {
+            let instance = instance.try_borrow_mut().map_err(|_| cycle_error())?;
+            let dict = PyDict::new(py);
+            for (name, value) in instance.fields() {
+                dict.set_item(name.as_str(), to_python(py, value)?)?;
+            }
+            dict.into_any().unbind()
+        }
+        other => {
+            return Err(PyTypeError::new_err(format!(
+                "Cannot convert {} to Python.",
+                other
+            )))
+        }
+    };
+    Ok(object)
+}
+
+fn cycle_error() -> PyErr {
+    PyTypeError::new_err("Cannot convert a value that contains itself to Python.")
+}
+
+/// None, booleans, numbers, strings, lists, tuples and dicts convert to the
+/// matching Lox values. Ints too large for an `i64` become big ints.
+fn from_python(object: &Bound<'_, PyAny>) -> PyResult<Value> {
+    if object.is_none() {
+        return Ok(Value::Nil);
+    }
+    // bool is a subclass of int, so it has to be checked first.
+    if let Ok(value) = object.downcast::<PyBool>() {
+        return Ok(Value::Boolean(value.is_true()));
+    }
+    if let Ok(value) = object.downcast::<PyInt>() {
+        if let Ok(value) = value.extract::<i64>() {
+            return Ok(Value::Int(value));
+        }
+        let digits = value.str()?.to_string();
+        let big = match digits.strip_prefix('-') {
+            Some(magnitude) => BigInt::parse(magnitude).map(|value| value.neg()),
+            None => BigInt::parse(&digits),
+        };
+        return big
+            .map(Value::integer)
+            .ok_or_else(|| PyTypeError::new_err(format!("Cannot convert {} to Lox.", digits)));
+    }
+    if let Ok(value) = object.downcast::<PyFloat>() {
+        return Ok(Value::Float(value.value()));
+    }
+    if let Ok(value) = object.downcast::<PyString>() {
+        return Ok(Value::from(value.to_str()?));
+    }
+    if let Ok(list) = object.downcast::<PyList>() {
+        let elements = list
+            .iter()
+            .map(|element| from_python(&element))
+            .collect::<PyResult<Vec<_>>>()?;
+        return Ok(Value::from(elements));
+    }
+    if let Ok(tuple) = object.downcast::<PyTuple>() {
+        let elements = tuple
+            .iter()
+            .map(|element| from_python(&element))
+            .collect::<PyResult<Vec<_>>>()?;
+        return Ok(Value::Tuple(elements.into()));
+    }
+    if let Ok(dict) = object.downcast::<PyDict>() {
+        let mut map = Map::new();
+        for (key, value) in dict.iter() {
+            map.insert(from_python(&key)?, from_python(&value)?)
+                .map_err(PyTypeError::new_err)?;
+        }
+        return Ok(Value::from(map));
+    }
+    Err(PyTypeError::new_err(format!(
+        "Cannot convert {} to Lox.",
+        object.get_type().name()?
+    )))
+}
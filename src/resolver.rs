@@ -1,12 +1,13 @@
 use std::cmp::PartialEq;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use crate::diagnostics::{DiagnosticKind, ErrorCode};
 use crate::expr::Expr;
 use crate::interpreter::Interpreter;
-use crate::stmt::Stmt;
+use crate::stmt::{FunctionDecl, Stmt};
+use crate::symbol::Symbol;
 use crate::token::Token;
-use crate::RuntimeError;
-use crate::{expr, print_error, runtime_error, stmt};
+use crate::{expr, stmt};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum FunctionType {
@@ -23,11 +24,28 @@ pub enum ClassType {
     SUBCLASS,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoopType {
+    NONE,
+    LOOP,
+}
+
+/// A variable declared in a local scope.
+struct Local {
+    /// Where the variable is kept in its scope's environment.
+    slot: usize,
+    /// Whether its initializer has run.
+    defined: bool,
+}
+
 pub struct Resolver<'a> {
     interpreter: &'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<Symbol, Local>>,
+    /// Names declared with `const` in each of `scopes`.
+    constants: Vec<HashSet<Symbol>>,
     current_function: FunctionType,
     current_class: ClassType,
+    current_loop: LoopType,
 }
 
 impl<'a> Resolver<'a> {
@@ -35,8 +53,10 @@ impl<'a> Resolver<'a> {
         Resolver {
             interpreter,
             scopes: Vec::new(),
+            constants: Vec::new(),
             current_function: FunctionType::NONE,
             current_class: ClassType::NONE,
+            current_loop: LoopType::NONE,
         }
     }
 
@@ -48,10 +68,12 @@ impl<'a> Resolver<'a> {
 
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.constants.push(HashSet::new());
     }
 
     fn end_scope(&mut self) {
         self.scopes.pop().expect("stack underflow");
+        self.constants.pop();
     }
 
     pub fn resolve_block(&mut self, stmts: &Vec<Stmt>) {
@@ -64,7 +86,7 @@ impl<'a> Resolver<'a> {
         stmt::Visitor::visit_stmt(self, stmt);
     }
 
-    fn resolve_expr(&mut self, expr: &Expr) {
+    pub fn resolve_expr(&mut self, expr: &Expr) {
         expr::Visitor::visit_expr(self, expr);
     }
 
@@ -76,38 +98,64 @@ impl<'a> Resolver<'a> {
         self.define(name);
     }
 
-    fn peek_scopes_mut(&mut self) -> &mut HashMap<String, bool> {
+    fn visit_const_stmt(&mut self, name: &Token, initializer: &Expr) {
+        self.declare(name);
+        self.resolve_expr(initializer);
+        self.define(name);
+        if let Some(constants) = self.constants.last_mut() {
+            constants.insert(name.lexeme.clone());
+        }
+    }
+
+    fn peek_scopes_mut(&mut self) -> &mut HashMap<Symbol, Local> {
         self.scopes.last_mut().expect("stack is empty")
     }
 
+    /// Adds a variable to the innermost scope, in the next slot.
+    fn add_local(&mut self, name: Symbol, defined: bool) {
+        let scope = self.peek_scopes_mut();
+        let slot = scope.len();
+        scope.insert(name, Local { slot, defined });
+    }
+
     fn declare(&mut self, name: &Token) {
         if self.scopes.is_empty() {
             return;
         }
-        let scope = self.peek_scopes_mut();
-        if scope.contains_key(&name.lexeme) {
-            RuntimeError {
-                token: name.clone(),
-                message: "Already a variable with this name in this scope.".to_string(),
-            }
-            .error();
+        if self.peek_scopes_mut().contains_key(&name.lexeme) {
+            self.interpreter.reporter().error(
+                DiagnosticKind::Resolve,
+                ErrorCode::DuplicateVariable,
+                name.line,
+                name.span,
+                &name.lexeme,
+                "Already a variable with this name in this scope.",
+            );
         }
 
-        scope.insert(name.lexeme.to_string(), false);
+        self.add_local(name.lexeme.clone(), false);
     }
 
     fn define(&mut self, name: &Token) {
         if self.scopes.is_empty() {
             return;
         }
-        self.peek_scopes_mut().insert(name.lexeme.to_string(), true);
+        if let Some(local) = self.peek_scopes_mut().get_mut(&name.lexeme) {
+            local.defined = true;
+        }
+        if let Some(constants) = self.constants.last_mut() {
+            constants.remove(&name.lexeme);
+        }
     }
 
     fn visit_var_expr(&mut self, name: &Token, expr: &Expr) {
         if let Some(scope) = self.scopes.last() {
-            if let Some(false) = scope.get(&name.lexeme) {
-                print_error(
+            if let Some(Local { defined: false, .. }) = scope.get(&name.lexeme) {
+                self.interpreter.reporter().error(
+                    DiagnosticKind::Resolve,
+                    ErrorCode::ReadInOwnInitializer,
                     name.line,
+                    name.span,
                     &name.lexeme,
                     "Can't read local variable in it's own initializer",
                 );
@@ -119,14 +167,32 @@ impl<'a> Resolver<'a> {
 
     fn resolve_local(&mut self, expr: &Expr, name: &Token) {
         for i in (0..self.scopes.len()).rev() {
-            if self.scopes[i].contains_key(&name.lexeme) {
-                self.interpreter.resolve(expr, self.scopes.len() - 1 - i);
+            if let Some(local) = self.scopes[i].get(&name.lexeme) {
+                self.interpreter
+                    .resolve(expr, self.scopes.len() - 1 - i, local.slot);
+                return;
             }
         }
     }
 
     fn visit_assign_expr(&mut self, name: &Token, value: &Expr, expr: &Expr) {
         self.resolve_expr(value);
+        // Globals aren't tracked here, so the environment catches those at runtime.
+        let scope = (0..self.scopes.len())
+            .rev()
+            .find(|&i| self.scopes[i].contains_key(&name.lexeme));
+        if let Some(i) = scope {
+            if self.constants[i].contains(&name.lexeme) {
+                self.interpreter.reporter().error(
+                    DiagnosticKind::Resolve,
+                    ErrorCode::ConstantReassignment,
+                    name.line,
+                    name.span,
+                    &name.lexeme,
+                    &format!("Can't reassign constant '{}'.", name.lexeme),
+                );
+            }
+        }
         self.resolve_local(expr, name);
     }
 
@@ -145,6 +211,8 @@ impl<'a> Resolver<'a> {
     ) {
         let enclosing_function = self.current_function.clone();
         self.current_function = function_type;
+        let enclosing_loop = self.current_loop.clone();
+        self.current_loop = LoopType::NONE;
 
         self.begin_scope();
         for param in params {
@@ -154,6 +222,7 @@ impl<'a> Resolver<'a> {
         self.resolve_block(body);
         self.end_scope();
         self.current_function = enclosing_function;
+        self.current_loop = enclosing_loop;
     }
 
     fn visit_expr_stmt(&mut self, expr: &Expr) {
@@ -179,16 +248,22 @@ impl<'a> Resolver<'a> {
 
     fn visit_return_stmt(&mut self, name: &Token, value: &Option<Expr>) {
         if self.current_function == FunctionType::NONE {
-            RuntimeError {
-                token: name.clone(),
-                message: "Can't return from top-level code".to_string(),
-            }
-            .error();
+            self.interpreter.reporter().error(
+                DiagnosticKind::Resolve,
+                ErrorCode::TopLevelReturn,
+                name.line,
+                name.span,
+                &name.lexeme,
+                "Can't return from top-level code.",
+            );
         }
         if let Some(value) = value {
             if self.current_function == FunctionType::INITIALIZER {
-                print_error(
+                self.interpreter.reporter().error(
+                    DiagnosticKind::Resolve,
+                    ErrorCode::ReturnFromInitializer,
                     name.line,
+                    name.span,
                     &name.lexeme,
                     "Can't return a value from an initializer.",
                 );
@@ -199,9 +274,75 @@ impl<'a> Resolver<'a> {
         }
     }
 
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) {
+    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt, increment: &Option<Expr>) {
+        let enclosing_loop = self.current_loop.clone();
+        self.current_loop = LoopType::LOOP;
+
         self.resolve_expr(condition);
         self.resolve_stmt(body);
+        if let Some(increment) = increment {
+            self.resolve_expr(increment);
+        }
+
+        self.current_loop = enclosing_loop;
+    }
+
+    fn visit_do_while_stmt(&mut self, body: &Stmt, condition: &Expr) {
+        let enclosing_loop = self.current_loop.clone();
+        self.current_loop = LoopType::LOOP;
+        self.resolve_stmt(body);
+        self.current_loop = enclosing_loop;
+
+        self.resolve_expr(condition);
+    }
+
+    fn visit_for_in_stmt(&mut self, name: &Token, iterable: &Expr, body: &Stmt) {
+        self.resolve_expr(iterable);
+
+        let enclosing_loop = self.current_loop.clone();
+        self.current_loop = LoopType::LOOP;
+
+        self.begin_scope();
+        self.declare(name);
+        self.define(name);
+        self.resolve_stmt(body);
+        self.end_scope();
+
+        self.current_loop = enclosing_loop;
+    }
+
+    fn visit_continue_stmt(&mut self, keyword: &Token) {
+        if self.current_loop == LoopType::NONE {
+            self.interpreter.reporter().error(
+                DiagnosticKind::Resolve,
+                ErrorCode::ContinueOutsideLoop,
+                keyword.line,
+                keyword.span,
+                &keyword.lexeme,
+                "Can't use 'continue' outside of a loop.",
+            );
+        }
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        body: &Vec<Stmt>,
+        handler: &Option<(Token, Vec<Stmt>)>,
+        finally: &Option<Vec<Stmt>>,
+    ) {
+        self.visit_block_stmt(body);
+
+        if let Some((name, handler)) = handler {
+            self.begin_scope();
+            self.declare(name);
+            self.define(name);
+            self.resolve_block(handler);
+            self.end_scope();
+        }
+
+        if let Some(finally) = finally {
+            self.visit_block_stmt(finally);
+        }
     }
 
     fn visit_binary_expr(&mut self, left: &Expr, right: &Expr) {
@@ -209,11 +350,14 @@ impl<'a> Resolver<'a> {
         self.resolve_expr(right);
     }
 
-    fn visit_call_expr(&mut self, callee: &Expr, arguments: &Vec<Expr>) {
+    fn visit_call_expr(&mut self, callee: &Expr, arguments: &Vec<Expr>, named: &[(Token, Expr)]) {
         self.resolve_expr(callee);
         for arg in arguments {
             self.resolve_expr(arg);
         }
+        for (_, arg) in named {
+            self.resolve_expr(arg);
+        }
     }
 
     fn visit_grouping_expr(&mut self, expr: &Expr) {
@@ -224,7 +368,100 @@ impl<'a> Resolver<'a> {
         self.resolve_expr(right);
     }
 
-    fn visit_class_stmt(&mut self, name: &Token, methods: &Vec<Stmt>, super_class: &Option<Expr>) {
+    fn visit_yield_expr(&mut self, keyword: &Token, value: &Option<Box<Expr>>) {
+        if self.current_function == FunctionType::NONE {
+            self.interpreter.reporter().error(
+                DiagnosticKind::Resolve,
+                ErrorCode::YieldOutsideFunction,
+                keyword.line,
+                keyword.span,
+                &keyword.lexeme,
+                "Can't use 'yield' outside of a function.",
+            );
+        }
+        if let Some(value) = value {
+            self.resolve_expr(value);
+        }
+    }
+
+    fn visit_lambda_expr(&mut self, declaration: &FunctionDecl) {
+        self.resolve_function(&declaration.params, &declaration.body, FunctionType::FUNCTION);
+    }
+
+    fn visit_list_expr(&mut self, elements: &Vec<Expr>) {
+        for element in elements {
+            self.resolve_expr(element);
+        }
+    }
+
+    fn visit_index_expr(&mut self, object: &Expr, index: &Expr) {
+        self.resolve_expr(object);
+        self.resolve_expr(index);
+    }
+
+    fn visit_set_index_expr(&mut self, object: &Expr, index: &Expr, value: &Expr) {
+        self.resolve_expr(object);
+        self.resolve_expr(index);
+        self.resolve_expr(value);
+    }
+
+    fn visit_slice_expr(
+        &mut self,
+        object: &Expr,
+        start: &Option<Box<Expr>>,
+        end: &Option<Box<Expr>>,
+    ) {
+        self.resolve_expr(object);
+        if let Some(start) = start {
+            self.resolve_expr(start);
+        }
+        if let Some(end) = end {
+            self.resolve_expr(end);
+        }
+    }
+
+    fn visit_is_expr(&mut self, object: &Expr, class: &Expr) {
+        self.resolve_expr(object);
+        self.resolve_expr(class);
+    }
+
+    fn visit_ternary_expr(&mut self, condition: &Expr, then_branch: &Expr, else_branch: &Expr) {
+        self.resolve_expr(condition);
+        self.resolve_expr(then_branch);
+        self.resolve_expr(else_branch);
+    }
+
+    fn visit_if_expr(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Option<Box<Expr>>,
+    ) {
+        self.resolve_expr(condition);
+        self.resolve_expr(then_branch);
+        if let Some(else_branch) = else_branch {
+            self.resolve_expr(else_branch);
+        }
+    }
+
+    fn visit_block_expr(&mut self, statements: &Vec<Stmt>, value: &Option<Box<Expr>>) {
+        self.begin_scope();
+        self.resolve_block(statements);
+        if let Some(value) = value {
+            self.resolve_expr(value);
+        }
+        self.end_scope();
+    }
+
+    fn visit_class_stmt(
+        &mut self,
+        name: &Token,
+        methods: &Vec<Stmt>,
+        fields: &[Stmt],
+        constants: &[Stmt],
+        statics: &[Stmt],
+        super_class: &Option<Expr>,
+    ) {
         let enclosing_class = self.current_class.clone();
         self.current_class = ClassType::CLASS;
 
@@ -234,8 +471,11 @@ impl<'a> Resolver<'a> {
         if let Some(super_class) = super_class {
             if let Expr::Var { name: n, .. } = super_class {
                 if n.lexeme == name.lexeme {
-                    print_error(
+                    self.interpreter.reporter().error(
+                        DiagnosticKind::Resolve,
+                        ErrorCode::SelfInheritance,
                         name.line,
+                        name.span,
                         &name.lexeme,
                         "A class can't inherit from itself.",
                     )
@@ -245,20 +485,53 @@ impl<'a> Resolver<'a> {
             self.resolve_expr(super_class);
 
             self.begin_scope();
-            self.peek_scopes_mut().insert(String::from("super"), true);
+            self.add_local(Symbol::from("super"), true);
+        }
+
+        // Class constants, statics and field initializers run outside any
+        // function. The first two belong to the class itself, so they don't
+        // get `this`.
+        let enclosing_function = self.current_function.clone();
+        self.current_function = FunctionType::NONE;
+        let class_type = std::mem::replace(&mut self.current_class, ClassType::NONE);
+        for constant in constants {
+            if let Stmt::Const { initializer, .. } = constant {
+                self.resolve_expr(initializer);
+            }
+        }
+        for field in statics {
+            if let Stmt::Var {
+                initializer: Some(initializer),
+                ..
+            } = field
+            {
+                self.resolve_expr(initializer);
+            }
         }
+        self.current_class = class_type;
 
         self.begin_scope();
-        self.peek_scopes_mut().insert("this".to_string(), true);
+        self.add_local(Symbol::from("this"), true);
+
+        for field in fields {
+            if let Stmt::Var {
+                initializer: Some(initializer),
+                ..
+            } = field
+            {
+                self.resolve_expr(initializer);
+            }
+        }
+        self.current_function = enclosing_function;
 
         for method in methods {
             match method {
-                Stmt::Function { params, body, name } => {
+                Stmt::Function(function) => {
                     let mut declaration = FunctionType::METHOD;
-                    if name.lexeme == "init" {
+                    if function.name.lexeme == "init" {
                         declaration = FunctionType::INITIALIZER;
                     }
-                    self.resolve_function(params, body, declaration);
+                    self.resolve_function(&function.params, &function.body, declaration);
                 }
                 _ => panic!("Method is not a function"),
             }
@@ -271,19 +544,50 @@ impl<'a> Resolver<'a> {
         self.current_class = enclosing_class;
     }
 
+    fn visit_import_stmt(&mut self, name: &Token, members: &Vec<Token>, alias: &Option<Token>) {
+        if let Some(alias) = alias {
+            self.declare(alias);
+            self.define(alias);
+        } else if !members.is_empty() {
+            for member in members {
+                self.declare(member);
+                self.define(member);
+            }
+        } else if name.lexeme.ends_with(".lox") {
+            // The file's names aren't known until it runs, so they can only
+            // be looked up as globals.
+            if !self.scopes.is_empty() {
+                self.interpreter.reporter().error(
+                    DiagnosticKind::Resolve,
+                    ErrorCode::NestedImport,
+                    name.line,
+                    name.span,
+                    &name.lexeme,
+                    "Can only import a whole file at the top level.",
+                );
+            }
+        } else {
+            self.declare(name);
+            self.define(name);
+        }
+    }
+
     fn visit_get_expr(&mut self, object: &Expr) {
         self.resolve_expr(object);
     }
 
-    fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr) {
+    fn visit_set_expr(&mut self, object: &Expr, _name: &Token, value: &Expr) {
         self.resolve_expr(object);
         self.resolve_expr(value);
     }
 
     fn visit_this_expr(&mut self, keyword: &Token, expr: &Expr) {
         if let ClassType::NONE = self.current_class {
-            print_error(
+            self.interpreter.reporter().error(
+                DiagnosticKind::Resolve,
+                ErrorCode::ThisOutsideClass,
                 keyword.line,
+                keyword.span,
                 &keyword.lexeme,
                 "Can't use 'this' outside of a class",
             );
@@ -293,14 +597,20 @@ impl<'a> Resolver<'a> {
 
     fn visit_super_expr(&mut self, keyword: &Token, expr: &Expr) {
         if self.current_class == ClassType::NONE {
-            print_error(
+            self.interpreter.reporter().error(
+                DiagnosticKind::Resolve,
+                ErrorCode::SuperOutsideClass,
                 keyword.line,
+                keyword.span,
                 &keyword.lexeme,
                 "Can't use 'super' outside of a class.",
             );
         } else if self.current_class != ClassType::SUBCLASS {
-            print_error(
+            self.interpreter.reporter().error(
+                DiagnosticKind::Resolve,
+                ErrorCode::SuperWithoutSuperclass,
                 keyword.line,
+                keyword.span,
                 &keyword.lexeme,
                 "Can't use 'super' in a class with no superclass",
             );
@@ -310,7 +620,7 @@ impl<'a> Resolver<'a> {
 }
 
 impl expr::Visitor<()> for Resolver<'_> {
-    fn visit_expr(&mut self, expr: &Expr) -> () {
+    fn visit_expr(&mut self, expr: &Expr) {
         match expr {
             Expr::Literal { .. } => {}
             Expr::Unary {
@@ -334,8 +644,11 @@ impl expr::Visitor<()> for Resolver<'_> {
                 ..
             } => self.visit_binary_expr(left, right),
             Expr::Call {
-                callee, arguments, ..
-            } => self.visit_call_expr(callee, arguments),
+                callee,
+                arguments,
+                named,
+                ..
+            } => self.visit_call_expr(callee, arguments, named),
             Expr::Get { object, .. } => self.visit_get_expr(object),
             Expr::Set {
                 object,
@@ -344,9 +657,39 @@ impl expr::Visitor<()> for Resolver<'_> {
                 ..
             } => self.visit_set_expr(object, name, value),
             Expr::This { keyword, .. } => self.visit_this_expr(keyword, expr),
-            Expr::Super {
-                keyword, method, ..
-            } => self.visit_super_expr(keyword, expr),
+            Expr::Super { keyword, .. } => self.visit_super_expr(keyword, expr),
+            Expr::List { elements, .. } => self.visit_list_expr(elements),
+            Expr::Tuple { elements, .. } => self.visit_list_expr(elements),
+            Expr::Yield { keyword, value, .. } => self.visit_yield_expr(keyword, value),
+            Expr::Is { object, class, .. } => self.visit_is_expr(object, class),
+            Expr::Comparison { operands, .. } => self.visit_list_expr(operands),
+            Expr::Sequence { expressions, .. } => self.visit_list_expr(expressions),
+            Expr::Lambda { declaration, .. } => self.visit_lambda_expr(declaration),
+            Expr::Index { object, index, .. } => self.visit_index_expr(object, index),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => self.visit_set_index_expr(object, index, value),
+            Expr::Slice {
+                object, start, end, ..
+            } => self.visit_slice_expr(object, start, end),
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => self.visit_ternary_expr(condition, then_branch, else_branch),
+            Expr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => self.visit_if_expr(condition, then_branch, else_branch),
+            Expr::Block {
+                statements, value, ..
+            } => self.visit_block_expr(statements, value),
         }
     }
 }
@@ -355,22 +698,60 @@ impl stmt::Visitor<()> for Resolver<'_> {
     fn visit_stmt(&mut self, stmt: &Stmt) {
         match stmt {
             Stmt::Expression(expr) => self.visit_expr_stmt(expr),
-            Stmt::Print(expr) => self.visit_print_stmt(expr),
+            Stmt::Print { expr, .. } => self.visit_print_stmt(expr),
             Stmt::Var { name, initializer } => self.visit_var_stmt(name, initializer),
+            Stmt::Const { name, initializer } => self.visit_const_stmt(name, initializer),
             Stmt::Block(stmts) => self.visit_block_stmt(stmts),
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
             } => self.visit_if_stmt(condition, then_branch, else_branch),
-            Stmt::While { condition, body } => self.visit_while_stmt(condition, body),
-            Stmt::Function { name, params, body } => self.visit_function_stmt(name, params, body),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => self.visit_while_stmt(condition, body, increment),
+            Stmt::Continue { keyword } => self.visit_continue_stmt(keyword),
+            Stmt::Throw { value, .. } => self.resolve_expr(value),
+            Stmt::Assert {
+                condition, message, ..
+            } => {
+                self.resolve_expr(condition);
+                if let Some(message) = message {
+                    self.resolve_expr(message);
+                }
+            }
+            Stmt::Try {
+                body,
+                handler,
+                finally,
+            } => self.visit_try_stmt(body, handler, finally),
+            Stmt::DoWhile { body, condition } => self.visit_do_while_stmt(body, condition),
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => self.visit_for_in_stmt(name, iterable, body),
+            Stmt::Function(function) => {
+                self.visit_function_stmt(&function.name, &function.params, &function.body)
+            }
             Stmt::Return { keyword, value } => self.visit_return_stmt(keyword, value),
             Stmt::Class {
                 name,
                 methods,
+                fields,
+                constants,
+                statics,
                 super_class,
-            } => self.visit_class_stmt(name, methods, super_class),
+            } => self.visit_class_stmt(name, methods, fields, constants, statics, super_class),
+            Stmt::Import {
+                name,
+                members,
+                alias,
+                ..
+            } => self.visit_import_stmt(name, members, alias),
         }
     }
 }
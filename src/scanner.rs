@@ -1,12 +1,15 @@
 use std::collections::HashMap;
 
-use crate::print_error;
+use crate::bigint::BigInt;
+use crate::diagnostics::{DiagnosticKind, ErrorCode, ErrorReporter};
+use crate::symbol::Symbol;
 use crate::token::TokenType::*;
-use crate::token::{Literal, Token, TokenType};
+use crate::token::{Literal, Span, Token, TokenType};
 
 pub struct Scanner<'a> {
     source: String,
     tokens: &'a mut Vec<Token>,
+    reporter: &'a ErrorReporter,
     start: usize,
     current: usize,
     line: usize,
@@ -14,10 +17,11 @@ pub struct Scanner<'a> {
 }
 
 impl<'a> Scanner<'a> {
-    pub fn new(source: String, tokens: &'a mut Vec<Token>) -> Self {
+    pub fn new(source: String, tokens: &'a mut Vec<Token>, reporter: &'a ErrorReporter) -> Self {
         Scanner {
             source,
             tokens,
+            reporter,
             start: 0,
             current: 0,
             line: 1,
@@ -28,21 +32,34 @@ impl<'a> Scanner<'a> {
     fn initialize_keywords() -> HashMap<String, TokenType> {
         let mut keywords = HashMap::new();
         keywords.insert("and".to_string(), AND);
+        keywords.insert("assert".to_string(), ASSERT);
+        keywords.insert("catch".to_string(), CATCH);
         keywords.insert("class".to_string(), CLASS);
+        keywords.insert("const".to_string(), CONST);
+        keywords.insert("continue".to_string(), CONTINUE);
+        keywords.insert("do".to_string(), DO);
         keywords.insert("else".to_string(), ELSE);
         keywords.insert("false".to_string(), FALSE);
+        keywords.insert("finally".to_string(), FINALLY);
         keywords.insert("for".to_string(), FOR);
         keywords.insert("fun".to_string(), FUN);
         keywords.insert("if".to_string(), IF);
+        keywords.insert("import".to_string(), IMPORT);
+        keywords.insert("in".to_string(), IN);
+        keywords.insert("is".to_string(), IS);
         keywords.insert("nil".to_string(), NIL);
         keywords.insert("or".to_string(), OR);
         keywords.insert("print".to_string(), PRINT);
         keywords.insert("return".to_string(), RETURN);
+        keywords.insert("static".to_string(), STATIC);
         keywords.insert("super".to_string(), SUPER);
         keywords.insert("this".to_string(), THIS);
+        keywords.insert("throw".to_string(), THROW);
         keywords.insert("true".to_string(), TRUE);
+        keywords.insert("try".to_string(), TRY);
         keywords.insert("var".to_string(), VAR);
         keywords.insert("while".to_string(), WHILE);
+        keywords.insert("yield".to_string(), YIELD);
         keywords
     }
 
@@ -53,9 +70,10 @@ impl<'a> Scanner<'a> {
         }
         self.tokens.push(Token {
             token_type: EOF,
-            lexeme: "".to_string(),
+            lexeme: Symbol::from(""),
             literal: Literal::None,
             line: self.line,
+            span: Span::new(self.source.len(), self.source.len()),
         });
     }
 
@@ -66,12 +84,28 @@ impl<'a> Scanner<'a> {
             ')' => self.add_token(RIGHT_PAREN, Literal::None),
             '{' => self.add_token(LEFT_BRACE, Literal::None),
             '}' => self.add_token(RIGHT_BRACE, Literal::None),
+            '[' => self.add_token(LEFT_BRACKET, Literal::None),
+            ']' => self.add_token(RIGHT_BRACKET, Literal::None),
             ',' => self.add_token(COMMA, Literal::None),
-            '.' => self.add_token(DOT, Literal::None),
+            '.' => {
+                let token = if !self.match_char('.') {
+                    DOT
+                } else if self.match_char('=') {
+                    DOT_DOT_EQUAL
+                } else if self.match_char('.') {
+                    DOT_DOT_DOT
+                } else {
+                    DOT_DOT
+                };
+                self.add_token(token, Literal::None)
+            }
             '-' => self.add_token(MINUS, Literal::None),
             '+' => self.add_token(PLUS, Literal::None),
             ';' => self.add_token(SEMICOLON, Literal::None),
             '*' => self.add_token(STAR, Literal::None),
+            '?' => self.add_token(QUESTION, Literal::None),
+            ':' => self.add_token(COLON, Literal::None),
+            '#' => self.add_token(HASH, Literal::None),
             '!' => {
                 let token = if self.match_char('=') {
                     BANG_EQUAL
@@ -83,6 +117,8 @@ impl<'a> Scanner<'a> {
             '=' => {
                 let token = if self.match_char('=') {
                     EQUAL_EQUAL
+                } else if self.match_char('>') {
+                    ARROW
                 } else {
                     EQUAL
                 };
@@ -110,38 +146,31 @@ impl<'a> Scanner<'a> {
                         self.advance();
                     }
                 } else if self.match_char('*') {
-                    while !(self.peek() == '*' && self.peek_next() == '/') && !self.is_at_end() {
-                        if self.peek() == '\n' {
-                            self.line = self.line + 1;
-                        }
-                        self.advance();
-                    }
-                    if self.is_at_end() {
-                        print_error(self.line, "at end", "Unclosed comment");
-                        return;
-                    }
-                    self.advance(); // consume *
-
-                    if self.is_at_end() {
-                        print_error(self.line, "at end", "Unclosed comment");
-                        return;
-                    }
-                    self.advance(); // consume *
+                    self.block_comment();
                 } else {
                     self.add_token(SLASH, Literal::None)
                 }
             }
             '\r' | '\t' | ' ' => {}
-            '\n' => self.line = self.line + 1,
-            '"' => self.string(),
+            '\n' => self.line += 1,
+            '"' => {
+                if self.peek() == '"' && self.peek_next() == '"' {
+                    self.raw_string();
+                } else {
+                    self.string();
+                }
+            }
             _ => {
                 if self.is_digit(c) {
                     self.number();
                 } else if self.is_alpha(c) {
                     self.identifier();
                 } else {
-                    print_error(
+                    self.reporter.error(
+                        DiagnosticKind::Scan,
+                        ErrorCode::UnexpectedCharacter,
                         self.line,
+                        Span::new(self.start, self.current),
                         &c.to_string(),
                         &format!("Unexpected character: {}", c),
                     );
@@ -150,23 +179,182 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    fn block_comment(&mut self) {
+        let start_line = self.line;
+        while !self.is_at_end() && (self.peek() != '*' || self.peek_next() != '/') {
+            if self.peek() == '\n' {
+                self.line += 1;
+            }
+            self.advance();
+        }
+
+        if self.is_at_end() {
+            self.reporter.error(
+                DiagnosticKind::Scan,
+                ErrorCode::UnterminatedComment,
+                start_line,
+                Span::new(self.start, self.current),
+                "at end",
+                "Unterminated comment.",
+            );
+            return;
+        }
+
+        self.advance(); // consume *
+        self.advance(); // consume /
+    }
+
     fn string(&mut self) {
+        let mut value = String::new();
+        let mut valid = true;
         while self.peek() != '"' && !self.is_at_end() {
+            match self.advance() {
+                '\\' => match self.escape() {
+                    Some(c) => value.push(c),
+                    None => valid = false,
+                },
+                c => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    value.push(c);
+                }
+            }
+        }
+
+        if self.is_at_end() {
+            self.reporter.error(
+                DiagnosticKind::Scan,
+                ErrorCode::UnterminatedString,
+                self.line,
+                Span::new(self.start, self.current),
+                "at end",
+                "Unterminated string",
+            );
+            return;
+        }
+
+        self.advance(); // consume "
+
+        if valid {
+            self.add_token(STRING, Literal::String(Symbol::from(value.as_str())));
+        }
+    }
+
+    /// Reads the escape sequence after a backslash. Invalid sequences are
+    /// reported and give `None`.
+    fn escape(&mut self) -> Option<char> {
+        if self.is_at_end() {
+            return None;
+        }
+        let escape_start = self.current - 1;
+        let c = match self.advance() {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            'u' => return self.unicode_escape(escape_start),
+            c => {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                let sequence = &self.source[escape_start..self.current];
+                self.reporter.error(
+                    DiagnosticKind::Scan,
+                    ErrorCode::InvalidEscape,
+                    self.line,
+                    Span::new(escape_start, self.current),
+                    sequence,
+                    "Invalid escape sequence.",
+                );
+                return None;
+            }
+        };
+        Some(c)
+    }
+
+    /// Reads the `{XXXX}` part of a `\u{XXXX}` escape, one to six hex digits
+    /// naming a Unicode scalar value.
+    fn unicode_escape(&mut self, escape_start: usize) -> Option<char> {
+        if !self.match_char('{') {
+            self.reporter.error(
+                DiagnosticKind::Scan,
+                ErrorCode::InvalidUnicodeEscape,
+                self.line,
+                Span::new(escape_start, self.current),
+                "\\u",
+                "Expect '{' after '\\u'.",
+            );
+            return None;
+        }
+        let digits_start = self.current;
+        while self.peek().is_ascii_hexdigit() {
+            self.advance();
+        }
+        let digits_end = self.current;
+        let closed = self.match_char('}');
+        let digits = &self.source[digits_start..digits_end];
+        let sequence = &self.source[escape_start..self.current];
+        if !closed || digits.is_empty() || digits.len() > 6 {
+            self.reporter.error(
+                DiagnosticKind::Scan,
+                ErrorCode::InvalidUnicodeEscape,
+                self.line,
+                Span::new(escape_start, self.current),
+                sequence,
+                "Invalid unicode escape sequence.",
+            );
+            return None;
+        }
+
+        let c = u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32);
+        if c.is_none() {
+            self.reporter.error(
+                DiagnosticKind::Scan,
+                ErrorCode::InvalidCodePoint,
+                self.line,
+                Span::new(escape_start, self.current),
+                sequence,
+                "Invalid unicode code point.",
+            );
+        }
+        c
+    }
+
+    /// Scans a `"""..."""` literal. Its contents are taken verbatim, so it
+    /// may span lines and contain single quote characters.
+    fn raw_string(&mut self) {
+        let start_line = self.line;
+        self.advance(); // consume second "
+        self.advance(); // consume third "
+
+        while !self.is_at_end() && !self.source.as_bytes()[self.current..].starts_with(b"\"\"\"") {
             if self.peek() == '\n' {
-                self.line = self.line + 1;
+                self.line += 1;
             }
             self.advance();
         }
 
         if self.is_at_end() {
-            print_error(self.line, "at end", "Unterminated string");
+            self.reporter.error(
+                DiagnosticKind::Scan,
+                ErrorCode::UnterminatedString,
+                start_line,
+                Span::new(self.start, self.current),
+                "at end",
+                "Unterminated raw string.",
+            );
             return;
         }
 
-        self.advance(); // consume "
+        self.current += 3; // consume closing """
 
-        let value = &self.source[self.start + 1..self.current - 1];
-        self.add_token(STRING, Literal::String(String::from(value)));
+        let value = &self.source[self.start + 3..self.current - 3];
+        self.add_token(STRING, Literal::String(Symbol::from(value)));
     }
 
     fn identifier(&mut self) {
@@ -174,7 +362,7 @@ impl<'a> Scanner<'a> {
             self.advance();
         }
         let text = &self.source[self.start..self.current];
-        let token = self.keywords.get(text).unwrap_or_else(|| &IDENTIFIER);
+        let token = self.keywords.get(text).unwrap_or(&IDENTIFIER);
 
         self.add_token(token.clone(), Literal::None);
     }
@@ -184,41 +372,67 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_alpha(&self, c: char) -> bool {
-        (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_'
+        c.is_ascii_lowercase() || c.is_ascii_uppercase() || c == '_'
     }
 
     fn is_digit(&self, c: char) -> bool {
-        c >= '0' && c <= '9'
+        c.is_ascii_digit()
     }
 
     fn number(&mut self) {
-        while self.is_digit(self.peek()) {
+        self.digits();
+        let mut is_float = false;
+        if self.peek() == '.' && self.is_digit(self.peek_next()) {
+            is_float = true;
             self.advance();
+            self.digits();
         }
-        if self.peek() == '.' && self.is_digit(self.peek_next()) {
+
+        let text = &self.source[self.start..self.current];
+        if !Self::separators_are_valid(text) {
+            self.reporter.error(
+                DiagnosticKind::Scan,
+                ErrorCode::MisplacedDigitSeparator,
+                self.line,
+                Span::new(self.start, self.current),
+                text,
+                "Digit separator '_' must be between two digits.",
+            );
+            return;
+        }
+        let text = text.replace('_', "");
+        if is_float {
+            self.add_token(NUMBER, Literal::Float(text.parse().unwrap()));
+            return;
+        }
+        match text.parse() {
+            Ok(value) => self.add_token(NUMBER, Literal::Int(value)),
+            Err(_) => self.add_token(NUMBER, Literal::BigInt(BigInt::parse(&text).unwrap())),
+        }
+    }
+
+    /// Consumes digits along with any `_` separators between them.
+    fn digits(&mut self) {
+        while self.is_digit(self.peek()) || self.peek() == '_' {
             self.advance();
-            while self.is_digit(self.peek()) {
-                self.advance();
-            }
         }
-        self.add_token(
-            NUMBER,
-            Literal::Number(
-                self.source[self.start..self.current]
-                    .parse::<f64>()
-                    .unwrap(),
-            ),
-        );
+    }
+
+    fn separators_are_valid(text: &str) -> bool {
+        let bytes = text.as_bytes();
+        bytes.iter().enumerate().all(|(i, &b)| {
+            b != b'_'
+                || (bytes[i - 1].is_ascii_digit()
+                    && i + 1 < bytes.len()
+                    && bytes[i + 1].is_ascii_digit())
+        })
     }
 
     fn match_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
-        if char::from(self.source.as_bytes()[self.current]) != expected {
-            return false;
-        }
-        self.current = self.current + 1;
+        self.current += expected.len_utf8();
         true
     }
 
@@ -226,16 +440,20 @@ impl<'a> Scanner<'a> {
         let text = &self.source[self.start..self.current];
         self.tokens.push(Token {
             token_type,
-            lexeme: text.to_string(),
+            lexeme: Symbol::from(text),
             literal,
             line: self.line,
+            span: Span::new(self.start, self.current),
         })
     }
 
+    // `current` is a byte offset that always sits on a char boundary, so
+    // the source can be sliced directly while multi-byte characters are
+    // still read whole.
     fn advance(&mut self) -> char {
-        let c = self.source.as_bytes()[self.current];
-        self.current = self.current + 1;
-        char::from(c)
+        let c = self.peek();
+        self.current += c.len_utf8();
+        c
     }
 
     fn is_at_end(&self) -> bool {
@@ -243,16 +461,12 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        char::from(self.source.as_bytes()[self.current])
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-        char::from(self.source.as_bytes()[self.current + 1])
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 }
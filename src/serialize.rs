@@ -0,0 +1,152 @@
+//! `serde` support for values, so hosts can hand script results to any
+//! serde format or read data back in as values.
+
+use std::fmt;
+use std::fmt::Formatter;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::bigint::BigInt;
+use crate::map::Map;
+use crate::value::Value;
+
+/// Values serialize the way `jsonStringify` writes them: lists and tuples
+/// become sequences, and maps and instances become maps, with an instance's
+/// fields sorted by name. Functions, classes and other values with no data
+/// form are an error, as is a list or map that contains itself.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Boolean(value) => serializer.serialize_bool(*value),
+            Value::Int(value) => serializer.serialize_i64(*value),
+            Value::BigInt(value) => match value.to_string().parse() {
+                Ok(value) => serializer.serialize_i128(value),
+                Err(_) => Err(ser::Error::custom("Integer is too large to serialize.")),
+            },
+            Value::Float(value) => serializer.serialize_f64(*value),
+            Value::String(value) => serializer.serialize_str(value),
+            Value::List(list) => {
+                // Held mutably while the elements are written, so reaching
+                // the list again means it contains itself.
+                let elements = list.try_borrow_mut().map_err(|_| cycle_error::<S>())?;
+                serialize_elements(&elements, serializer)
+            }
+            Value::Tuple(elements) => serialize_elements(elements, serializer),
+            Value::Map(map) => {
+                let map = map.try_borrow_mut().map_err(|_| cycle_error::<S>())?;
+                let mut output = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in map.entries() {
+                    output.serialize_entry(key, value)?;
+                }
+                output.end()
+            }
+            Value::ClassInstance(instance) => {
+                let instance = instance.try_borrow_mut().map_err(|_| cycle_error::<S>())?;
+                let mut fields: Vec<_> = instance.fields().collect();
+                fields.sort_by_key(|(name, _)| *name);
+                let mut output = serializer.serialize_map(Some(fields.len()))?;
+                for (name, value) in fields {
+                    output.serialize_entry(name.as_str(), value)?;
+                }
+                output.end()
+            }
+            other => Err(ser::Error::custom(format!("Cannot serialize {}.", other))),
+        }
+    }
+}
+
+fn serialize_elements<S: Serializer>(elements: &[Value], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut output = serializer.serialize_seq(Some(elements.len()))?;
+    for element in elements {
+        output.serialize_element(element)?;
+    }
+    output.end()
+}
+
+fn cycle_error<S: Serializer>() -> S::Error {
+    ser::Error::custom("Cannot serialize a value that contains itself.")
+}
+
+/// Data deserializes into nil, booleans, numbers, strings, lists and maps.
+/// Integers too large for an `i64` become big ints.
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "a Lox value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Boolean(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+        Ok(Value::Int(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+        self.visit_u128(value.into())
+    }
+
+    fn visit_i128<E: de::Error>(self, value: i128) -> Result<Value, E> {
+        let magnitude = self.visit_u128(value.unsigned_abs())?;
+        match magnitude {
+            Value::Int(magnitude) if value < 0 => Ok(Value::Int(-magnitude)),
+            Value::BigInt(magnitude) if value < 0 => Ok(Value::integer(magnitude.neg())),
+            magnitude => Ok(magnitude),
+        }
+    }
+
+    fn visit_u128<E: de::Error>(self, value: u128) -> Result<Value, E> {
+        match BigInt::parse(&value.to_string()) {
+            Some(value) => Ok(Value::integer(value)),
+            None => Err(E::custom("invalid integer")),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+        Ok(Value::Float(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut elements = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Value::from(elements))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut entries: A) -> Result<Value, A::Error> {
+        let mut map = Map::new();
+        while let Some((key, value)) = entries.next_entry::<Value, Value>()? {
+            map.insert(key, value).map_err(de::Error::custom)?;
+        }
+        Ok(Value::from(map))
+    }
+}
@@ -0,0 +1,39 @@
+use crate::interpreter::{Input, Interpreter, InterpreterOptions};
+use crate::LoxError;
+
+/// An interpreter that keeps its state between runs, so code run later can
+/// use the variables, functions and classes defined earlier. The REPL runs
+/// every line in one session.
+pub struct Session {
+    interpreter: Interpreter,
+}
+
+impl Session {
+    pub fn new(options: InterpreterOptions) -> Self {
+        Session {
+            interpreter: Interpreter::with_options(options),
+        }
+    }
+
+    /// A session whose scripts read `readLine()` and `readAll()` input from
+    /// `input`.
+    pub fn with_input(input: Input, options: InterpreterOptions) -> Self {
+        let mut session = Session::new(options);
+        session.interpreter.set_input(input);
+        session
+    }
+
+    /// Compiles and runs `source`. An error only stops this run; whatever
+    /// ran before it stays defined.
+    pub fn run(&mut self, source: &str) -> Result<(), LoxError> {
+        self.interpreter.run(source.to_string())
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+}
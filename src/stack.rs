@@ -0,0 +1,92 @@
+//! Stacks of a known size for the code that recurses on the Rust stack: the
+//! parser, the resolver and the tree walker, which keeps every Lox call
+//! there. Programs run on one of these instead of the host's thread, which
+//! may have as little as 2 MB, so the call depth and nesting limits hold
+//! whichever thread calls in. Coroutines get one each to keep their frames
+//! on while they are suspended.
+//!
+//! Switching stacks stays on the same thread. WebAssembly has no way to do
+//! it, so there programs run on the host's stack and coroutines aren't
+//! available.
+
+/// The stack programs run on. Pages are only committed once they are used.
+#[cfg(not(target_family = "wasm"))]
+const PROGRAM_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// How close to the end of a stack calls and nesting stop going deeper.
+#[cfg(not(target_family = "wasm"))]
+const RED_ZONE: usize = 1024 * 1024;
+
+/// The smallest stack worth making, with room for a few calls past the red
+/// zone.
+#[cfg(not(target_family = "wasm"))]
+pub const MIN_SIZE: usize = 2 * RED_ZONE;
+
+#[cfg(not(target_family = "wasm"))]
+pub use switching::*;
+
+#[cfg(not(target_family = "wasm"))]
+mod switching {
+    use super::{PROGRAM_STACK_SIZE, RED_ZONE};
+    use corosensei::stack::{DefaultStack, Stack};
+    use std::cell::{Cell, RefCell};
+
+    thread_local! {
+        /// The lowest address the current stack may reach before it counts as
+        /// full, or 0 on a stack this module didn't make.
+        static LIMIT: Cell<usize> = const { Cell::new(0) };
+        /// The program stack from the last run, kept to save mapping another.
+        static SPARE: RefCell<Option<DefaultStack>> = const { RefCell::new(None) };
+    }
+
+    /// Runs `f` on a program stack, unless it is already on one of ours.
+    pub fn run<R>(f: impl FnOnce() -> R) -> R {
+        if LIMIT.get() != 0 {
+            return f();
+        }
+        let mut stack = SPARE.take().unwrap_or_else(|| {
+            DefaultStack::new(PROGRAM_STACK_SIZE)
+                .expect("Could not allocate the interpreter's stack")
+        });
+        let result = switched(limit(&stack), || corosensei::on_stack(&mut stack, f));
+        SPARE.set(Some(stack));
+        result
+    }
+
+    /// Where `stack` counts as full.
+    pub fn limit(stack: &impl Stack) -> usize {
+        stack.limit().get() + RED_ZONE
+    }
+
+    /// Runs `f`, which switches to a stack that is full at `limit`.
+    pub fn switched<R>(limit: usize, f: impl FnOnce() -> R) -> R {
+        /// Puts the previous limit back, even if `f` panics.
+        struct Restore(usize);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                LIMIT.set(self.0);
+            }
+        }
+
+        let _restore = Restore(LIMIT.replace(limit));
+        f()
+    }
+
+    /// Whether the current stack is too close to full to go any deeper.
+    pub fn is_low() -> bool {
+        let marker = 0u8;
+        let position = std::hint::black_box(&marker) as *const u8 as usize;
+        position < LIMIT.get()
+    }
+}
+
+#[cfg(target_family = "wasm")]
+pub fn run<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+#[cfg(target_family = "wasm")]
+pub fn is_low() -> bool {
+    false
+}
@@ -1,18 +1,57 @@
 use crate::expr::Expr;
-use crate::token::Token;
+use crate::sync::Rc;
+use crate::token::{Span, Token};
 
 pub trait Visitor<T> {
     fn visit_stmt(&mut self, stmt: &Stmt) -> T;
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionKind {
+    FUNCTION,
+    /// A method declared without a parameter list, invoked on property access.
+    GETTER,
+    /// A `set name(value)` method, invoked when the property is assigned.
+    SETTER,
+}
+
+/// A function, method or lambda as written. Functions created from it at
+/// runtime share it with the syntax tree instead of copying the body.
+#[derive(Debug, PartialEq)]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+    pub kind: FunctionKind,
+    /// The last parameter collects any remaining arguments into a list.
+    pub variadic: bool,
+}
+
+impl FunctionDecl {
+    /// From the name to the end of the body, less the closing brace.
+    pub fn span(&self) -> Span {
+        self.body
+            .iter()
+            .filter_map(Stmt::span)
+            .fold(self.name.span, Span::to)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
     Expression(Expr),
-    Print(Expr),
+    Print {
+        keyword: Token,
+        expr: Expr,
+    },
     Var {
         name: Token,
         initializer: Option<Expr>,
     },
+    Const {
+        name: Token,
+        initializer: Expr,
+    },
     Block(Vec<Stmt>),
     If {
         condition: Expr,
@@ -22,19 +61,185 @@ pub enum Stmt {
     While {
         condition: Expr,
         body: Box<Stmt>,
+        /// The increment clause of a desugared `for` loop, evaluated after
+        /// every iteration including ones cut short by `continue`.
+        increment: Option<Expr>,
     },
-    Function {
-        name: Token,
-        params: Vec<Token>,
-        body: Vec<Stmt>,
+    DoWhile {
+        body: Box<Stmt>,
+        condition: Expr,
     },
+    Function(Rc<FunctionDecl>),
     Return {
         keyword: Token,
         value: Option<Expr>,
     },
+    Assert {
+        keyword: Token,
+        condition: Expr,
+        message: Option<Expr>,
+    },
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    /// `try { body } catch (name) { handler } finally { finally }`. The
+    /// handler sees either the thrown value or, for runtime errors, the error
+    /// message. At least one of `handler` and `finally` is present.
+    Try {
+        body: Vec<Stmt>,
+        handler: Option<(Token, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    },
+    Continue {
+        keyword: Token,
+    },
+    ForIn {
+        name: Token,
+        keyword: Token,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
     Class {
         name: Token,
         methods: Vec<Stmt>,
+        /// `var` declarations in the class body, initialized on every
+        /// instantiation before `init` runs. Shared with the class, which
+        /// every instance holds a copy of.
+        fields: Rc<[Stmt]>,
+        /// `const` declarations in the class body, evaluated once when the
+        /// class is defined and read as `ClassName.NAME`.
+        constants: Vec<Stmt>,
+        /// `static var` declarations, shared by the class and its instances.
+        statics: Vec<Stmt>,
         super_class: Option<Expr>,
     },
+    /// `import "path";`, `import a, b from "path";` or
+    /// `import "path" as name;`. Paths ending in `.lox` name files; anything
+    /// else names a built-in module.
+    Import {
+        path: Token,
+        name: Token,
+        members: Vec<Token>,
+        alias: Option<Token>,
+    },
+}
+
+impl Stmt {
+    /// The source the statement covers, as far as its tokens tell. Closing
+    /// semicolons and braces aren't kept, so they're left out.
+    pub fn span(&self) -> Option<Span> {
+        let block = |stmts: &[Stmt]| stmts.iter().filter_map(Stmt::span).reduce(Span::to);
+        let spans: Vec<Option<Span>> = match self {
+            Stmt::Expression(expr) => vec![expr.span()],
+            Stmt::Print { keyword, expr } => vec![Some(keyword.span), expr.span()],
+            Stmt::Var { name, initializer } => {
+                vec![Some(name.span), initializer.as_ref().and_then(Expr::span)]
+            }
+            Stmt::Const { name, initializer } => vec![Some(name.span), initializer.span()],
+            Stmt::Block(stmts) => vec![block(stmts)],
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => vec![
+                condition.span(),
+                then_branch.span(),
+                else_branch.as_ref().and_then(|branch| branch.span()),
+            ],
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => vec![
+                condition.span(),
+                body.span(),
+                increment.as_ref().and_then(Expr::span),
+            ],
+            Stmt::DoWhile { body, condition } => vec![body.span(), condition.span()],
+            Stmt::Function(function) => vec![Some(function.span())],
+            Stmt::Return { keyword, value } => {
+                vec![Some(keyword.span), value.as_ref().and_then(Expr::span)]
+            }
+            Stmt::Assert {
+                keyword,
+                condition,
+                message,
+            } => vec![
+                Some(keyword.span),
+                condition.span(),
+                message.as_ref().and_then(Expr::span),
+            ],
+            Stmt::Throw { keyword, value } => vec![Some(keyword.span), value.span()],
+            Stmt::Try {
+                body,
+                handler,
+                finally,
+            } => vec![
+                block(body),
+                handler.as_ref().map(|(name, handler)| {
+                    block(handler).map_or(name.span, |span| span.to(name.span))
+                }),
+                finally.as_deref().and_then(block),
+            ],
+            Stmt::Continue { keyword } => vec![Some(keyword.span)],
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => vec![Some(name.span), iterable.span(), body.span()],
+            Stmt::Class {
+                name,
+                methods,
+                fields,
+                constants,
+                statics,
+                super_class,
+            } => vec![
+                Some(name.span),
+                super_class.as_ref().and_then(Expr::span),
+                block(methods),
+                block(fields),
+                block(constants),
+                block(statics),
+            ],
+            Stmt::Import {
+                path,
+                members,
+                alias,
+                ..
+            } => {
+                let mut spans = vec![Some(path.span), alias.as_ref().map(|alias| alias.span)];
+                spans.extend(members.iter().map(|member| Some(member.span)));
+                spans
+            }
+        };
+        spans.into_iter().flatten().reduce(Span::to)
+    }
+
+    /// The line the statement starts on, where it can be told.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Expression(expr) => expr.line(),
+            Stmt::Print { keyword, .. } => Some(keyword.line),
+            Stmt::Var { name, .. }
+            | Stmt::Const { name, .. }
+            | Stmt::ForIn { name, .. }
+            | Stmt::Class { name, .. } => Some(name.line),
+            Stmt::Function(function) => Some(function.name.line),
+            Stmt::Block(statements) => statements.iter().find_map(Stmt::line),
+            Stmt::If { condition, .. } | Stmt::While { condition, .. } => condition.line(),
+            Stmt::DoWhile { body, condition } => body.line().or_else(|| condition.line()),
+            Stmt::Return { keyword, .. }
+            | Stmt::Assert { keyword, .. }
+            | Stmt::Throw { keyword, .. }
+            | Stmt::Continue { keyword } => Some(keyword.line),
+            Stmt::Try { body, handler, .. } => body
+                .iter()
+                .find_map(Stmt::line)
+                .or_else(|| handler.as_ref().map(|(name, _)| name.line)),
+            Stmt::Import { path, .. } => Some(path.line),
+        }
+    }
 }
@@ -0,0 +1,203 @@
+//! String values. A string shares its buffer with its copies, so passing one
+//! around or reading it from a variable never copies the text. Joining two
+//! short strings copies them into a new buffer, but longer joins only
+//! remember their two halves and are flattened when the result is first
+//! read, so building a long string up piece by piece takes linear time.
+
+use crate::symbol::Symbol;
+use crate::sync::Rc;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Formatter;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+/// Joins shorter than this are copied straight away.
+const SHORT_JOIN: usize = 256;
+
+#[derive(Clone)]
+pub struct LoxString(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Flat(Rc<str>),
+    Join(Rc<Join>),
+}
+
+struct Join {
+    left: LoxString,
+    right: LoxString,
+    len: usize,
+    flat: OnceLock<Rc<str>>,
+}
+
+impl LoxString {
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Repr::Flat(text) => text,
+            Repr::Join(join) => join.flat.get_or_init(|| join.flatten()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Repr::Flat(text) => text.len(),
+            Repr::Join(join) => join.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// This string followed by `other`.
+    pub fn concat(&self, other: &LoxString) -> LoxString {
+        let len = self.len() + other.len();
+        if len < SHORT_JOIN {
+            let mut text = String::with_capacity(len);
+            text.push_str(self.as_str());
+            text.push_str(other.as_str());
+            return LoxString::from(text);
+        }
+        LoxString(Repr::Join(Rc::new(Join {
+            left: self.settled(),
+            right: other.settled(),
+            len,
+            flat: OnceLock::new(),
+        })))
+    }
+
+    /// The same string, without the halves of a join that has already been
+    /// flattened, so that a join of it doesn't keep both copies alive.
+    fn settled(&self) -> LoxString {
+        match &self.0 {
+            Repr::Join(join) => match join.flat.get() {
+                Some(flat) => LoxString(Repr::Flat(flat.clone())),
+                None => self.clone(),
+            },
+            Repr::Flat(_) => self.clone(),
+        }
+    }
+}
+
+impl Join {
+    /// Copies the pieces in order. Strings built in a loop nest as deep as
+    /// the loop ran, so this doesn't recurse.
+    fn flatten(&self) -> Rc<str> {
+        let mut text = String::with_capacity(self.len);
+        let mut pieces = vec![&self.right, &self.left];
+        while let Some(piece) = pieces.pop() {
+            match &piece.0 {
+                Repr::Flat(flat) => text.push_str(flat),
+                Repr::Join(join) => match join.flat.get() {
+                    Some(flat) => text.push_str(flat),
+                    None => {
+                        pieces.push(&join.right);
+                        pieces.push(&join.left);
+                    }
+                },
+            }
+        }
+        Rc::from(text)
+    }
+}
+
+/// Takes deeply nested joins apart one at a time instead of recursively.
+impl Drop for Join {
+    fn drop(&mut self) {
+        let mut pieces = vec![mem::take(&mut self.left), mem::take(&mut self.right)];
+        while let Some(piece) = pieces.pop() {
+            if let Repr::Join(join) = piece.0 {
+                if let Ok(mut join) = Rc::try_unwrap(join) {
+                    pieces.push(mem::take(&mut join.left));
+                    pieces.push(mem::take(&mut join.right));
+                }
+            }
+        }
+    }
+}
+
+impl Default for LoxString {
+    fn default() -> Self {
+        LoxString::from("")
+    }
+}
+
+impl From<&str> for LoxString {
+    fn from(text: &str) -> Self {
+        LoxString(Repr::Flat(Rc::from(text)))
+    }
+}
+
+impl From<String> for LoxString {
+    fn from(text: String) -> Self {
+        LoxString(Repr::Flat(Rc::from(text)))
+    }
+}
+
+/// Shares the symbol's buffer, so string literals aren't copied.
+impl From<&Symbol> for LoxString {
+    fn from(symbol: &Symbol) -> Self {
+        LoxString(Repr::Flat(symbol.as_rc().clone()))
+    }
+}
+
+impl Deref for LoxString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for LoxString {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for LoxString {}
+
+impl PartialEq<str> for LoxString {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for LoxString {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for LoxString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LoxString {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Hash for LoxString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl fmt::Debug for LoxString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for LoxString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
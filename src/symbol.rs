@@ -0,0 +1,118 @@
+//! Interned strings, for the names and string literals in source code.
+//! Each distinct string is stored once per thread, so a symbol is as cheap
+//! to clone as an `Rc` and comparing two of them is usually a pointer
+//! comparison. Interned strings live as long as the thread does.
+
+use crate::sync::Rc;
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Formatter;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+thread_local! {
+    static TABLE: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+impl Symbol {
+    pub fn intern(string: &str) -> Symbol {
+        TABLE.with(|table| {
+            let mut table = table.borrow_mut();
+            if let Some(interned) = table.get(string) {
+                return Symbol(interned.clone());
+            }
+            let interned: Rc<str> = Rc::from(string);
+            table.insert(interned.clone());
+            Symbol(interned)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn as_rc(&self) -> &Rc<str> {
+        &self.0
+    }
+}
+
+/// Symbols interned by the same thread are equal only if they are the same
+/// string in the table. With the `sync` feature they can also come from
+/// other threads' tables, so those are compared by contents.
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+/// Hashes like the `str` it holds, so maps keyed by symbols can be searched
+/// with a `&str`.
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Self {
+        Symbol::intern(string)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &*self.0)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", &*self.0)
+    }
+}
@@ -0,0 +1,116 @@
+//! Shared ownership for interpreter state. By default these are the usual
+//! single-threaded `Rc` and `RefCell`. With the `sync` feature `Rc` is
+//! `Arc` and `RefCell` is backed by an `RwLock`, which makes values and
+//! interpreters `Send` so a host can move them between threads.
+
+#[cfg(not(feature = "sync"))]
+pub use std::cell::RefCell;
+#[cfg(not(feature = "sync"))]
+pub use std::rc::{Rc, Weak};
+
+#[cfg(feature = "sync")]
+pub use lock::RefCell;
+#[cfg(feature = "sync")]
+pub use std::sync::{Arc as Rc, Weak};
+
+/// `Send + Sync` with the `sync` feature and implemented by everything
+/// without it. Natives and host values kept by the interpreter need it.
+#[cfg(feature = "sync")]
+pub trait MaybeSync: Send + Sync {}
+#[cfg(feature = "sync")]
+impl<T: Send + Sync + ?Sized> MaybeSync for T {}
+
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSync for T {}
+
+/// An opaque host value.
+#[cfg(not(feature = "sync"))]
+pub type AnyValue = dyn std::any::Any;
+#[cfg(feature = "sync")]
+pub type AnyValue = dyn std::any::Any + Send + Sync;
+
+#[cfg(feature = "sync")]
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    assert_send::<crate::Value>();
+    assert_send::<crate::Interpreter>();
+    assert_send::<crate::Session>();
+};
+
+#[cfg(feature = "sync")]
+mod lock {
+    use std::fmt;
+    use std::fmt::Formatter;
+    use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+    /// A `RefCell` lookalike over an `RwLock`. Where a `RefCell` would
+    /// panic on a conflicting borrow this blocks instead, so
+    /// `try_borrow_mut` is the way to detect reentrancy.
+    #[derive(Default)]
+    pub struct RefCell<T: ?Sized>(RwLock<T>);
+
+    impl<T> RefCell<T> {
+        pub const fn new(value: T) -> Self {
+            RefCell(RwLock::new(value))
+        }
+
+        pub fn replace(&self, value: T) -> T {
+            std::mem::replace(&mut *self.borrow_mut(), value)
+        }
+    }
+
+    impl<T: ?Sized> RefCell<T> {
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn try_borrow(&self) -> Result<RwLockReadGuard<'_, T>, TryLockError<()>> {
+            match self.0.try_read() {
+                Ok(guard) => Ok(guard),
+                Err(TryLockError::Poisoned(error)) => Ok(error.into_inner()),
+                Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+            }
+        }
+
+        pub fn try_borrow_mut(&self) -> Result<RwLockWriteGuard<'_, T>, TryLockError<()>> {
+            match self.0.try_write() {
+                Ok(guard) => Ok(guard),
+                Err(TryLockError::Poisoned(error)) => Ok(error.into_inner()),
+                Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+            }
+        }
+    }
+
+    impl<T> From<T> for RefCell<T> {
+        fn from(value: T) -> Self {
+            RefCell::new(value)
+        }
+    }
+
+    impl<T: Clone> Clone for RefCell<T> {
+        fn clone(&self) -> Self {
+            RefCell::new(self.borrow().clone())
+        }
+    }
+
+    impl<T: PartialEq + ?Sized> PartialEq for RefCell<T> {
+        fn eq(&self, other: &Self) -> bool {
+            *self.borrow() == *other.borrow()
+        }
+    }
+
+    impl<T: fmt::Debug + ?Sized> fmt::Debug for RefCell<T> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            match self.0.try_read() {
+                Ok(value) => f.debug_struct("RefCell").field("value", &&*value).finish(),
+                Err(_) => f.write_str("RefCell { <borrowed> }"),
+            }
+        }
+    }
+}
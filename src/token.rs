@@ -1,3 +1,5 @@
+use crate::bigint::BigInt;
+use crate::symbol::Symbol;
 use std::fmt;
 use std::fmt::Formatter;
 
@@ -7,19 +9,28 @@ pub enum TokenType {
     RIGHT_PAREN,
     LEFT_BRACE,
     RIGHT_BRACE,
+    LEFT_BRACKET,
+    RIGHT_BRACKET,
     COMMA,
     DOT,
+    DOT_DOT,
+    DOT_DOT_DOT,
+    DOT_DOT_EQUAL,
     MINUS,
     PLUS,
     SEMICOLON,
     SLASH,
     STAR,
+    QUESTION,
+    COLON,
+    HASH,
 
     // One or more character token
     BANG,
     BANG_EQUAL,
     EQUAL,
     EQUAL_EQUAL,
+    ARROW,
     GREATER,
     GREATER_EQUAL,
     LESS,
@@ -32,29 +43,45 @@ pub enum TokenType {
 
     //Keywords
     AND,
+    ASSERT,
+    CATCH,
     CLASS,
+    CONST,
+    CONTINUE,
+    DO,
     ELSE,
     FALSE,
+    FINALLY,
     FUN,
     FOR,
     IF,
+    IMPORT,
+    IN,
+    IS,
     NIL,
     OR,
     PRINT,
     SUPER,
     RETURN,
+    STATIC,
     THIS,
+    THROW,
     TRUE,
+    TRY,
     VAR,
     WHILE,
+    YIELD,
 
     EOF,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
-    String(String),
-    Number(f64),
+    String(Symbol),
+    Int(i64),
+    /// An integer literal too large for `i64`.
+    BigInt(BigInt),
+    Float(f64),
     Bool(bool),
     None,
 }
@@ -62,18 +89,43 @@ pub enum Literal {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Literal,
     pub line: usize,
+    pub span: Span,
+}
+
+/// A range of byte offsets into the source, including `start` but not
+/// `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span covering both this one and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
 }
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let value = match &self.literal {
-            Literal::String(value) => value,
-            Literal::Number(value) => &format!("{:?}", value),
+            Literal::String(value) => value.as_str(),
+            Literal::Int(value) => &value.to_string(),
+            Literal::BigInt(value) => &value.to_string(),
+            Literal::Float(value) => &format!("{:?}", value),
             Literal::Bool(value) => &value.to_string(),
-            Literal::None => &"nil".to_string(),
+            Literal::None => "nil",
         };
         write!(f, "{:?} {} {}", self.token_type, self.lexeme, value)
     }
@@ -1,2 +1,4 @@
+#![allow(dead_code)]
+
 pub mod ast_printer;
 pub mod rpn_printer;
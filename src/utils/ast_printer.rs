@@ -27,7 +27,9 @@ impl expr::Visitor<String> for AstPrinter {
         match expr {
             Expr::Literal { value, .. } => match value {
                 Literal::String(value) => value.to_string(),
-                Literal::Number(value) => format!("{:?}", value),
+                Literal::Int(value) => value.to_string(),
+                Literal::BigInt(value) => value.to_string(),
+                Literal::Float(value) => format!("{:?}", value),
                 Literal::Bool(value) => value.to_string(),
                 Literal::None => String::from("nil"),
             },
@@ -27,7 +27,9 @@ impl expr::Visitor<String> for RpnNotation {
         match expr {
             Expr::Literal { value, .. } => match value {
                 Literal::String(v) => v.to_string(),
-                Literal::Number(v) => format!("{}", v),
+                Literal::Int(v) => format!("{}", v),
+                Literal::BigInt(v) => format!("{}", v),
+                Literal::Float(v) => format!("{}", v),
                 Literal::Bool(v) => v.to_string(),
                 Literal::None => String::from("nil"),
             },
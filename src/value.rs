@@ -1,31 +1,393 @@
-use crate::class::{Class, ClassInstance, ClassInstanceRef};
+use crate::bigint::BigInt;
+use crate::class::{Class, ClassInstanceRef};
+use crate::coroutine::CoroutineRef;
 use crate::function::{Function, NativeFunction};
+use crate::gc;
+use crate::map::{Map, MapRef};
+use crate::module::ModuleRef;
+use crate::natives::json;
+use crate::string::LoxString;
+use crate::sync::{AnyValue, MaybeSync, Rc, RefCell};
+use crate::vm::ClosureRef;
+use std::any::Any;
 use std::fmt;
 use std::fmt::Formatter;
 
+pub type ListRef = Rc<RefCell<Vec<Value>>>;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Boolean(bool),
-    Number(f64),
-    String(String),
+    Int(i64),
+    /// An int outside the `i64` range. Results that fit are always
+    /// narrowed back to `Int`.
+    BigInt(BigInt),
+    Float(f64),
+    String(LoxString),
+    List(ListRef),
+    Tuple(Rc<Vec<Value>>),
+    Map(MapRef),
+    Range(Range),
     Function(Function),
     NativeFunction(NativeFunction),
+    /// A function compiled for the bytecode backend.
+    Closure(ClosureRef),
     Class(Class),
     ClassInstance(ClassInstanceRef),
+    Module(ModuleRef),
+    Coroutine(CoroutineRef),
+    /// An opaque host object, e.g. a file handle, that scripts can pass
+    /// around but not look inside.
+    Userdata(Userdata),
     Nil,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    pub fn values(&self) -> impl Iterator<Item = i64> {
+        let end = self.end.saturating_add(i64::from(self.inclusive));
+        self.start..end
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let operator = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, operator, self.end)
+    }
+}
+
+#[derive(Clone)]
+pub struct Userdata {
+    type_name: &'static str,
+    value: Rc<AnyValue>,
+}
+
+impl Userdata {
+    /// Wraps `value`. `type_name` is what `type()` reports and what the
+    /// value prints as.
+    pub fn new<T: Any + MaybeSync>(type_name: &'static str, value: T) -> Self {
+        Userdata {
+            type_name,
+            value: Rc::new(value),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// The wrapped value, if it is a `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+}
+
+impl fmt::Debug for Userdata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}>", self.type_name)
+    }
+}
+
+/// Userdata is equal only to itself.
+impl PartialEq for Userdata {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl fmt::Display for Userdata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}>", self.type_name)
+    }
+}
+
+impl Value {
+    /// The value of an int or float as a float, for mixed arithmetic.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(value) => Some(*value as f64),
+            Value::BigInt(value) => Some(value.to_f64()),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The value of an int of either size as a `BigInt`.
+    pub fn as_big(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(value) => Some(BigInt::from_i64(*value)),
+            Value::BigInt(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Wraps an integer result, narrowing it to `Int` when it fits.
+    pub fn integer(value: BigInt) -> Value {
+        match value.to_i64() {
+            Some(value) => Value::Int(value),
+            None => Value::BigInt(value),
+        }
+    }
+
+    /// The name of this value's type. `type()` reports instances by their
+    /// class name instead.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Boolean(_) => "boolean",
+            Value::Int(_) | Value::BigInt(_) | Value::Float(_) => "number",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Tuple(_) => "tuple",
+            Value::Map(_) => "map",
+            Value::Range(_) => "range",
+            Value::Function(_) | Value::NativeFunction(_) | Value::Closure(_) => "function",
+            Value::Class(_) => "class",
+            Value::ClassInstance(_) => "instance",
+            Value::Module(_) => "module",
+            Value::Coroutine(_) => "coroutine",
+            Value::Userdata(userdata) => userdata.type_name(),
+            Value::Nil => "nil",
+        }
+    }
+
+    /// Converts this value to a Rust type, e.g. `value.try_into::<f64>()`.
+    /// Fails with a message naming the expected and actual types.
+    pub fn try_into<T: TryFrom<Value, Error = String>>(self) -> Result<T, String> {
+        T::try_from(self)
+    }
+
+    /// Converts this value to JSON text the way `jsonStringify` does, for
+    /// handing results to other programs. Lists and tuples become arrays,
+    /// and maps with string keys and instances become objects. Functions,
+    /// classes and other values with no JSON form are an error. With the
+    /// `serde` feature, values also implement `Serialize` and `Deserialize`.
+    pub fn to_json(&self, pretty: bool) -> Result<String, String> {
+        json::to_json(self, pretty)
+    }
+}
+
+fn expected(kind: &str, value: &Value) -> String {
+    format!("Expected {}, got {}.", kind, value.type_name())
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value.into())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.into())
+    }
+}
+
+impl From<LoxString> for Value {
+    fn from(value: LoxString) -> Self {
+        Value::String(value)
+    }
+}
+
+/// Lists made this way are known to the cycle collector, which can free
+/// them when they are part of a cycle.
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        let list = ListRef::new(values.into());
+        gc::track(&list);
+        Value::List(list)
+    }
+}
+
+/// Like lists, maps made this way are known to the cycle collector.
+impl From<Map> for Value {
+    fn from(map: Map) -> Self {
+        let map = Rc::new(RefCell::new(map));
+        gc::track(&map);
+        Value::Map(map)
+    }
+}
+
+/// `None` becomes nil.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+/// Ints are widened, so any number converts.
+impl TryFrom<Value> for f64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.as_f64().ok_or_else(|| expected("a number", &value))
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(value) => Ok(value),
+            Value::BigInt(_) => Err("Integer is too large.".to_string()),
+            other => Err(expected("an integer", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Boolean(value) => Ok(value),
+            other => Err(expected("a boolean", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(value) => Ok(value.to_string()),
+            other => Err(expected("a string", &other)),
+        }
+    }
+}
+
+/// Lists are copied out; tuples convert too.
+impl TryFrom<Value> for Vec<Value> {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::List(list) => Ok(list.borrow().clone()),
+            Value::Tuple(elements) => Ok(elements.as_ref().clone()),
+            other => Err(expected("a list", &other)),
+        }
+    }
+}
+
+/// Nil becomes `None`.
+impl<T: TryFrom<Value, Error = String>> TryFrom<Value> for Option<T> {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Nil => Ok(None),
+            other => T::try_from(other).map(Some),
+        }
+    }
+}
+
+/// Formats a float the way the reference Lox implementation prints numbers:
+/// whole values drop the `.0`, others keep full precision, and infinities
+/// and NaN are spelled out. Like JavaScript, magnitudes from 1e21 up and
+/// below 1e-7 switch to exponent notation rather than printing every digit.
+pub fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        return String::from("NaN");
+    }
+    if value.is_infinite() {
+        let sign = if value < 0.0 { "-" } else { "" };
+        return format!("{}Infinity", sign);
+    }
+    if value.abs() >= 1e21 || (value != 0.0 && value.abs() < 1e-7) {
+        return format!("{:e}", value);
+    }
+    if value.fract() == 0.0 {
+        return format!("{:.0}", value);
+    }
+    value.to_string()
+}
+
+pub fn format_list(elements: &[String]) -> String {
+    format!("[{}]", elements.join(", "))
+}
+
+/// Formats already formatted key and value pairs.
+pub fn format_map(entries: &[(String, String)]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .collect();
+    format!("{{{}}}", entries.join(", "))
+}
+
+pub fn format_tuple(elements: &[String]) -> String {
+    if elements.len() == 1 {
+        format!("({},)", elements[0])
+    } else {
+        format!("({})", elements.join(", "))
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let s = match self {
             Value::Boolean(value) => value.to_string(),
-            Value::Number(value) => value.to_string(),
+            Value::Int(value) => value.to_string(),
+            Value::BigInt(value) => value.to_string(),
+            Value::Float(value) => format_float(*value),
             Value::String(value) => value.to_string(),
+            Value::Range(range) => range.to_string(),
+            Value::List(list) => {
+                let elements: Vec<String> = list
+                    .borrow()
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect();
+                format_list(&elements)
+            }
+            Value::Tuple(tuple) => {
+                let elements: Vec<String> = tuple.iter().map(|value| value.to_string()).collect();
+                format_tuple(&elements)
+            }
+            Value::Map(map) => {
+                let entries: Vec<(String, String)> = map
+                    .borrow()
+                    .entries()
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect();
+                format_map(&entries)
+            }
             Value::Nil => String::from("nil"),
             Value::Function(func) => format!("{}", func),
             Value::NativeFunction(_) => "<native fn>".to_string(),
+            Value::Closure(closure) => format!("{}", closure),
             Value::Class(class) => format!("{}", class),
-            Value::ClassInstance(instance) => format!("{}", instance.borrow().to_string()),
+            Value::ClassInstance(instance) => format!("{}", instance.borrow()),
+            Value::Module(module) => format!("{}", module),
+            Value::Coroutine(coroutine) => format!("{}", coroutine),
+            Value::Userdata(userdata) => format!("{}", userdata),
         };
         write!(f, "{}", s)
     }
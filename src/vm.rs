@@ -0,0 +1,625 @@
+//! A clox-style backend for `--backend=vm`. Programs are compiled to
+//! bytecode and run on a stack machine, which saves looking variables up in
+//! environments and recursing through the tree. It covers variables,
+//! control flow, functions, closures and classes with methods; programs
+//! using anything else, such as lists, run on the tree-walker instead, with
+//! a warning naming what the VM couldn't compile. Arithmetic and comparisons
+//! on numbers are done in place on the VM's compact `Packed` values; other
+//! operands, globals and calls to other callables go through the
+//! interpreter, so both backends behave the same.
+
+mod cache;
+mod chunk;
+mod compiler;
+mod disassembler;
+mod packed;
+
+use crate::class::Class;
+use crate::gc;
+use crate::interpreter::Interpreter;
+use crate::symbol::Symbol;
+use crate::sync::{Rc, RefCell};
+use crate::value::Value;
+use crate::{Exception, RuntimeErrorKind};
+pub use cache::{decode, encode, source_hash};
+use chunk::{Chunk, Op, Prototype};
+pub use compiler::{compile, Unsupported};
+pub use disassembler::disassemble;
+use packed::Packed;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::mem;
+
+/// How deeply calls between compiled functions can nest unless the options
+/// say otherwise.
+const MAX_FRAMES: usize = 10_000;
+
+pub type ClosureRef = Rc<Closure>;
+
+/// A compiled function together with the variables it captured.
+pub struct Closure {
+    prototype: Rc<Prototype>,
+    upvalues: Vec<UpvalueRef>,
+    /// The instance a method is bound to, which calls find in slot 0.
+    receiver: Option<Value>,
+}
+
+impl Closure {
+    pub fn arity(&self) -> usize {
+        self.prototype.arity
+    }
+
+    pub fn name(&self) -> &str {
+        &self.prototype.name
+    }
+
+    pub(crate) fn upvalues(&self) -> &[UpvalueRef] {
+        &self.upvalues
+    }
+
+    pub(crate) fn receiver(&self) -> Option<&Value> {
+        self.receiver.as_ref()
+    }
+
+    /// The method bound to `receiver`, sharing this one's captured
+    /// variables.
+    pub fn bind(&self, receiver: Value) -> ClosureRef {
+        let method = Rc::new(Closure {
+            prototype: self.prototype.clone(),
+            upvalues: self.upvalues.clone(),
+            receiver: Some(receiver),
+        });
+        gc::track(&method);
+        method
+    }
+
+    /// The values of captured variables that have left the stack, and the
+    /// instance a method is bound to. The rest are reachable from the stack
+    /// itself.
+    pub fn closed_values(&self) -> Vec<Value> {
+        self.upvalues
+            .iter()
+            .filter_map(|upvalue| match &*upvalue.borrow() {
+                Upvalue::Closed(value) => Some(value.to_value()),
+                Upvalue::Open(_) => None,
+            })
+            .chain(self.receiver.clone())
+            .collect()
+    }
+
+    /// What a call finds in slot 0.
+    fn slot_zero(self: &ClosureRef) -> Packed {
+        match &self.receiver {
+            Some(receiver) => Packed::from(receiver.clone()),
+            None => Packed::Closure(self.clone()),
+        }
+    }
+}
+
+/// Closures are only equal to themselves.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.prototype.name)
+    }
+}
+
+pub(crate) type UpvalueRef = Rc<RefCell<Upvalue>>;
+
+/// A captured variable, which stays in its stack slot until the scope
+/// declaring it ends.
+pub(crate) enum Upvalue {
+    Open(usize),
+    Closed(Packed),
+}
+
+impl Upvalue {
+    /// The closure held by a variable that has left the stack. Other values
+    /// are boxed, and the collector doesn't look inside those.
+    pub(crate) fn closure(&self) -> Option<&ClosureRef> {
+        match self {
+            Upvalue::Closed(Packed::Closure(closure)) => Some(closure),
+            _ => None,
+        }
+    }
+
+    /// Sets a variable that has left the stack to nil, handing back its
+    /// value for the collector to drop.
+    pub(crate) fn take(&mut self) -> Option<Value> {
+        match self {
+            Upvalue::Closed(value) => Some(mem::replace(value, Packed::Nil).into_value()),
+            Upvalue::Open(_) => None,
+        }
+    }
+}
+
+/// The value stack, shared by every call into the VM so that closures can
+/// find the variables they captured wherever they are called from.
+#[derive(Default)]
+pub struct Stack {
+    values: Vec<Packed>,
+    /// Captured variables still on the stack, by slot.
+    open_upvalues: Vec<(usize, UpvalueRef)>,
+    /// Calls in progress, across every entry into the VM.
+    frames: usize,
+}
+
+impl Stack {
+    pub fn values(&self) -> impl Iterator<Item = Value> + '_ {
+        self.values.iter().map(Packed::to_value)
+    }
+
+    fn push(&mut self, value: Packed) {
+        self.values.push(value);
+    }
+
+    fn pop(&mut self) -> Packed {
+        self.values
+            .pop()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    fn peek(&self) -> &Packed {
+        self.values
+            .last()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    fn capture(&mut self, slot: usize) -> UpvalueRef {
+        if let Some((_, upvalue)) = self.open_upvalues.iter().find(|(open, _)| *open == slot) {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        gc::track(&upvalue);
+        self.open_upvalues.push((slot, upvalue.clone()));
+        upvalue
+    }
+
+    /// Moves the captured variables in `from` and the slots above it off
+    /// the stack.
+    fn close_upvalues(&mut self, from: usize) {
+        let values = &self.values;
+        self.open_upvalues.retain(|(slot, upvalue)| {
+            if *slot < from {
+                return true;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(values[*slot].clone());
+            false
+        });
+    }
+
+    fn get_upvalue(&self, upvalue: &UpvalueRef) -> Packed {
+        match &*upvalue.borrow() {
+            Upvalue::Open(slot) => self.values[*slot].clone(),
+            Upvalue::Closed(value) => value.clone(),
+        }
+    }
+
+    fn set_upvalue(&mut self, upvalue: &UpvalueRef, value: Packed) {
+        match &mut *upvalue.borrow_mut() {
+            Upvalue::Open(slot) => self.values[*slot] = value,
+            Upvalue::Closed(closed) => *closed = value,
+        }
+    }
+}
+
+struct Frame {
+    closure: ClosureRef,
+    ip: usize,
+    /// The stack slot holding the function being called, with its
+    /// arguments and locals above it.
+    base: usize,
+}
+
+/// Runs a compiled program in the interpreter's globals.
+pub fn run(interpreter: &mut Interpreter, prototype: Rc<Prototype>) -> Result<(), Exception> {
+    let closure = Rc::new(Closure {
+        prototype,
+        upvalues: Vec::new(),
+        receiver: None,
+    });
+    call(interpreter, &closure, Vec::new()).map(|_| ())
+}
+
+/// Calls a closure with arguments already checked against its arity, e.g.
+/// from a native like `map`.
+pub fn call(
+    interpreter: &mut Interpreter,
+    closure: &ClosureRef,
+    args: Vec<Value>,
+) -> Result<Value, Exception> {
+    let stack = interpreter.stack();
+    let base = stack.values.len();
+    let frames = stack.frames;
+    stack.push(closure.slot_zero());
+    stack.values.extend(args.into_iter().map(Packed::from));
+    let frame = Frame {
+        closure: closure.clone(),
+        ip: 0,
+        base,
+    };
+    let result = execute(interpreter, frame).map(Packed::into_value);
+    if result.is_err() {
+        let stack = interpreter.stack();
+        stack.close_upvalues(base);
+        stack.values.truncate(base);
+        stack.frames = frames;
+    }
+    result
+}
+
+fn execute(interpreter: &mut Interpreter, mut frame: Frame) -> Result<Packed, Exception> {
+    let mut callers: Vec<Frame> = Vec::new();
+    let max_frames = interpreter.options().max_call_depth.unwrap_or(MAX_FRAMES);
+    interpreter.stack().frames += 1;
+    loop {
+        let chunk = &frame.closure.prototype.chunk;
+        let op = chunk.code[frame.ip];
+        frame.ip += 1;
+        match op {
+            Op::Constant(index) => {
+                let value = chunk.constants[index as usize].clone();
+                interpreter.stack().push(value);
+            }
+            Op::Nil => interpreter.stack().push(Packed::Nil),
+            Op::True => interpreter.stack().push(Packed::Boolean(true)),
+            Op::False => interpreter.stack().push(Packed::Boolean(false)),
+            Op::Pop => {
+                interpreter.stack().pop();
+            }
+            Op::GetLocal(slot) => {
+                let stack = interpreter.stack();
+                let value = stack.values[frame.base + slot as usize].clone();
+                stack.push(value);
+            }
+            Op::SetLocal(slot) => {
+                let stack = interpreter.stack();
+                let value = stack.peek().clone();
+                stack.values[frame.base + slot as usize] = value;
+            }
+            Op::GetUpvalue(index) => {
+                let stack = interpreter.stack();
+                let value = stack.get_upvalue(&frame.closure.upvalues[index as usize]);
+                stack.push(value);
+            }
+            Op::SetUpvalue(index) => {
+                let stack = interpreter.stack();
+                let value = stack.peek().clone();
+                stack.set_upvalue(&frame.closure.upvalues[index as usize], value);
+            }
+            Op::GetGlobal(name) => {
+                let value = interpreter
+                    .globals
+                    .borrow()
+                    .get(&chunk.tokens[name as usize])?;
+                interpreter.stack().push(Packed::from(value));
+            }
+            Op::SetGlobal(name) => {
+                let value = interpreter.stack().peek().to_value();
+                interpreter
+                    .globals
+                    .borrow_mut()
+                    .assign(&chunk.tokens[name as usize], value)?;
+            }
+            Op::DefineGlobal(name) => {
+                let value = interpreter.stack().pop().into_value();
+                let name = chunk.tokens[name as usize].lexeme.clone();
+                interpreter.globals.borrow_mut().define(name, value);
+            }
+            Op::DefineGlobalConstant(name) => {
+                let value = interpreter.stack().pop().into_value();
+                let name = chunk.tokens[name as usize].lexeme.clone();
+                interpreter
+                    .globals
+                    .borrow_mut()
+                    .define_constant(name, value);
+            }
+            Op::Negate(operator) => {
+                let stack = interpreter.stack();
+                let right = stack.pop();
+                let value = match right {
+                    Packed::Int(value) if value != i64::MIN => Packed::Int(-value),
+                    Packed::Float(value) => Packed::Float(-value),
+                    right => {
+                        let operator = &chunk.tokens[operator as usize];
+                        Packed::from(interpreter.unary_operation(operator, right.into_value())?)
+                    }
+                };
+                interpreter.stack().push(value);
+            }
+            Op::Not => {
+                let stack = interpreter.stack();
+                let value = stack.pop();
+                stack.push(Packed::Boolean(!value.is_truthy()));
+            }
+            Op::Add(operator) => binary(interpreter, chunk, operator, |left, right| {
+                match (left, right) {
+                    (Packed::Int(left), Packed::Int(right)) => {
+                        left.checked_add(*right).map(Packed::Int)
+                    }
+                    _ => floats(left, right).map(|(left, right)| Packed::Float(left + right)),
+                }
+            })?,
+            Op::Subtract(operator) => binary(interpreter, chunk, operator, |left, right| {
+                match (left, right) {
+                    (Packed::Int(left), Packed::Int(right)) => {
+                        left.checked_sub(*right).map(Packed::Int)
+                    }
+                    _ => floats(left, right).map(|(left, right)| Packed::Float(left - right)),
+                }
+            })?,
+            Op::Multiply(operator) => binary(interpreter, chunk, operator, |left, right| {
+                match (left, right) {
+                    (Packed::Int(left), Packed::Int(right)) => {
+                        left.checked_mul(*right).map(Packed::Int)
+                    }
+                    _ => floats(left, right).map(|(left, right)| Packed::Float(left * right)),
+                }
+            })?,
+            // Division always produces a float; dividing by zero is an
+            // error the interpreter reports.
+            Op::Divide(operator) => binary(interpreter, chunk, operator, |left, right| {
+                match (left, right) {
+                    (Packed::Int(left), Packed::Int(right)) if *right != 0 => {
+                        Some(Packed::Float(*left as f64 / *right as f64))
+                    }
+                    _ => match floats(left, right)? {
+                        (_, 0.0) => None,
+                        (left, right) => Some(Packed::Float(left / right)),
+                    },
+                }
+            })?,
+            Op::Equal(operator) => binary(interpreter, chunk, operator, |left, right| {
+                equal(left, right).map(Packed::Boolean)
+            })?,
+            Op::NotEqual(operator) => binary(interpreter, chunk, operator, |left, right| {
+                equal(left, right).map(|equal| Packed::Boolean(!equal))
+            })?,
+            Op::Greater(operator) => binary(interpreter, chunk, operator, |left, right| {
+                compare(left, right, |ordering| ordering.is_gt())
+            })?,
+            Op::GreaterEqual(operator) => binary(interpreter, chunk, operator, |left, right| {
+                compare(left, right, |ordering| ordering.is_ge())
+            })?,
+            Op::Less(operator) => binary(interpreter, chunk, operator, |left, right| {
+                compare(left, right, |ordering| ordering.is_lt())
+            })?,
+            Op::LessEqual(operator) => binary(interpreter, chunk, operator, |left, right| {
+                compare(left, right, |ordering| ordering.is_le())
+            })?,
+            Op::Binary(operator) => binary(interpreter, chunk, operator, |_, _| None)?,
+            Op::Jump(target) => frame.ip = target as usize,
+            Op::JumpIfFalse(target) => {
+                if !interpreter.stack().peek().is_truthy() {
+                    frame.ip = target as usize;
+                }
+            }
+            Op::JumpIfTrue(target) => {
+                if interpreter.stack().peek().is_truthy() {
+                    frame.ip = target as usize;
+                }
+            }
+            Op::Call(count, paren) => {
+                let count = count as usize;
+                let paren = &chunk.tokens[paren as usize];
+                let stack = interpreter.stack();
+                let slot = stack.values.len() - 1 - count;
+                if let Packed::Closure(closure) = &stack.values[slot] {
+                    let closure = closure.clone();
+                    Interpreter::check_arity(paren, closure.arity(), false, count)?;
+                    if stack.frames >= max_frames {
+                        return Exception::error(paren.clone(), RuntimeErrorKind::StackOverflow);
+                    }
+                    stack.frames += 1;
+                    if closure.receiver.is_some() {
+                        stack.values[slot] = closure.slot_zero();
+                    }
+                    let callee = Frame {
+                        closure,
+                        ip: 0,
+                        base: slot,
+                    };
+                    callers.push(mem::replace(&mut frame, callee));
+                } else {
+                    let args = stack.values.split_off(slot + 1);
+                    let args = args.into_iter().map(Packed::into_value).collect();
+                    let callee = stack.pop().into_value();
+                    let value = interpreter.call_value(callee, paren, args)?;
+                    interpreter.stack().push(Packed::from(value));
+                }
+            }
+            Op::Closure(index) => {
+                let prototype = chunk.functions[index as usize].clone();
+                let stack = interpreter.stack();
+                let upvalues = prototype
+                    .upvalues
+                    .iter()
+                    .map(|source| {
+                        let index = source.index as usize;
+                        if source.is_local {
+                            stack.capture(frame.base + index)
+                        } else {
+                            frame.closure.upvalues[index].clone()
+                        }
+                    })
+                    .collect();
+                let closure = Rc::new(Closure {
+                    prototype,
+                    upvalues,
+                    receiver: None,
+                });
+                gc::track(&closure);
+                stack.push(Packed::Closure(closure));
+            }
+            Op::Class(count, name) | Op::Subclass(count, name) => {
+                let name = &chunk.tokens[name as usize];
+                let stack = interpreter.stack();
+                let methods = stack.values.split_off(stack.values.len() - count as usize);
+                let super_class = match op {
+                    Op::Subclass(..) => match stack.peek().to_value() {
+                        Value::Class(class) => Some(Box::new(class)),
+                        _ => {
+                            return Exception::runtime_error(
+                                name.clone(),
+                                String::from("Superclass must be a class"),
+                            )
+                        }
+                    },
+                    _ => None,
+                };
+                let methods = methods
+                    .into_iter()
+                    .map(|method| match method {
+                        Packed::Closure(method) => Ok((Symbol::from(method.name()), method)),
+                        _ => Exception::runtime_error(
+                            name.clone(),
+                            String::from("Methods must be functions."),
+                        ),
+                    })
+                    .collect::<Result<HashMap<_, _>, _>>()?;
+                let class = Class::compiled(name.lexeme.to_string(), super_class, methods);
+                interpreter.stack().push(Packed::from(Value::Class(class)));
+            }
+            Op::GetProperty(name) => {
+                let object = interpreter.stack().pop().into_value();
+                let value = interpreter.get_property(object, &chunk.tokens[name as usize])?;
+                interpreter.stack().push(Packed::from(value));
+            }
+            Op::SetProperty(name) => {
+                let stack = interpreter.stack();
+                let value = stack.pop().into_value();
+                let object = stack.pop().into_value();
+                let value =
+                    interpreter.set_property(object, &chunk.tokens[name as usize], value)?;
+                interpreter.stack().push(Packed::from(value));
+            }
+            Op::GetSuper(method) => {
+                let stack = interpreter.stack();
+                let super_class = stack.pop().into_value();
+                let this = stack.pop().into_value();
+                let method = &chunk.tokens[method as usize];
+                let value = interpreter.get_super(super_class, this, method)?;
+                interpreter.stack().push(Packed::from(value));
+            }
+            Op::CloseUpvalue => {
+                let stack = interpreter.stack();
+                stack.close_upvalues(stack.values.len() - 1);
+                stack.pop();
+            }
+            Op::Return => {
+                let stack = interpreter.stack();
+                let value = stack.pop();
+                stack.close_upvalues(frame.base);
+                stack.values.truncate(frame.base);
+                stack.frames -= 1;
+                match callers.pop() {
+                    Some(caller) => {
+                        frame = caller;
+                        stack.push(value);
+                    }
+                    None => return Ok(value),
+                }
+            }
+            Op::Print => {
+                let value = interpreter.stack().pop().into_value();
+                interpreter.print(&value)?;
+            }
+            Op::Throw(keyword) => {
+                let value = interpreter.stack().pop().into_value();
+                return Err(Exception::Throw(
+                    value,
+                    chunk.tokens[keyword as usize].clone(),
+                ));
+            }
+            Op::Step => interpreter.count_step()?,
+        }
+    }
+}
+
+/// Applies a binary operator to the top two values of the stack: through
+/// `fast` when it handles their types, and the interpreter otherwise.
+fn binary(
+    interpreter: &mut Interpreter,
+    chunk: &Chunk,
+    operator: u32,
+    fast: impl Fn(&Packed, &Packed) -> Option<Packed>,
+) -> Result<(), Exception> {
+    let stack = interpreter.stack();
+    let right = stack.pop();
+    let left = stack
+        .values
+        .last_mut()
+        .expect("the compiler keeps the stack balanced");
+    if let Some(value) = fast(left, &right) {
+        *left = value;
+        return Ok(());
+    }
+    let left = stack.pop().into_value();
+    let operator = &chunk.tokens[operator as usize];
+    let value = interpreter.binary_operation(left, operator, right.into_value())?;
+    interpreter.stack().push(Packed::from(value));
+    Ok(())
+}
+
+/// Two numbers as floats, for operations where either one is a float.
+fn floats(left: &Packed, right: &Packed) -> Option<(f64, f64)> {
+    match (left, right) {
+        (Packed::Float(left), Packed::Float(right)) => Some((*left, *right)),
+        (Packed::Int(left), Packed::Float(right)) => Some((*left as f64, *right)),
+        (Packed::Float(left), Packed::Int(right)) => Some((*left, *right as f64)),
+        _ => None,
+    }
+}
+
+/// Compares two numbers like `Interpreter::compare_numbers`, where NaN is
+/// neither less, greater nor equal.
+fn compare(left: &Packed, right: &Packed, test: fn(Ordering) -> bool) -> Option<Packed> {
+    let ordering = match (left, right) {
+        (Packed::Int(left), Packed::Int(right)) => Some(left.cmp(right)),
+        _ => compare_floats(left, right)?,
+    };
+    Some(Packed::Boolean(ordering.is_some_and(test)))
+}
+
+/// Whether two values are equal, for the types that can be told without
+/// the interpreter.
+fn equal(left: &Packed, right: &Packed) -> Option<bool> {
+    match (left, right) {
+        (Packed::Nil, Packed::Nil) => Some(true),
+        (Packed::Boolean(left), Packed::Boolean(right)) => Some(left == right),
+        (Packed::Int(left), Packed::Int(right)) => Some(left == right),
+        (Packed::Boxed(left), Packed::Boxed(right)) => match (&**left, &**right) {
+            (Value::String(left), Value::String(right)) => Some(left == right),
+            _ => None,
+        },
+        _ => compare_floats(left, right).map(|ordering| ordering == Some(Ordering::Equal)),
+    }
+}
+
+/// Orders two numbers where either one is a float, comparing an int with a
+/// float exactly rather than rounding the int.
+fn compare_floats(left: &Packed, right: &Packed) -> Option<Option<Ordering>> {
+    match (left, right) {
+        (Packed::Float(left), Packed::Float(right)) => Some(left.partial_cmp(right)),
+        (Packed::Int(left), Packed::Float(right)) => {
+            Some(Interpreter::compare_int_float(&Value::Int(*left), *right))
+        }
+        (Packed::Float(left), Packed::Int(right)) => {
+            Some(Interpreter::compare_int_float(&Value::Int(*right), *left).map(Ordering::reverse))
+        }
+        _ => None,
+    }
+}
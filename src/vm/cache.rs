@@ -0,0 +1,447 @@
+//! The `.loxb` format for compiled programs: a header of the magic bytes
+//! `LOXB`, the format version and a hash of the source, followed by the
+//! script's prototype. Numbers are little-endian and lists and strings are
+//! prefixed with their length. Files from another version of the format,
+//! or compiled from different source, are ignored.
+
+use crate::bigint::BigInt;
+use crate::symbol::Symbol;
+use crate::sync::Rc;
+use crate::token::{Literal, Span, Token, TokenType};
+use crate::value::Value;
+use crate::vm::chunk::{Chunk, Op, Prototype, UpvalueSource};
+use crate::vm::packed::Packed;
+
+const MAGIC: &[u8; 4] = b"LOXB";
+/// Changes whenever the encoding of anything below does.
+const VERSION: u16 = 3;
+
+/// Token types by their number in the file. New ones go at the end.
+const TOKEN_TYPES: [TokenType; 60] = {
+    use TokenType::*;
+    [
+        LEFT_PAREN,
+        RIGHT_PAREN,
+        LEFT_BRACE,
+        RIGHT_BRACE,
+        LEFT_BRACKET,
+        RIGHT_BRACKET,
+        COMMA,
+        DOT,
+        DOT_DOT,
+        DOT_DOT_DOT,
+        DOT_DOT_EQUAL,
+        MINUS,
+        PLUS,
+        SEMICOLON,
+        SLASH,
+        STAR,
+        QUESTION,
+        COLON,
+        BANG,
+        BANG_EQUAL,
+        EQUAL,
+        EQUAL_EQUAL,
+        ARROW,
+        GREATER,
+        GREATER_EQUAL,
+        LESS,
+        LESS_EQUAL,
+        IDENTIFIER,
+        STRING,
+        NUMBER,
+        AND,
+        ASSERT,
+        CATCH,
+        CLASS,
+        CONST,
+        CONTINUE,
+        DO,
+        ELSE,
+        FALSE,
+        FINALLY,
+        FUN,
+        FOR,
+        IF,
+        IMPORT,
+        IN,
+        IS,
+        NIL,
+        OR,
+        PRINT,
+        SUPER,
+        RETURN,
+        STATIC,
+        THIS,
+        THROW,
+        TRUE,
+        TRY,
+        VAR,
+        WHILE,
+        YIELD,
+        EOF,
+    ]
+};
+
+/// The FNV-1a hash of `source`, which unlike `std`'s hashers is the same
+/// in every build.
+pub fn source_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn encode(prototype: &Prototype, source_hash: u64) -> Vec<u8> {
+    let mut writer = Writer { bytes: Vec::new() };
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u16(VERSION);
+    writer.u64(source_hash);
+    writer.prototype(prototype);
+    writer.bytes
+}
+
+/// Reads a program back, checking it was compiled from source with the
+/// given hash if there is one. `None` if the bytes aren't a program this
+/// version can run.
+pub fn decode(bytes: &[u8], source_hash: Option<u64>) -> Option<Rc<Prototype>> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC || reader.u16()? != VERSION {
+        return None;
+    }
+    let hash = reader.u64()?;
+    if source_hash.is_some_and(|source_hash| source_hash != hash) {
+        return None;
+    }
+    let prototype = reader.prototype()?;
+    if reader.position != bytes.len() {
+        return None;
+    }
+    Some(Rc::new(prototype))
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u64(len as u64);
+    }
+
+    fn string(&mut self, string: &str) {
+        self.len(string.len());
+        self.bytes.extend_from_slice(string.as_bytes());
+    }
+
+    fn prototype(&mut self, prototype: &Prototype) {
+        self.string(&prototype.name);
+        self.len(prototype.arity);
+        self.len(prototype.upvalues.len());
+        for upvalue in &prototype.upvalues {
+            self.u8(u8::from(upvalue.is_local));
+            self.u32(upvalue.index);
+        }
+        self.chunk(&prototype.chunk);
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) {
+        self.len(chunk.code.len());
+        for (op, line) in chunk.code.iter().zip(&chunk.lines) {
+            self.op(*op);
+            self.len(*line);
+        }
+        self.len(chunk.constants.len());
+        for constant in &chunk.constants {
+            self.constant(constant);
+        }
+        self.len(chunk.tokens.len());
+        for token in &chunk.tokens {
+            self.token(token);
+        }
+        self.len(chunk.functions.len());
+        for function in &chunk.functions {
+            self.prototype(function);
+        }
+    }
+
+    fn op(&mut self, op: Op) {
+        let (tag, operand) = match op {
+            Op::Constant(index) => (0, Some(index)),
+            Op::Nil => (1, None),
+            Op::True => (2, None),
+            Op::False => (3, None),
+            Op::Pop => (4, None),
+            Op::GetLocal(slot) => (5, Some(slot)),
+            Op::SetLocal(slot) => (6, Some(slot)),
+            Op::GetUpvalue(index) => (7, Some(index)),
+            Op::SetUpvalue(index) => (8, Some(index)),
+            Op::GetGlobal(name) => (9, Some(name)),
+            Op::SetGlobal(name) => (10, Some(name)),
+            Op::DefineGlobal(name) => (11, Some(name)),
+            Op::DefineGlobalConstant(name) => (12, Some(name)),
+            Op::Negate(operator) => (13, Some(operator)),
+            Op::Binary(operator) => (14, Some(operator)),
+            Op::Jump(target) => (15, Some(target)),
+            Op::JumpIfFalse(target) => (16, Some(target)),
+            Op::JumpIfTrue(target) => (17, Some(target)),
+            Op::Call(count, paren) => {
+                self.u8(18);
+                self.u8(count);
+                self.u32(paren);
+                return;
+            }
+            Op::Class(count, name) => {
+                self.u8(36);
+                self.u8(count);
+                self.u32(name);
+                return;
+            }
+            Op::Subclass(count, name) => {
+                self.u8(37);
+                self.u8(count);
+                self.u32(name);
+                return;
+            }
+            Op::Closure(index) => (19, Some(index)),
+            Op::CloseUpvalue => (20, None),
+            Op::Return => (21, None),
+            Op::Print => (22, None),
+            Op::Throw(keyword) => (23, Some(keyword)),
+            Op::Step => (24, None),
+            Op::Not => (25, None),
+            Op::Add(operator) => (26, Some(operator)),
+            Op::Subtract(operator) => (27, Some(operator)),
+            Op::Multiply(operator) => (28, Some(operator)),
+            Op::Divide(operator) => (29, Some(operator)),
+            Op::Equal(operator) => (30, Some(operator)),
+            Op::NotEqual(operator) => (31, Some(operator)),
+            Op::Greater(operator) => (32, Some(operator)),
+            Op::GreaterEqual(operator) => (33, Some(operator)),
+            Op::Less(operator) => (34, Some(operator)),
+            Op::LessEqual(operator) => (35, Some(operator)),
+            Op::GetProperty(name) => (38, Some(name)),
+            Op::SetProperty(name) => (39, Some(name)),
+            Op::GetSuper(method) => (40, Some(method)),
+        };
+        self.u8(tag);
+        if let Some(operand) = operand {
+            self.u32(operand);
+        }
+    }
+
+    /// Constants only ever come from literals.
+    fn constant(&mut self, value: &Packed) {
+        match value.to_value() {
+            Value::Int(value) => {
+                self.u8(0);
+                self.u64(value as u64);
+            }
+            Value::BigInt(value) => {
+                self.u8(1);
+                self.string(&value.to_string());
+            }
+            Value::Float(value) => {
+                self.u8(2);
+                self.u64(value.to_bits());
+            }
+            Value::String(value) => {
+                self.u8(3);
+                self.string(&value);
+            }
+            other => unreachable!("the compiler doesn't make {} constants", other.type_name()),
+        }
+    }
+
+    /// Tokens in chunks are names and operators, so their literal values
+    /// aren't kept.
+    fn token(&mut self, token: &Token) {
+        let token_type = TOKEN_TYPES
+            .iter()
+            .position(|token_type| *token_type == token.token_type)
+            .unwrap();
+        self.u8(token_type as u8);
+        self.string(&token.lexeme);
+        self.len(token.line);
+        self.len(token.span.start);
+        self.len(token.span.end);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let end = self.position.checked_add(len)?;
+        let bytes = self.bytes.get(self.position..end)?;
+        self.position = end;
+        Some(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    /// Reads a list, never reserving more space up front than the bytes
+    /// left could fill.
+    fn list<T>(&mut self, mut read: impl FnMut(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        let len = self.len()?;
+        let mut items = Vec::with_capacity(len.min(self.bytes.len() - self.position));
+        for _ in 0..len {
+            items.push(read(self)?);
+        }
+        Some(items)
+    }
+
+    fn prototype(&mut self) -> Option<Prototype> {
+        let name = self.string()?;
+        let arity = self.len()?;
+        let upvalues = self.list(|reader| {
+            let is_local = match reader.u8()? {
+                0 => false,
+                1 => true,
+                _ => return None,
+            };
+            let index = reader.u32()?;
+            Some(UpvalueSource { is_local, index })
+        })?;
+        let chunk = self.chunk()?;
+        let prototype = Prototype {
+            name,
+            arity,
+            upvalues,
+            chunk,
+        };
+        prototype.is_well_formed().then_some(prototype)
+    }
+
+    fn chunk(&mut self) -> Option<Chunk> {
+        let (code, lines) = self
+            .list(|reader| Some((reader.op()?, reader.len()?)))?
+            .into_iter()
+            .unzip();
+        Some(Chunk {
+            code,
+            lines,
+            constants: self.list(Reader::constant)?,
+            tokens: self.list(Reader::token)?,
+            functions: self.list(|reader| reader.prototype().map(Rc::new))?,
+        })
+    }
+
+    fn op(&mut self) -> Option<Op> {
+        let op = match self.u8()? {
+            0 => Op::Constant(self.u32()?),
+            1 => Op::Nil,
+            2 => Op::True,
+            3 => Op::False,
+            4 => Op::Pop,
+            5 => Op::GetLocal(self.u32()?),
+            6 => Op::SetLocal(self.u32()?),
+            7 => Op::GetUpvalue(self.u32()?),
+            8 => Op::SetUpvalue(self.u32()?),
+            9 => Op::GetGlobal(self.u32()?),
+            10 => Op::SetGlobal(self.u32()?),
+            11 => Op::DefineGlobal(self.u32()?),
+            12 => Op::DefineGlobalConstant(self.u32()?),
+            13 => Op::Negate(self.u32()?),
+            14 => Op::Binary(self.u32()?),
+            15 => Op::Jump(self.u32()?),
+            16 => Op::JumpIfFalse(self.u32()?),
+            17 => Op::JumpIfTrue(self.u32()?),
+            18 => Op::Call(self.u8()?, self.u32()?),
+            19 => Op::Closure(self.u32()?),
+            20 => Op::CloseUpvalue,
+            21 => Op::Return,
+            22 => Op::Print,
+            23 => Op::Throw(self.u32()?),
+            24 => Op::Step,
+            25 => Op::Not,
+            26 => Op::Add(self.u32()?),
+            27 => Op::Subtract(self.u32()?),
+            28 => Op::Multiply(self.u32()?),
+            29 => Op::Divide(self.u32()?),
+            30 => Op::Equal(self.u32()?),
+            31 => Op::NotEqual(self.u32()?),
+            32 => Op::Greater(self.u32()?),
+            33 => Op::GreaterEqual(self.u32()?),
+            34 => Op::Less(self.u32()?),
+            35 => Op::LessEqual(self.u32()?),
+            36 => Op::Class(self.u8()?, self.u32()?),
+            37 => Op::Subclass(self.u8()?, self.u32()?),
+            38 => Op::GetProperty(self.u32()?),
+            39 => Op::SetProperty(self.u32()?),
+            40 => Op::GetSuper(self.u32()?),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn constant(&mut self) -> Option<Packed> {
+        let value = match self.u8()? {
+            0 => Value::Int(self.u64()? as i64),
+            1 => Value::BigInt(BigInt::parse(&self.string()?)?),
+            2 => Value::Float(f64::from_bits(self.u64()?)),
+            3 => Value::String(self.string()?.into()),
+            _ => return None,
+        };
+        Some(Packed::from(value))
+    }
+
+    fn token(&mut self) -> Option<Token> {
+        let token_type = TOKEN_TYPES.get(self.u8()? as usize)?.clone();
+        let lexeme = self.string()?;
+        let line = self.len()?;
+        let span = Span::new(self.len()?, self.len()?);
+        Some(Token {
+            token_type,
+            lexeme: Symbol::from(lexeme.as_str()),
+            literal: Literal::None,
+            line,
+            span,
+        })
+    }
+}
@@ -0,0 +1,247 @@
+use crate::sync::Rc;
+use crate::token::Token;
+use crate::vm::packed::Packed;
+
+/// One instruction. Operands index into the chunk's tables, except for
+/// stack slots and jump targets, which are absolute.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    /// Pushes `constants[index]`.
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Pushes the local in `slot` of the current call.
+    GetLocal(u32),
+    /// Stores the value on top of the stack in `slot`, leaving it there.
+    SetLocal(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    /// Reads the global named by `tokens[index]`.
+    GetGlobal(u32),
+    SetGlobal(u32),
+    /// Pops a value into a new global named by `tokens[index]`.
+    DefineGlobal(u32),
+    DefineGlobalConstant(u32),
+    /// Applies `-` to the top of the stack, reporting errors at
+    /// `tokens[index]`.
+    Negate(u32),
+    Not,
+    /// The arithmetic and comparison operators work on numbers in place
+    /// and leave other operands to the interpreter, reporting errors at
+    /// `tokens[index]`.
+    Add(u32),
+    Subtract(u32),
+    Multiply(u32),
+    Divide(u32),
+    Equal(u32),
+    NotEqual(u32),
+    Greater(u32),
+    GreaterEqual(u32),
+    Less(u32),
+    LessEqual(u32),
+    /// Applies any other operator `tokens[index]` to the top two values.
+    Binary(u32),
+    Jump(u32),
+    /// Jumps if the top of the stack is falsey, without popping it.
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    /// Calls the value below the given number of arguments, reporting
+    /// errors at `tokens[index]`.
+    Call(u8, u32),
+    /// Creates a closure over `functions[index]`.
+    Closure(u32),
+    /// Makes the class named by `tokens[index]` from the given number of
+    /// method closures on top of the stack.
+    Class(u8, u32),
+    /// Like `Class`, inheriting from the superclass below the methods,
+    /// which is left on the stack.
+    Subclass(u8, u32),
+    /// Replaces the object on top of the stack with its property named by
+    /// `tokens[index]`.
+    GetProperty(u32),
+    /// Sets the property named by `tokens[index]` of the object below the
+    /// top of the stack to the value on top, leaving just the value.
+    SetProperty(u32),
+    /// Replaces the instance and superclass on top of the stack with the
+    /// superclass's method named by `tokens[index]`, bound to the instance.
+    GetSuper(u32),
+    /// Moves the local on top of the stack out to the closures that
+    /// captured it, then pops it.
+    CloseUpvalue,
+    Return,
+    Print,
+    /// Throws the value on top of the stack from `tokens[index]`.
+    Throw(u32),
+    /// Counts a statement against the run's limits.
+    Step,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    /// The source line of each instruction.
+    pub lines: Vec<usize>,
+    pub constants: Vec<Packed>,
+    /// Names and operators, kept for error messages and global lookups.
+    pub tokens: Vec<Token>,
+    pub functions: Vec<Rc<Prototype>>,
+}
+
+/// Where a closure finds one of the variables it captured when it is
+/// created: a local of the enclosing call, or one of its upvalues.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueSource {
+    pub is_local: bool,
+    pub index: u32,
+}
+
+/// A compiled function, before it closes over any variables.
+#[derive(Debug)]
+pub struct Prototype {
+    pub name: String,
+    pub arity: usize,
+    pub upvalues: Vec<UpvalueSource>,
+    pub chunk: Chunk,
+}
+
+impl Prototype {
+    /// Whether every operand points inside the chunk and the stack stays
+    /// balanced, as they do in anything the compiler makes. Checked on
+    /// programs read from files, since the VM relies on both.
+    pub fn is_well_formed(&self) -> bool {
+        let chunk = &self.chunk;
+        let within = |index: u32, len: usize| (index as usize) < len;
+        let code = chunk.code.iter().all(|op| match *op {
+            Op::Constant(index) => within(index, chunk.constants.len()),
+            Op::GetUpvalue(index) | Op::SetUpvalue(index) => within(index, self.upvalues.len()),
+            Op::GetGlobal(token)
+            | Op::SetGlobal(token)
+            | Op::DefineGlobal(token)
+            | Op::DefineGlobalConstant(token)
+            | Op::Negate(token)
+            | Op::Add(token)
+            | Op::Subtract(token)
+            | Op::Multiply(token)
+            | Op::Divide(token)
+            | Op::Equal(token)
+            | Op::NotEqual(token)
+            | Op::Greater(token)
+            | Op::GreaterEqual(token)
+            | Op::Less(token)
+            | Op::LessEqual(token)
+            | Op::Binary(token)
+            | Op::Call(_, token)
+            | Op::Class(_, token)
+            | Op::Subclass(_, token)
+            | Op::GetProperty(token)
+            | Op::SetProperty(token)
+            | Op::GetSuper(token)
+            | Op::Throw(token) => within(token, chunk.tokens.len()),
+            Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                within(target, chunk.code.len())
+            }
+            Op::Closure(index) => within(index, chunk.functions.len()),
+            _ => true,
+        });
+        let upvalues = chunk.functions.iter().all(|function| {
+            function
+                .upvalues
+                .iter()
+                .all(|upvalue| upvalue.is_local || within(upvalue.index, self.upvalues.len()))
+        });
+        code && upvalues
+            && chunk.lines.len() == chunk.code.len()
+            && matches!(chunk.code.last(), Some(Op::Return))
+            && self.is_balanced()
+    }
+
+    /// Whether every instruction finds the values it uses on the stack and
+    /// only reaches locals below the top, with the stack the same height
+    /// whichever way an instruction is reached. Follows each path through
+    /// the code from the call's slot and arguments.
+    fn is_balanced(&self) -> bool {
+        let code = &self.chunk.code;
+        let mut depths: Vec<Option<usize>> = vec![None; code.len()];
+        let mut pending = vec![(0, 1 + self.arity)];
+        while let Some((ip, depth)) = pending.pop() {
+            let Some(seen) = depths.get_mut(ip) else {
+                return false;
+            };
+            match *seen {
+                Some(seen) if seen == depth => continue,
+                Some(_) => return false,
+                None => *seen = Some(depth),
+            }
+            let (used, pushed) = match code[ip] {
+                Op::Constant(_)
+                | Op::Nil
+                | Op::True
+                | Op::False
+                | Op::GetUpvalue(_)
+                | Op::GetGlobal(_) => (0, 1),
+                Op::Step | Op::Jump(_) => (0, 0),
+                Op::GetLocal(slot) if (slot as usize) < depth => (0, 1),
+                Op::SetLocal(slot) if (slot as usize) < depth => (1, 1),
+                Op::GetLocal(_) | Op::SetLocal(_) => return false,
+                // A local function captures the slot the closure is about
+                // to be pushed into, so that it can call itself.
+                Op::Closure(index) => {
+                    let captured = self.chunk.functions[index as usize]
+                        .upvalues
+                        .iter()
+                        .all(|upvalue| !upvalue.is_local || (upvalue.index as usize) <= depth);
+                    if !captured {
+                        return false;
+                    }
+                    (0, 1)
+                }
+                Op::Pop
+                | Op::DefineGlobal(_)
+                | Op::DefineGlobalConstant(_)
+                | Op::CloseUpvalue
+                | Op::Print => (1, 0),
+                Op::SetUpvalue(_)
+                | Op::SetGlobal(_)
+                | Op::Negate(_)
+                | Op::Not
+                | Op::JumpIfFalse(_)
+                | Op::JumpIfTrue(_)
+                | Op::GetProperty(_) => (1, 1),
+                Op::Add(_)
+                | Op::Subtract(_)
+                | Op::Multiply(_)
+                | Op::Divide(_)
+                | Op::Equal(_)
+                | Op::NotEqual(_)
+                | Op::Greater(_)
+                | Op::GreaterEqual(_)
+                | Op::Less(_)
+                | Op::LessEqual(_)
+                | Op::Binary(_)
+                | Op::SetProperty(_)
+                | Op::GetSuper(_) => (2, 1),
+                Op::Call(count, _) => (1 + count as usize, 1),
+                Op::Class(count, _) => (count as usize, 1),
+                Op::Subclass(count, _) => (1 + count as usize, 2),
+                Op::Return | Op::Throw(_) => (1, 0),
+            };
+            // The call's own slot is never popped.
+            if depth < used + 1 {
+                return false;
+            }
+            let after = depth - used + pushed;
+            match code[ip] {
+                Op::Jump(target) => pending.push((target as usize, depth)),
+                Op::Return | Op::Throw(_) => {}
+                Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                    pending.push((target as usize, after));
+                    pending.push((ip + 1, after));
+                }
+                _ => pending.push((ip + 1, after)),
+            }
+        }
+        true
+    }
+}
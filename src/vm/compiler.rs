@@ -0,0 +1,777 @@
+use crate::expr::Expr;
+use crate::stmt::{FunctionDecl, FunctionKind, Stmt};
+use crate::string::LoxString;
+use crate::symbol::Symbol;
+use crate::sync::Rc;
+use crate::token::{Literal, Span, Token, TokenType};
+use crate::value::Value;
+use crate::vm::chunk::{Chunk, Op, Prototype, UpvalueSource};
+use crate::vm::packed::Packed;
+
+/// The program uses something the VM can't run yet, so it is left to the
+/// tree-walker.
+#[derive(Debug)]
+pub struct Unsupported {
+    /// What the VM can't run, in the plural, like "lists".
+    pub construct: &'static str,
+    pub line: usize,
+    pub span: Span,
+    /// The source text it starts with.
+    pub location: String,
+}
+
+impl Unsupported {
+    fn at(construct: &'static str, token: &Token) -> Self {
+        Unsupported {
+            construct,
+            line: token.line,
+            span: token.span,
+            location: token.lexeme.to_string(),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Unsupported>;
+
+/// Compiles a resolved program into the prototype of a function taking no
+/// arguments. Declarations outside of blocks define globals, like they do
+/// in the tree-walker.
+pub fn compile(stmts: &[Stmt]) -> Result<Rc<Prototype>> {
+    let mut compiler = Compiler {
+        functions: vec![FunctionState::new(
+            String::from("script"),
+            0,
+            1,
+            FunctionType::Function,
+        )],
+    };
+    for stmt in stmts {
+        compiler.statement(stmt)?;
+    }
+    compiler.emit_return();
+    Ok(Rc::new(compiler.functions.pop().unwrap().finish()))
+}
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    captured: bool,
+}
+
+struct Loop {
+    /// Locals deeper than this are popped by `continue`.
+    depth: usize,
+    /// `continue` jumps waiting for the address of the loop's next step.
+    continues: Vec<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Function,
+    Method,
+    /// An `init` method, which returns the instance.
+    Initializer,
+}
+
+/// A function being compiled. Slot 0 holds the function itself, or the
+/// instance a method is called on.
+struct FunctionState {
+    name: String,
+    arity: usize,
+    function_type: FunctionType,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueSource>,
+    depth: usize,
+    loops: Vec<Loop>,
+    /// The line of the statement being compiled.
+    line: usize,
+}
+
+impl FunctionState {
+    fn new(name: String, arity: usize, line: usize, function_type: FunctionType) -> Self {
+        let receiver = match function_type {
+            FunctionType::Function => "",
+            FunctionType::Method | FunctionType::Initializer => "this",
+        };
+        FunctionState {
+            name,
+            arity,
+            function_type,
+            chunk: Chunk::default(),
+            locals: vec![Local {
+                name: Symbol::from(receiver),
+                depth: 0,
+                captured: false,
+            }],
+            upvalues: Vec::new(),
+            depth: 0,
+            loops: Vec::new(),
+            line,
+        }
+    }
+
+    fn finish(self) -> Prototype {
+        Prototype {
+            name: self.name,
+            arity: self.arity,
+            upvalues: self.upvalues,
+            chunk: self.chunk,
+        }
+    }
+
+    fn resolve_local(&self, name: &Symbol) -> Option<u32> {
+        self.locals
+            .iter()
+            .rposition(|local| local.name == *name)
+            .map(|slot| slot as u32)
+    }
+
+    fn add_upvalue(&mut self, source: UpvalueSource) -> u32 {
+        match self.upvalues.iter().position(|upvalue| *upvalue == source) {
+            Some(index) => index as u32,
+            None => {
+                self.upvalues.push(source);
+                self.upvalues.len() as u32 - 1
+            }
+        }
+    }
+}
+
+struct Compiler {
+    /// The function being compiled, innermost last.
+    functions: Vec<FunctionState>,
+}
+
+enum Variable {
+    Local(u32),
+    Upvalue(u32),
+    Global(u32),
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().chunk
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let function = self.current();
+        function.chunk.lines.push(function.line);
+        function.chunk.code.push(op);
+        function.chunk.code.len() - 1
+    }
+
+    fn here(&mut self) -> u32 {
+        self.chunk().code.len() as u32
+    }
+
+    /// Points the jump at `jump` to the next instruction.
+    fn patch(&mut self, jump: usize) {
+        let target = self.here();
+        match &mut self.chunk().code[jump] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::JumpIfTrue(to) => *to = target,
+            op => unreachable!("{:?} is not a jump", op),
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        let constants = &mut self.chunk().constants;
+        constants.push(Packed::from(value));
+        constants.len() as u32 - 1
+    }
+
+    fn token(&mut self, token: &Token) -> u32 {
+        let tokens = &mut self.chunk().tokens;
+        tokens.push(token.clone());
+        tokens.len() as u32 - 1
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let function = self.current();
+        function.depth -= 1;
+        let depth = function.depth;
+        while let Some(local) = self.current().locals.pop_if(|local| local.depth > depth) {
+            self.emit(if local.captured {
+                Op::CloseUpvalue
+            } else {
+                Op::Pop
+            });
+        }
+    }
+
+    /// Returns nil, or the instance from an initializer.
+    fn emit_return(&mut self) {
+        if self.current().function_type == FunctionType::Initializer {
+            self.emit(Op::GetLocal(0));
+        } else {
+            self.emit(Op::Nil);
+        }
+        self.emit(Op::Return);
+    }
+
+    /// Pops the locals inside the innermost loop without forgetting them,
+    /// for a `continue` that leaves their scopes early.
+    fn pop_loop_locals(&mut self, keyword: &Token) -> Result<()> {
+        let function = self.current();
+        let depth = function
+            .loops
+            .last()
+            .ok_or_else(|| Unsupported::at("'continue' statements outside of loops", keyword))?
+            .depth;
+        let ops: Vec<Op> = function
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| {
+                if local.captured {
+                    Op::CloseUpvalue
+                } else {
+                    Op::Pop
+                }
+            })
+            .collect();
+        for op in ops {
+            self.emit(op);
+        }
+        Ok(())
+    }
+
+    fn add_local(&mut self, name: &Symbol) {
+        let function = self.current();
+        let depth = function.depth;
+        function.locals.push(Local {
+            name: name.clone(),
+            depth,
+            captured: false,
+        });
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &Symbol) -> Option<u32> {
+        if function == 0 {
+            return None;
+        }
+        if let Some(slot) = self.functions[function - 1].resolve_local(name) {
+            self.functions[function - 1].locals[slot as usize].captured = true;
+            let source = UpvalueSource {
+                is_local: true,
+                index: slot,
+            };
+            return Some(self.functions[function].add_upvalue(source));
+        }
+        let index = self.resolve_upvalue(function - 1, name)?;
+        let source = UpvalueSource {
+            is_local: false,
+            index,
+        };
+        Some(self.functions[function].add_upvalue(source))
+    }
+
+    /// Names the resolver left unresolved are globals, and so are the
+    /// ones here.
+    fn variable(&mut self, name: &Token) -> Variable {
+        let innermost = self.functions.len() - 1;
+        if let Some(slot) = self.functions[innermost].resolve_local(&name.lexeme) {
+            return Variable::Local(slot);
+        }
+        if let Some(index) = self.resolve_upvalue(innermost, &name.lexeme) {
+            return Variable::Upvalue(index);
+        }
+        Variable::Global(self.token(name))
+    }
+
+    /// Turns the value on top of the stack into a new local, or pops it
+    /// into a global when outside of any block.
+    fn declare(&mut self, name: &Token, constant: bool) {
+        if self.current().depth > 0 {
+            self.add_local(&name.lexeme);
+            return;
+        }
+        let name = self.token(name);
+        self.emit(if constant {
+            Op::DefineGlobalConstant(name)
+        } else {
+            Op::DefineGlobal(name)
+        });
+    }
+
+    fn statements(&mut self, stmts: &[Stmt]) -> Result<()> {
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<()> {
+        // An empty block has no line of its own and keeps the one before.
+        if let Some(line) = stmt.line() {
+            self.current().line = line;
+        }
+        self.emit(Op::Step);
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.expression(expr)?;
+                self.emit(Op::Pop);
+            }
+            Stmt::Print { expr, .. } => {
+                self.expression(expr)?;
+                self.emit(Op::Print);
+            }
+            Stmt::Var { name, initializer } => {
+                match initializer {
+                    Some(initializer) => self.expression(initializer)?,
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.declare(name, false);
+            }
+            Stmt::Const { name, initializer } => {
+                self.expression(initializer)?;
+                self.declare(name, true);
+            }
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                self.statements(stmts)?;
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition)?;
+                let else_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.statement(then_branch)?;
+                let end_jump = self.emit(Op::Jump(0));
+                self.patch(else_jump);
+                self.emit(Op::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch(end_jump);
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                let start = self.here();
+                self.expression(condition)?;
+                let exit_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.loop_body(body)?;
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit(Op::Pop);
+                }
+                self.emit(Op::Jump(start));
+                self.patch(exit_jump);
+                self.emit(Op::Pop);
+            }
+            Stmt::DoWhile { body, condition } => {
+                let start = self.here();
+                self.loop_body(body)?;
+                self.expression(condition)?;
+                let exit_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.emit(Op::Jump(start));
+                self.patch(exit_jump);
+                self.emit(Op::Pop);
+            }
+            Stmt::Continue { keyword } => {
+                self.pop_loop_locals(keyword)?;
+                let jump = self.emit(Op::Jump(0));
+                self.current()
+                    .loops
+                    .last_mut()
+                    .unwrap()
+                    .continues
+                    .push(jump);
+            }
+            Stmt::Function(declaration) => {
+                if self.current().depth > 0 {
+                    // Declared first so the body can call itself.
+                    self.add_local(&declaration.name.lexeme);
+                    self.function(declaration, FunctionType::Function)?;
+                } else {
+                    self.function(declaration, FunctionType::Function)?;
+                    self.declare(&declaration.name, false);
+                }
+            }
+            Stmt::Return { value, .. } => match value {
+                Some(value) => {
+                    self.expression(value)?;
+                    self.emit(Op::Return);
+                }
+                None => self.emit_return(),
+            },
+            Stmt::Throw { keyword, value } => {
+                self.expression(value)?;
+                let keyword = self.token(keyword);
+                self.emit(Op::Throw(keyword));
+            }
+            Stmt::Class {
+                name,
+                methods,
+                fields,
+                constants,
+                statics,
+                super_class,
+            } => {
+                let declarations = [
+                    ("class fields", &fields[..]),
+                    ("class constants", constants),
+                    ("static fields", statics),
+                ];
+                for (construct, declarations) in declarations {
+                    if let Some(Stmt::Var { name, .. } | Stmt::Const { name, .. }) =
+                        declarations.first()
+                    {
+                        return Err(Unsupported::at(construct, name));
+                    }
+                }
+                self.class(name, methods, super_class.as_ref())?;
+            }
+            Stmt::Assert { keyword, .. } => {
+                return Err(Unsupported::at("'assert' statements", keyword))
+            }
+            Stmt::Try { .. } => {
+                return Err(Unsupported {
+                    construct: "'try' statements",
+                    line: self.current().line,
+                    span: stmt.span().unwrap_or_default(),
+                    location: String::from("try"),
+                })
+            }
+            Stmt::ForIn { keyword, .. } => return Err(Unsupported::at("'for-in' loops", keyword)),
+            Stmt::Import { name, .. } => return Err(Unsupported::at("'import' statements", name)),
+        }
+        Ok(())
+    }
+
+    /// Compiles a class like clox does, except that the class is made once
+    /// its methods are: `Class` gathers the closures above it into a new
+    /// class, and `Subclass` also takes the superclass below them, which
+    /// stays behind as the `super` that the methods capture. A class
+    /// declared in a block gets its slot first, so its methods can refer
+    /// to it.
+    fn class(&mut self, name: &Token, methods: &[Stmt], super_class: Option<&Expr>) -> Result<()> {
+        if methods.len() > u8::MAX as usize {
+            return Err(Unsupported::at("classes with more than 255 methods", name));
+        }
+        let slot = if self.current().depth > 0 {
+            self.emit(Op::Nil);
+            self.add_local(&name.lexeme);
+            Some(self.current().locals.len() as u32 - 1)
+        } else {
+            None
+        };
+
+        self.begin_scope();
+        if let Some(super_class) = super_class {
+            self.expression(super_class)?;
+            self.add_local(&Symbol::from("super"));
+        }
+        let mut count = 0;
+        for method in methods {
+            if let Stmt::Function(declaration) = method {
+                let function_type = if declaration.name.lexeme == "init" {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+                self.function(declaration, function_type)?;
+                count += 1;
+            }
+        }
+        let name = self.token(name);
+        self.emit(match super_class {
+            Some(_) => Op::Subclass(count, name),
+            None => Op::Class(count, name),
+        });
+        match slot {
+            Some(slot) => {
+                self.emit(Op::SetLocal(slot));
+                self.emit(Op::Pop);
+            }
+            None => {
+                self.emit(Op::DefineGlobal(name));
+            }
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Compiles a loop body, pointing any `continue` in it at the code
+    /// that follows.
+    fn loop_body(&mut self, body: &Stmt) -> Result<()> {
+        let function = self.current();
+        let depth = function.depth;
+        function.loops.push(Loop {
+            depth,
+            continues: Vec::new(),
+        });
+        self.statement(body)?;
+        for jump in self.current().loops.pop().unwrap().continues {
+            self.patch(jump);
+        }
+        Ok(())
+    }
+
+    /// Compiles a function declaration and leaves a closure over it on the
+    /// stack.
+    fn function(&mut self, declaration: &FunctionDecl, function_type: FunctionType) -> Result<()> {
+        let FunctionDecl {
+            name,
+            params,
+            body,
+            kind,
+            variadic,
+        } = declaration;
+        match kind {
+            FunctionKind::FUNCTION => {}
+            FunctionKind::GETTER => return Err(Unsupported::at("getters", name)),
+            FunctionKind::SETTER => return Err(Unsupported::at("setters", name)),
+        }
+        if *variadic {
+            let rest = params.last().unwrap_or(name);
+            return Err(Unsupported::at("rest parameters", rest));
+        }
+        self.functions.push(FunctionState::new(
+            name.lexeme.to_string(),
+            params.len(),
+            name.line,
+            function_type,
+        ));
+        self.begin_scope();
+        for param in params {
+            self.add_local(&param.lexeme);
+        }
+        self.statements(body)?;
+        self.emit_return();
+        let function = self.functions.pop().unwrap().finish();
+
+        let functions = &mut self.chunk().functions;
+        functions.push(Rc::new(function));
+        let index = functions.len() as u32 - 1;
+        self.emit(Op::Closure(index));
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<()> {
+        match expr {
+            Expr::Literal { value, .. } => {
+                let op = match value {
+                    Literal::None => Op::Nil,
+                    Literal::Bool(true) => Op::True,
+                    Literal::Bool(false) => Op::False,
+                    Literal::String(value) => {
+                        Op::Constant(self.constant(Value::String(LoxString::from(value))))
+                    }
+                    Literal::Int(value) => Op::Constant(self.constant(Value::Int(*value))),
+                    Literal::BigInt(value) => {
+                        Op::Constant(self.constant(Value::BigInt(value.clone())))
+                    }
+                    Literal::Float(value) => Op::Constant(self.constant(Value::Float(*value))),
+                };
+                self.emit(op);
+            }
+            Expr::Grouping { expr, .. } => self.expression(expr)?,
+            Expr::Unary {
+                operator, right, ..
+            } => {
+                self.expression(right)?;
+                let op = match operator.token_type {
+                    TokenType::BANG => Op::Not,
+                    _ => Op::Negate(self.token(operator)),
+                };
+                self.emit(op);
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                let op = match operator.token_type {
+                    TokenType::PLUS => Op::Add,
+                    TokenType::MINUS => Op::Subtract,
+                    TokenType::STAR => Op::Multiply,
+                    TokenType::SLASH => Op::Divide,
+                    TokenType::EQUAL_EQUAL => Op::Equal,
+                    TokenType::BANG_EQUAL => Op::NotEqual,
+                    TokenType::GREATER => Op::Greater,
+                    TokenType::GREATER_EQUAL => Op::GreaterEqual,
+                    TokenType::LESS => Op::Less,
+                    TokenType::LESS_EQUAL => Op::LessEqual,
+                    _ => Op::Binary,
+                };
+                let operator = self.token(operator);
+                self.emit(op(operator));
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                self.expression(left)?;
+                let jump = if operator.token_type == TokenType::OR {
+                    self.emit(Op::JumpIfTrue(0))
+                } else {
+                    self.emit(Op::JumpIfFalse(0))
+                };
+                self.emit(Op::Pop);
+                self.expression(right)?;
+                self.patch(jump);
+            }
+            Expr::Var { name, .. } => {
+                let op = match self.variable(name) {
+                    Variable::Local(slot) => Op::GetLocal(slot),
+                    Variable::Upvalue(index) => Op::GetUpvalue(index),
+                    // Coroutines run on the tree-walker, which can suspend
+                    // a function part way through.
+                    Variable::Global(_) if name.lexeme == "coroutine" => {
+                        return Err(Unsupported::at("coroutines", name))
+                    }
+                    Variable::Global(name) => Op::GetGlobal(name),
+                };
+                self.emit(op);
+            }
+            Expr::This { keyword, .. } => self.get_variable(keyword),
+            Expr::Assign { name, value, .. } => {
+                self.expression(value)?;
+                let op = match self.variable(name) {
+                    Variable::Local(slot) => Op::SetLocal(slot),
+                    Variable::Upvalue(index) => Op::SetUpvalue(index),
+                    Variable::Global(name) => Op::SetGlobal(name),
+                };
+                self.emit(op);
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                named,
+                ..
+            } => {
+                if let Some((name, _)) = named.first() {
+                    return Err(Unsupported::at("named arguments", name));
+                }
+                if arguments.len() > u8::MAX as usize {
+                    return Err(Unsupported::at("calls with more than 255 arguments", paren));
+                }
+                self.expression(callee)?;
+                for argument in arguments {
+                    self.expression(argument)?;
+                }
+                let paren = self.token(paren);
+                self.emit(Op::Call(arguments.len() as u8, paren));
+            }
+            Expr::Lambda { declaration, .. } => {
+                self.function(declaration, FunctionType::Function)?
+            }
+            Expr::Sequence { expressions, .. } => {
+                for (i, expr) in expressions.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Op::Pop);
+                    }
+                    self.expression(expr)?;
+                }
+            }
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition)?;
+                let else_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.expression(then_branch)?;
+                let end_jump = self.emit(Op::Jump(0));
+                self.patch(else_jump);
+                self.emit(Op::Pop);
+                self.expression(else_branch)?;
+                self.patch(end_jump);
+            }
+            Expr::Get { object, name, .. } => {
+                self.expression(object)?;
+                let name = self.token(name);
+                self.emit(Op::GetProperty(name));
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                self.expression(object)?;
+                self.expression(value)?;
+                let name = self.token(name);
+                self.emit(Op::SetProperty(name));
+            }
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                let this = Token {
+                    lexeme: Symbol::from("this"),
+                    ..keyword.clone()
+                };
+                self.get_variable(&this);
+                self.get_variable(keyword);
+                let method = self.token(method);
+                self.emit(Op::GetSuper(method));
+            }
+            Expr::Yield { keyword, .. } => {
+                return Err(Unsupported::at("'yield' expressions", keyword))
+            }
+            Expr::Comparison { operators, .. } => {
+                return Err(Unsupported::at("chained comparisons", &operators[0]))
+            }
+            Expr::Is { keyword, .. } => return Err(Unsupported::at("'is' expressions", keyword)),
+            Expr::Index { bracket, .. } | Expr::SetIndex { bracket, .. } => {
+                return Err(Unsupported::at("indexing", bracket))
+            }
+            Expr::Slice { bracket, .. } => return Err(Unsupported::at("slices", bracket)),
+            Expr::List { .. } => return Err(self.unsupported("lists", "[", expr)),
+            Expr::Tuple { .. } => return Err(self.unsupported("tuples", "#(", expr)),
+            Expr::If { .. } => return Err(self.unsupported("'if' expressions", "if", expr)),
+            Expr::Block { .. } => return Err(self.unsupported("block expressions", "{", expr)),
+        }
+        Ok(())
+    }
+
+    /// Pushes the value of a variable, or of `this` or `super`.
+    fn get_variable(&mut self, name: &Token) {
+        let op = match self.variable(name) {
+            Variable::Local(slot) => Op::GetLocal(slot),
+            Variable::Upvalue(index) => Op::GetUpvalue(index),
+            Variable::Global(name) => Op::GetGlobal(name),
+        };
+        self.emit(op);
+    }
+
+    /// For an expression with no token of its own to point at, starting
+    /// with `location`.
+    fn unsupported(&mut self, construct: &'static str, location: &str, expr: &Expr) -> Unsupported {
+        Unsupported {
+            construct,
+            line: expr.line().unwrap_or(self.current().line),
+            span: expr.span().unwrap_or_default(),
+            location: location.to_string(),
+        }
+    }
+}
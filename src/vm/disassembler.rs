@@ -0,0 +1,111 @@
+//! Lists compiled programs as text, one instruction per line with its
+//! address, source line and operands, followed by the functions it defines:
+//!
+//! ```text
+//! == script ==
+//! 0000    1 Step
+//! 0001    | Constant                0 '1'
+//! 0002    | Print
+//! 0003    | Nil
+//! 0004    | Return
+//! ```
+
+use crate::vm::chunk::{Chunk, Op, Prototype};
+
+pub fn disassemble(prototype: &Prototype) -> String {
+    let mut lines = Vec::new();
+    function(&mut lines, prototype);
+    lines.join("\n")
+}
+
+fn function(lines: &mut Vec<String>, prototype: &Prototype) {
+    lines.push(format!("== {} ==", prototype.name));
+    let chunk = &prototype.chunk;
+    for (address, op) in chunk.code.iter().enumerate() {
+        // Like clox, a `|` marks an instruction on the same line as the
+        // one before.
+        let line = match address {
+            0 => format!("{:4}", chunk.lines[0]),
+            _ if chunk.lines[address] == chunk.lines[address - 1] => String::from("   |"),
+            _ => format!("{:4}", chunk.lines[address]),
+        };
+        lines.push(format!(
+            "{:04} {} {}",
+            address,
+            line,
+            instruction(chunk, *op)
+        ));
+        if let Op::Closure(index) = op {
+            for upvalue in &chunk.functions[*index as usize].upvalues {
+                let kind = if upvalue.is_local { "local" } else { "upvalue" };
+                lines.push(format!("{:04}    |   {} {}", address, kind, upvalue.index));
+            }
+        }
+    }
+    for nested in &chunk.functions {
+        lines.push(String::new());
+        function(lines, nested);
+    }
+}
+
+fn instruction(chunk: &Chunk, op: Op) -> String {
+    let token = |index: u32| format!("'{}'", chunk.tokens[index as usize].lexeme);
+    let (name, operand, detail) = match op {
+        Op::Constant(index) => (
+            "Constant",
+            index,
+            format!("'{}'", chunk.constants[index as usize]),
+        ),
+        Op::Nil => return String::from("Nil"),
+        Op::True => return String::from("True"),
+        Op::False => return String::from("False"),
+        Op::Pop => return String::from("Pop"),
+        Op::GetLocal(slot) => ("GetLocal", slot, String::new()),
+        Op::SetLocal(slot) => ("SetLocal", slot, String::new()),
+        Op::GetUpvalue(index) => ("GetUpvalue", index, String::new()),
+        Op::SetUpvalue(index) => ("SetUpvalue", index, String::new()),
+        Op::GetGlobal(name) => ("GetGlobal", name, token(name)),
+        Op::SetGlobal(name) => ("SetGlobal", name, token(name)),
+        Op::DefineGlobal(name) => ("DefineGlobal", name, token(name)),
+        Op::DefineGlobalConstant(name) => ("DefineGlobalConstant", name, token(name)),
+        Op::Negate(operator) => ("Negate", operator, token(operator)),
+        Op::Not => return String::from("Not"),
+        Op::Add(operator) => ("Add", operator, token(operator)),
+        Op::Subtract(operator) => ("Subtract", operator, token(operator)),
+        Op::Multiply(operator) => ("Multiply", operator, token(operator)),
+        Op::Divide(operator) => ("Divide", operator, token(operator)),
+        Op::Equal(operator) => ("Equal", operator, token(operator)),
+        Op::NotEqual(operator) => ("NotEqual", operator, token(operator)),
+        Op::Greater(operator) => ("Greater", operator, token(operator)),
+        Op::GreaterEqual(operator) => ("GreaterEqual", operator, token(operator)),
+        Op::Less(operator) => ("Less", operator, token(operator)),
+        Op::LessEqual(operator) => ("LessEqual", operator, token(operator)),
+        Op::Binary(operator) => ("Binary", operator, token(operator)),
+        Op::Jump(target) => return jump("Jump", target),
+        Op::JumpIfFalse(target) => return jump("JumpIfFalse", target),
+        Op::JumpIfTrue(target) => return jump("JumpIfTrue", target),
+        Op::Call(count, _) => ("Call", u32::from(count), String::new()),
+        Op::Closure(index) => (
+            "Closure",
+            index,
+            format!("<fn {}>", chunk.functions[index as usize].name),
+        ),
+        Op::Class(count, name) => ("Class", u32::from(count), token(name)),
+        Op::Subclass(count, name) => ("Subclass", u32::from(count), token(name)),
+        Op::GetProperty(name) => ("GetProperty", name, token(name)),
+        Op::SetProperty(name) => ("SetProperty", name, token(name)),
+        Op::GetSuper(method) => ("GetSuper", method, token(method)),
+        Op::CloseUpvalue => return String::from("CloseUpvalue"),
+        Op::Return => return String::from("Return"),
+        Op::Print => return String::from("Print"),
+        Op::Throw(_) => return String::from("Throw"),
+        Op::Step => return String::from("Step"),
+    };
+    format!("{:<20} {:4} {}", name, operand, detail)
+        .trim_end()
+        .to_string()
+}
+
+fn jump(name: &str, target: u32) -> String {
+    format!("{:<20} -> {:04}", name, target)
+}
@@ -0,0 +1,68 @@
+use crate::sync::Rc;
+use crate::value::Value;
+use crate::vm::ClosureRef;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// A value as the VM keeps it on its stack, in its constants and in its
+/// upvalues. Numbers, booleans and closures are held directly and anything
+/// else behind a shared pointer, so a value is two words and pushing,
+/// copying and dropping one never touches a full `Value`.
+#[derive(Debug, Clone)]
+pub enum Packed {
+    Nil,
+    Boolean(bool),
+    Int(i64),
+    Float(f64),
+    Closure(ClosureRef),
+    Boxed(Rc<Value>),
+}
+
+const _: () = assert!(size_of::<Packed>() == 16);
+
+impl Packed {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Packed::Nil => false,
+            Packed::Boolean(value) => *value,
+            _ => true,
+        }
+    }
+
+    pub fn into_value(self) -> Value {
+        match self {
+            Packed::Nil => Value::Nil,
+            Packed::Boolean(value) => Value::Boolean(value),
+            Packed::Int(value) => Value::Int(value),
+            Packed::Float(value) => Value::Float(value),
+            Packed::Closure(closure) => Value::Closure(closure),
+            Packed::Boxed(value) => Rc::try_unwrap(value).unwrap_or_else(|value| (*value).clone()),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        self.clone().into_value()
+    }
+}
+
+impl From<Value> for Packed {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Nil => Packed::Nil,
+            Value::Boolean(value) => Packed::Boolean(value),
+            Value::Int(value) => Packed::Int(value),
+            Value::Float(value) => Packed::Float(value),
+            Value::Closure(closure) => Packed::Closure(closure),
+            value => Packed::Boxed(Rc::new(value)),
+        }
+    }
+}
+
+impl fmt::Display for Packed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Packed::Boxed(value) => write!(f, "{}", value),
+            other => write!(f, "{}", other.to_value()),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! The JavaScript-facing API for a browser playground, built with
+//! `--features wasm` for wasm32-unknown-unknown.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{run_captured, InterpreterOptions};
+
+/// What `run` returns to JavaScript: `{ output, diagnostics }`.
+#[wasm_bindgen(getter_with_clone)]
+pub struct RunResult {
+    pub output: String,
+    pub diagnostics: Vec<String>,
+}
+
+/// Runs `source` in a sandbox and returns what it printed along with any
+/// warnings and errors, one message per diagnostic.
+#[wasm_bindgen]
+pub fn run(source: &str) -> RunResult {
+    let captured = run_captured(source, InterpreterOptions::default());
+    RunResult {
+        output: captured.output,
+        diagnostics: captured.diagnostics,
+    }
+}
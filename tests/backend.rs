@@ -0,0 +1,42 @@
+//! The VM compiles classes itself, and warns when it has to leave a program
+//! to the tree-walker.
+
+use rlox::{disassemble, run_captured, Backend, Captured, InterpreterOptions};
+
+fn run_on_vm(source: &str) -> Captured {
+    let options = InterpreterOptions {
+        backend: Backend::Vm,
+        ..InterpreterOptions::default()
+    };
+    run_captured(source, options)
+}
+
+#[test]
+fn classes_are_compiled() {
+    let source = "class A {
+  init(x) { this.x = x; }
+  get() { return this.x; }
+}
+class B < A {
+  get() { return \"b\" + super.get(); }
+}
+var b = B(1);
+print b.get();
+print b.init(2) == b;
+print b.x;
+";
+    assert!(disassemble(source).unwrap().is_some());
+    let captured = run_on_vm(source);
+    assert_eq!(captured.output, "b1\ntrue\n2\n");
+    assert!(captured.diagnostics.is_empty());
+}
+
+#[test]
+fn falling_back_is_a_warning() {
+    let captured = run_on_vm("print 1;\nprint [2, 3];\n");
+    assert_eq!(captured.output, "1\n[2, 3]\n");
+    assert_eq!(
+        captured.diagnostics,
+        ["[line 2] Warning E5003 at '[': The VM can't compile lists yet, so the program runs on the tree-walker."]
+    );
+}
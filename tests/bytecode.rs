@@ -0,0 +1,88 @@
+//! Programs loaded from `.loxb` files are checked before they run, so a
+//! damaged file is rejected instead of crashing the host.
+
+use std::{env, fs};
+
+use rlox::{run_file, Backend, InterpreterBuilder, InterpreterOptions, LoxError};
+
+const GET_LOCAL: u8 = 5;
+const POP: u8 = 4;
+const NIL: u8 = 1;
+const RETURN: u8 = 21;
+
+/// The header of a file compiled by this build.
+fn header() -> Vec<u8> {
+    let dir = env::temp_dir().join(format!("rlox-bytecode-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("empty.lox");
+    fs::write(&script, "").unwrap();
+    let options = InterpreterOptions {
+        backend: Backend::Vm,
+        cache: true,
+        ..InterpreterOptions::default()
+    };
+    run_file(script.to_str().unwrap(), vec![], options).unwrap();
+    let bytes = fs::read(script.with_extension("loxb")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    // The magic bytes, the version and the source hash.
+    bytes[..14].to_vec()
+}
+
+/// A file holding a script of `code`, given as an opcode and its operand.
+fn program(code: &[(u8, Option<u32>)]) -> Vec<u8> {
+    let mut bytes = header();
+    let len = |bytes: &mut Vec<u8>, len: usize| bytes.extend((len as u64).to_le_bytes());
+    len(&mut bytes, 0); // name
+    len(&mut bytes, 0); // arity
+    len(&mut bytes, 0); // upvalues
+    len(&mut bytes, code.len());
+    for (op, operand) in code {
+        bytes.push(*op);
+        if let Some(operand) = operand {
+            bytes.extend(operand.to_le_bytes());
+        }
+        len(&mut bytes, 1); // line
+    }
+    len(&mut bytes, 0); // constants
+    len(&mut bytes, 0); // tokens
+    len(&mut bytes, 0); // functions
+    bytes
+}
+
+fn run(bytes: &[u8]) -> Result<(), LoxError> {
+    InterpreterBuilder::sandboxed().build().run_bytecode(bytes)
+}
+
+#[test]
+fn well_formed_program_runs() {
+    let bytes = program(&[
+        (GET_LOCAL, Some(0)),
+        (POP, None),
+        (NIL, None),
+        (RETURN, None),
+    ]);
+    assert!(run(&bytes).is_ok());
+}
+
+#[test]
+fn local_past_the_stack_is_rejected() {
+    let bytes = program(&[
+        (GET_LOCAL, Some(100_000)),
+        (POP, None),
+        (NIL, None),
+        (RETURN, None),
+    ]);
+    assert!(matches!(run(&bytes), Err(LoxError::Io(_))));
+}
+
+#[test]
+fn popping_an_empty_stack_is_rejected() {
+    let bytes = program(&[(POP, None), (POP, None), (NIL, None), (RETURN, None)]);
+    assert!(matches!(run(&bytes), Err(LoxError::Io(_))));
+}
+
+#[test]
+fn returning_nothing_is_rejected() {
+    let bytes = program(&[(RETURN, None)]);
+    assert!(matches!(run(&bytes), Err(LoxError::Io(_))));
+}
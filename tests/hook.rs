@@ -0,0 +1,28 @@
+//! The statement hook is given the line each statement is on.
+
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+
+use rlox::InterpreterBuilder;
+
+#[test]
+fn hook_is_given_each_statement_line() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let mut interpreter = InterpreterBuilder::sandboxed().build();
+    let seen = lines.clone();
+    interpreter.set_statement_hook(move |_, line| {
+        seen.lock().unwrap().push(line);
+        ControlFlow::Continue(())
+    });
+    let source = "var a = 1;
+print 2;
+2;
+{
+  print 1;
+}
+nil;
+print \"a\" + a;
+";
+    interpreter.run(source.to_string()).unwrap();
+    assert_eq!(*lines.lock().unwrap(), [1, 2, 3, 5, 5, 7, 8]);
+}
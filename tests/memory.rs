@@ -0,0 +1,35 @@
+//! The `max_memory` option stops a program before it allocates past the
+//! limit, even within the statements between periodic measurements.
+
+use rlox::{InterpreterBuilder, InterpreterOptions, LoxError};
+
+fn run(source: &str) -> Result<(), LoxError> {
+    let options = InterpreterOptions {
+        max_memory: Some(10_000_000),
+        ..InterpreterOptions::default()
+    };
+    InterpreterBuilder::sandboxed()
+        .options(options)
+        .build()
+        .run(source.to_string())
+}
+
+#[test]
+fn doubling_a_string_past_the_limit_runs_out_of_memory() {
+    let result = run("
+        import charAt from \"string\";
+        var s = \"12345678\";
+        for (var i = 0; i < 28; i = i + 1) s = s + s;
+        print charAt(s, 5);
+    ");
+    assert!(matches!(result, Err(LoxError::OutOfMemory)));
+}
+
+#[test]
+fn values_within_the_limit_are_allowed() {
+    let result = run("
+        var s = \"12345678\";
+        for (var i = 0; i < 16; i = i + 1) s = s + s;
+    ");
+    assert!(result.is_ok());
+}
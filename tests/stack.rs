@@ -0,0 +1,104 @@
+//! Programs run on a stack the library makes, so deep recursion and deep
+//! nesting are errors rather than crashes whichever thread calls in. The
+//! test harness runs each test on a thread with only 2 MB of stack.
+
+use rlox::{run_captured, InterpreterBuilder, InterpreterOptions, LoxError, Value};
+
+const RECURSE: &str = "
+fun recurse(n) { return recurse(n + 1); }
+try { recurse(0); } catch (error) { print error; }
+";
+
+#[test]
+fn deep_recursion_overflows_catchably() {
+    let captured = run_captured(RECURSE, InterpreterOptions::default());
+    assert_eq!(captured.output, "Stack overflow.\n");
+    assert!(captured.diagnostics.is_empty());
+}
+
+#[test]
+fn call_depth_past_the_stack_stops_at_the_stack() {
+    let options = InterpreterOptions {
+        max_call_depth: Some(usize::MAX),
+        ..InterpreterOptions::default()
+    };
+    let captured = run_captured(RECURSE, options);
+    assert_eq!(captured.output, "Stack overflow.\n");
+}
+
+#[test]
+fn eval_recursion_overflows() {
+    let mut interpreter = InterpreterBuilder::sandboxed().build();
+    interpreter
+        .run("fun recurse(n) { return recurse(n + 1); }".to_string())
+        .unwrap();
+    assert!(matches!(
+        interpreter.eval("recurse(0)"),
+        Err(LoxError::Runtime(_))
+    ));
+}
+
+fn parens(depth: usize) -> String {
+    format!("{}1{}", "(".repeat(depth), ")".repeat(depth))
+}
+
+fn is_too_deep(result: Result<impl Sized, LoxError>) -> bool {
+    match result {
+        Err(LoxError::Compile(errors)) => errors
+            .iter()
+            .any(|error| error.to_string().contains("Code is nested too deeply.")),
+        _ => false,
+    }
+}
+
+#[test]
+fn nesting_within_the_limit_evaluates() {
+    let mut interpreter = InterpreterBuilder::sandboxed().build();
+    assert!(matches!(interpreter.eval(&parens(900)), Ok(Value::Int(1))));
+    assert!(rlox::parse(&format!("print {};", parens(900))).is_ok());
+}
+
+#[test]
+fn nesting_past_the_limit_is_a_compile_error() {
+    let source = format!("print {};", parens(1_100));
+    assert!(is_too_deep(rlox::parse(&source)));
+    assert!(is_too_deep(
+        InterpreterBuilder::sandboxed().build().eval(&parens(1_100))
+    ));
+    assert!(is_too_deep(
+        InterpreterBuilder::sandboxed().build().run(source.clone())
+    ));
+    let captured = run_captured(&source, InterpreterOptions::default());
+    assert!(captured.output.is_empty());
+    assert!(captured.diagnostics[0].contains("Code is nested too deeply."));
+}
+
+fn with_coroutine_stack(size: usize) -> InterpreterOptions {
+    InterpreterOptions {
+        coroutine_stack_size: Some(size),
+        ..InterpreterOptions::default()
+    }
+}
+
+#[test]
+fn coroutine_stack_that_cannot_be_mapped_is_a_runtime_error() {
+    let source = "
+        fun body() { yield 1; }
+        var c = coroutine(body);
+        try { c(); } catch (error) { print error; }
+    ";
+    let captured = run_captured(source, with_coroutine_stack(1 << 60));
+    assert_eq!(captured.output, "Could not allocate a coroutine's stack.\n");
+}
+
+#[test]
+fn recursion_in_a_small_coroutine_stack_overflows_catchably() {
+    let source = "
+        fun recurse(n) { return recurse(n + 1); }
+        fun body() { try { recurse(0); } catch (error) { print error; } }
+        coroutine(body)();
+    ";
+    let captured = run_captured(source, with_coroutine_stack(0));
+    assert_eq!(captured.output, "Stack overflow.\n");
+    assert!(captured.diagnostics.is_empty());
+}